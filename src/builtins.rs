@@ -3,10 +3,34 @@
 //! This module implements all the built-in commands that are handled directly
 //! by the shell rather than being executed as external programs.
 
+use crate::completion::CompletionSpec;
 use crate::exec::find_exec_in_path;
+use crate::history::History;
+use crate::jobs::{JobState, JobTable};
+use crate::keymap::EditingMode;
+use crate::parser::{quote_word, Command};
+use crate::variables::{is_valid_name, Variables};
+use std::collections::BTreeMap;
 use std::env;
 use std::io::ErrorKind;
 
+/// Names of all commands implemented directly by the shell.
+pub const BUILTINS: &[&str] = &[
+    "echo", "exit", "type", "pwd", "cd", "export", "unset", "env", "set", "history", "complete",
+    "jobs", "fg", "bg", "wait",
+];
+
+/// Returns true if `name` is a shell built-in command.
+pub fn is_builtin(name: &str) -> bool {
+    BUILTINS.contains(&name)
+}
+
+/// Returns true if the shell runs `name` with `args` itself. `env` is only
+/// built in without arguments; with a command to run, it is the external `env`.
+pub fn runs_as_builtin(name: &str, args: &[String]) -> bool {
+    is_builtin(name) && (name != "env" || args.is_empty())
+}
+
 /// Handles the `echo` command by joining all arguments with spaces.
 ///
 /// # Arguments
@@ -26,7 +50,7 @@ use std::io::ErrorKind;
 /// assert_eq!(result.unwrap().unwrap(), "hello world\n");
 /// ```
 pub fn handle_echo(args: &[String]) -> Result<Option<String>, String> {
-    Ok(Some(format!("{}\r\n", args.join(" "))))
+    Ok(Some(format!("{}\n", args.join(" "))))
 }
 
 /// Handles the `pwd` command by returning the current working directory.
@@ -41,26 +65,37 @@ pub fn handle_echo(args: &[String]) -> Result<Option<String>, String> {
 /// * `Err(message)` - Error getting current directory
 pub fn handle_pwd(_args: &[String]) -> Result<Option<String>, String> {
     match env::current_dir() {
-        Ok(dir) => Ok(Some(format!("{}\r\n", dir.display()))),
+        Ok(dir) => Ok(Some(format!("{}\n", dir.display()))),
         Err(e) => Err(format!("pwd: error getting current directory: {}", e)),
     }
 }
 
 /// Helper function that generates the string for the 'type' command.
-/// Checks if a command is a built-in or searches for it in PATH.
+/// Checks if a command is a shell function or a built-in, or searches for it in PATH.
 ///
 /// # Arguments
 ///
 /// * `name` - The command name to look up
+/// * `path_var` - The value of the PATH variable, None if it is unset
+/// * `functions` - The shell functions, which take precedence over the rest
 ///
 /// # Returns
 ///
 /// A formatted string describing where the command is found
-fn type_info_string(name: &str) -> String {
-    if ["echo", "exit", "type", "pwd", "cd"].contains(&name) {
+fn type_info_string(
+    name: &str,
+    path_var: Option<&str>,
+    functions: &BTreeMap<String, Command>,
+) -> String {
+    if let Some(body) = functions.get(name) {
+        format!("{} is a function\n{}() {}", name, name, body)
+    } else if name != "env" && is_builtin(name) {
         format!("{} is a shell builtin", name)
-    } else if let Some(full_path) = find_exec_in_path(name) {
+    } else if let Some(full_path) = find_exec_in_path(name, path_var) {
+        // `env` runs from PATH as soon as it has a command to run
         format!("{} is {}", name, full_path)
+    } else if is_builtin(name) {
+        format!("{} is a shell builtin", name)
     } else {
         format!("{}: not found", name)
     }
@@ -71,14 +106,23 @@ fn type_info_string(name: &str) -> String {
 /// # Arguments
 ///
 /// * `args` - Should contain exactly one argument (the command to look up)
+/// * `path_var` - The value of the PATH variable, None if it is unset
+/// * `functions` - The shell functions
 ///
 /// # Returns
 ///
 /// * `Ok(Some(info))` - Information about the command with trailing newline
 /// * `Err(message)` - Error for wrong number of arguments
-pub fn handle_type(args: &[String]) -> Result<Option<String>, String> {
+pub fn handle_type(
+    args: &[String],
+    path_var: Option<&str>,
+    functions: &BTreeMap<String, Command>,
+) -> Result<Option<String>, String> {
     match args {
-        [name] => Ok(Some(format!("{}\r\n", type_info_string(name)))),
+        [name] => Ok(Some(format!(
+            "{}\n",
+            type_info_string(name, path_var, functions)
+        ))),
         [] => Err("type: missing argument".to_string()),
         _ => Err("type: too many arguments".to_string()),
     }
@@ -99,9 +143,9 @@ fn change_dir(target_path_str: &str) -> Result<(), String> {
     let target_path = if target_path_str == "~" || target_path_str.starts_with("~/") {
         match env::var("HOME") {
             Ok(home_dir) => {
-                if target_path_str.starts_with("~/") {
+                if let Some(rest) = target_path_str.strip_prefix("~/") {
                     let mut path = std::path::PathBuf::from(home_dir);
-                    path.push(&target_path_str[2..]); // Append path after '~/'
+                    path.push(rest); // Append path after '~/'
                     path.to_string_lossy().into_owned()
                 } else {
                     home_dir // Just HOME
@@ -152,39 +196,441 @@ pub fn handle_cd(args: &[String]) -> Result<Option<String>, String> {
 ///
 /// # Arguments
 ///
-/// * `args` - Optional exit code (defaults to 0 if not provided)
+/// * `args` - Optional exit code (defaults to the last status if not provided)
+/// * `last_status` - Exit status of the previous command (`$?`)
 ///
 /// # Note
 ///
 /// This function does not return as it calls `std::process::exit()`.
-pub fn handle_exit(args: &[String]) -> ! {
+pub fn handle_exit(args: &[String], last_status: i32) -> ! {
     let code = args
         .first()
         .and_then(|s| s.parse::<i32>().ok())
-        .unwrap_or(0);
+        .unwrap_or(last_status);
     std::process::exit(code);
 }
 
+/// Formats variables as one `<prefix>NAME=value` line each, quoting values so
+/// the lines can be read back by the shell. Returns None if there are none.
+fn format_variables<'a>(
+    variables: impl IntoIterator<Item = (&'a str, &'a str)>,
+    prefix: &str,
+) -> Option<String> {
+    let listing: String = variables
+        .into_iter()
+        .map(|(name, value)| format!("{}{}={}\n", prefix, name, quote_word(value)))
+        .collect();
+    (!listing.is_empty()).then_some(listing)
+}
+
+/// Handles the `export` command by marking variables as exported, so that they
+/// are passed to the commands the shell runs.
+///
+/// # Arguments
+///
+/// * `vars` - The shell's variable table
+/// * `args` - `NAME=value` or `NAME` words; no arguments or `-p` lists exported variables
+///
+/// # Returns
+///
+/// * `Ok(Some(listing))` - The exported variables, when listing
+/// * `Ok(None)` - All variables were exported
+/// * `Err(message)` - An argument is not a valid variable name
+pub fn handle_export(vars: &mut Variables, args: &[String]) -> Result<Option<String>, String> {
+    if args.is_empty() || args == ["-p"] {
+        return Ok(format_variables(vars.exported(), "export "));
+    }
+
+    let mut errors = Vec::new();
+    for arg in args {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (arg.as_str(), None),
+        };
+        if is_valid_name(name) {
+            vars.export(name, value);
+        } else if arg.starts_with('-') {
+            errors.push(format!("export: {}: invalid option", arg));
+        } else {
+            errors.push(format!("export: `{}': not a valid identifier", arg));
+        }
+    }
+    if errors.is_empty() {
+        Ok(None)
+    } else {
+        Err(errors.join("\n"))
+    }
+}
+
+/// Handles the `unset` command by removing variables.
+///
+/// # Arguments
+///
+/// * `vars` - The shell's variable table
+/// * `args` - Names of the variables to remove (an optional leading `-v` is accepted)
+///
+/// # Returns
+///
+/// * `Ok(None)` - All variables were removed
+/// * `Err(message)` - An argument is not a valid variable name
+pub fn handle_unset(vars: &mut Variables, args: &[String]) -> Result<Option<String>, String> {
+    let names = match args.split_first() {
+        Some((flag, rest)) if flag == "-v" => rest,
+        _ => args,
+    };
+
+    let mut errors = Vec::new();
+    for name in names {
+        if is_valid_name(name) {
+            vars.unset(name);
+        } else {
+            errors.push(format!("unset: `{}': not a valid identifier", name));
+        }
+    }
+    if errors.is_empty() {
+        Ok(None)
+    } else {
+        Err(errors.join("\n"))
+    }
+}
+
+/// Handles the `env` command (without arguments) by listing the environment
+/// given to child processes, i.e. the exported variables.
+///
+/// # Returns
+///
+/// * `Ok(Some(listing))` - One `NAME=value` line per exported variable
+pub fn handle_env(vars: &Variables) -> Result<Option<String>, String> {
+    let listing: String = vars
+        .exported()
+        .into_iter()
+        .map(|(name, value)| format!("{}={}\n", name, value))
+        .collect();
+    Ok((!listing.is_empty()).then_some(listing))
+}
+
+/// Handles the `set` command: lists all shell variables, local and exported,
+/// or shows and changes shell options.
+///
+/// The only options are the line editor's keymaps, `emacs` and `vi`, of which
+/// exactly one is on: turning one on with `-o` turns the other off, and
+/// turning one off with `+o` turns the other on.
+///
+/// # Arguments
+///
+/// * `vars` - The shell's variable table
+/// * `editing_mode` - The line editor's keymap option
+/// * `args` - Empty to list variables; `-o`/`+o` alone to list the options
+///   (as a table or as `set` commands), or followed by an option name to set it
+///
+/// # Returns
+///
+/// * `Ok(Some(listing))` - The variables or options
+/// * `Ok(None)` - The option was set
+/// * `Err(message)` - An unsupported argument or unknown option name was given
+pub fn handle_set(
+    vars: &Variables,
+    editing_mode: &mut EditingMode,
+    args: &[String],
+) -> Result<Option<String>, String> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        [] => Ok(format_variables(vars.all(), "")),
+        ["-o"] => Ok(Some(
+            EditingMode::ALL
+                .iter()
+                .map(|&mode| {
+                    let state = if mode == *editing_mode { "on" } else { "off" };
+                    format!("{:<15}\t{}\n", mode.name(), state)
+                })
+                .collect(),
+        )),
+        ["+o"] => Ok(Some(
+            EditingMode::ALL
+                .iter()
+                .map(|&mode| {
+                    let flag = if mode == *editing_mode { '-' } else { '+' };
+                    format!("set {}o {}\n", flag, mode.name())
+                })
+                .collect(),
+        )),
+        [flag @ ("-o" | "+o"), name] => {
+            let mode = EditingMode::ALL
+                .into_iter()
+                .find(|mode| mode.name() == *name)
+                .ok_or_else(|| format!("set: {}: invalid option name", name))?;
+            *editing_mode = match (*flag, mode) {
+                ("-o", mode) => mode,
+                (_, EditingMode::Emacs) => EditingMode::Vi,
+                (_, EditingMode::Vi) => EditingMode::Emacs,
+            };
+            Ok(None)
+        }
+        [arg, ..] => Err(format!("set: {}: invalid option", arg)),
+    }
+}
+
+/// Handles the `history` command by listing the recorded command lines,
+/// numbered from 1.
+///
+/// # Arguments
+///
+/// * `history` - The shell's command history
+/// * `args` - Empty to list every entry, or a count to list only the most recent ones
+///
+/// # Returns
+///
+/// * `Ok(Some(listing))` - One numbered line per entry
+/// * `Err(message)` - The count is not a number, or too many arguments were given
+pub fn handle_history(history: &History, args: &[String]) -> Result<Option<String>, String> {
+    let count = match args {
+        [] => history.len(),
+        [count] => count
+            .parse::<usize>()
+            .map_err(|_| format!("history: {}: numeric argument required", count))?,
+        _ => return Err("history: too many arguments".to_string()),
+    };
+
+    let start = history.len().saturating_sub(count);
+    let listing: String = history.entries()[start..]
+        .iter()
+        .enumerate()
+        .map(|(index, line)| format!("{:>5}  {}\n", start + index + 1, line))
+        .collect();
+    Ok((!listing.is_empty()).then_some(listing))
+}
+
+/// Handles the `complete` command, which registers how the arguments of
+/// commands are completed by Tab.
+///
+/// `-W wordlist` offers the words of the list, `-F function` calls the shell
+/// function and `-C command` runs the command, and both offer the lines of
+/// their output. `-p` lists specs as `complete` commands, as does `complete`
+/// without arguments, and `-r` removes them.
+///
+/// # Arguments
+///
+/// * `specs` - The shell's completion specs, by command name
+/// * `args` - Options followed by the names of the commands they apply to
+///
+/// # Returns
+///
+/// * `Ok(Some(listing))` - The specs, when listing
+/// * `Ok(None)` - The specs were registered or removed
+/// * `Err(message)` - An invalid option, or a name without a spec
+pub fn handle_complete(
+    specs: &mut BTreeMap<String, CompletionSpec>,
+    args: &[String],
+) -> Result<Option<String>, String> {
+    let mut spec = CompletionSpec::default();
+    let (mut print, mut remove) = (false, false);
+    let mut args = args.iter().peekable();
+    while let Some(option) = args.next_if(|arg| arg.starts_with('-') && arg.len() > 1) {
+        match option.as_str() {
+            "--" => break,
+            "-p" => print = true,
+            "-r" => remove = true,
+            "-W" | "-C" | "-F" => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| format!("complete: {}: option requires an argument", option))?;
+                match option.as_str() {
+                    "-W" => spec.words = value.split_whitespace().map(str::to_string).collect(),
+                    "-C" => spec.command = Some(value.clone()),
+                    _ => spec.function = Some(value.clone()),
+                }
+            }
+            _ => return Err(format!("complete: {}: invalid option", option)),
+        }
+    }
+    let names: Vec<&String> = args.collect();
+
+    if remove {
+        if names.is_empty() {
+            specs.clear();
+            return Ok(None);
+        }
+        let missing: Vec<String> = names
+            .into_iter()
+            .filter(|name| specs.remove(name.as_str()).is_none())
+            .map(|name| format!("complete: {}: no completion specification", name))
+            .collect();
+        return if missing.is_empty() {
+            Ok(None)
+        } else {
+            Err(missing.join("\n"))
+        };
+    }
+
+    let is_spec_given = spec != CompletionSpec::default();
+    if print || (names.is_empty() && !is_spec_given) {
+        let mut listing = String::new();
+        let mut missing = Vec::new();
+        let listed: Vec<&str> = if names.is_empty() {
+            specs.keys().map(String::as_str).collect()
+        } else {
+            names.iter().map(|name| name.as_str()).collect()
+        };
+        for name in listed {
+            match specs.get(name) {
+                Some(spec) => listing.push_str(&format!("{}\n", spec.to_command(name))),
+                None => missing.push(format!("complete: {}: no completion specification", name)),
+            }
+        }
+        return if missing.is_empty() {
+            Ok((!listing.is_empty()).then_some(listing))
+        } else {
+            Err(missing.join("\n"))
+        };
+    }
+
+    if names.is_empty() {
+        return Err(
+            "complete: usage: complete [-pr] [-W wordlist] [-F function] [-C command] \
+             [name ...]"
+                .to_string(),
+        );
+    }
+    for name in names {
+        specs.insert(name.clone(), spec.clone());
+    }
+    Ok(None)
+}
+
+/// Handles the `jobs` command, which lists the background jobs with their
+/// states. Jobs that have finished are listed one last time, then forgotten.
+///
+/// # Arguments
+///
+/// * `jobs` - The shell's job table
+/// * `args` - Job specs of the jobs to list; all jobs if empty
+///
+/// # Returns
+///
+/// * `Ok(Some(listing))` - One line per job, e.g. `[1]+  Running    sleep 10 &`
+/// * `Ok(None)` - There are no jobs
+/// * `Err(message)` - A job spec matches no job
+pub fn handle_jobs(jobs: &mut JobTable, args: &[String]) -> Result<Option<String>, String> {
+    jobs.poll();
+    let ids = args
+        .iter()
+        .map(|spec| jobs.find(Some(spec)))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("jobs: {}", e))?;
+    let ids = (!args.is_empty()).then_some(ids.as_slice());
+    let listing: String = jobs
+        .list(ids)
+        .iter()
+        .map(|line| format!("{}\n", line))
+        .collect();
+    Ok((!listing.is_empty()).then_some(listing))
+}
+
+/// Handles the `fg` command, which brings a background or stopped job to the
+/// foreground: prints its command line, gives it the terminal, continues it if
+/// it is stopped and waits for it.
+///
+/// # Arguments
+///
+/// * `jobs` - The shell's job table
+/// * `args` - The job spec of the job; the current job if empty
+///
+/// # Returns
+///
+/// * `Ok(status)` - The exit status of the job, or 128 + the signal that stopped it again
+/// * `Err(message)` - No such job, or it has already finished
+pub fn handle_fg(jobs: &mut JobTable, args: &[String]) -> Result<i32, String> {
+    jobs.poll();
+    let id = jobs
+        .find(args.first().map(String::as_str))
+        .map_err(|e| format!("fg: {}", e))?;
+    let job = jobs.get(id);
+    if let JobState::Done(_) = job.state() {
+        return Err("fg: job has terminated".to_string());
+    }
+    println!("{}", job.command);
+    Ok(jobs.foreground(id))
+}
+
+/// Handles the `bg` command, which continues a stopped job in the background.
+///
+/// # Arguments
+///
+/// * `jobs` - The shell's job table
+/// * `args` - The job spec of the job; the current job if empty
+///
+/// # Returns
+///
+/// * `Ok(Some(line))` - The job continued, e.g. `[1]+ sleep 10 &`
+/// * `Err(message)` - No such job, or the job is already running in the background
+pub fn handle_bg(jobs: &mut JobTable, args: &[String]) -> Result<Option<String>, String> {
+    jobs.poll();
+    let id = jobs
+        .find(args.first().map(String::as_str))
+        .map_err(|e| format!("bg: {}", e))?;
+    match jobs.resume_in_background(id) {
+        Some(line) => Ok(Some(format!("{}\n", line))),
+        None => Err(format!("bg: job {} already in background", id)),
+    }
+}
+
+/// Handles the `wait` command, which waits for background jobs to finish or
+/// stop. Jobs waited for are removed without being reported as Done.
+///
+/// # Arguments
+///
+/// * `jobs` - The shell's job table
+/// * `args` - Job specs (`%1`) or process ids; all jobs if empty
+///
+/// # Returns
+///
+/// * `Ok(status)` - The exit status of the last job waited for, 0 without arguments
+/// * `Err(message)` - An argument names no job of this shell
+pub fn handle_wait(jobs: &mut JobTable, args: &[String]) -> Result<i32, String> {
+    if args.is_empty() {
+        for id in jobs.ids() {
+            jobs.wait(id);
+        }
+        return Ok(0);
+    }
+
+    let mut status = 0;
+    for arg in args {
+        let id = if arg.starts_with('%') {
+            jobs.find(Some(arg)).map_err(|e| format!("wait: {}", e))?
+        } else {
+            let pid = arg
+                .parse::<libc::pid_t>()
+                .map_err(|_| format!("wait: `{}': not a pid or valid job spec", arg))?;
+            jobs.find_pid(pid)
+                .ok_or_else(|| format!("wait: pid {} is not a child of this shell", pid))?
+        };
+        status = jobs.wait(id);
+    }
+    Ok(status)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::parser::{parse_command_line, Node};
 
     #[test]
     fn test_echo_empty() {
         let result = handle_echo(&[]);
-        assert_eq!(result.unwrap().unwrap(), "\r\n");
+        assert_eq!(result.unwrap().unwrap(), "\n");
     }
 
     #[test]
     fn test_echo_single_arg() {
         let result = handle_echo(&["hello".to_string()]);
-        assert_eq!(result.unwrap().unwrap(), "hello\r\n");
+        assert_eq!(result.unwrap().unwrap(), "hello\n");
     }
 
     #[test]
     fn test_echo_multiple_args() {
         let result = handle_echo(&["hello".to_string(), "world".to_string()]);
-        assert_eq!(result.unwrap().unwrap(), "hello world\r\n");
+        assert_eq!(result.unwrap().unwrap(), "hello world\n");
     }
 
     #[test]
@@ -196,20 +642,24 @@ mod tests {
 
     #[test]
     fn test_type_builtin() {
-        let result = handle_type(&["echo".to_string()]);
-        assert_eq!(result.unwrap().unwrap(), "echo is a shell builtin\r\n");
+        let result = handle_type(&["echo".to_string()], None, &BTreeMap::new());
+        assert_eq!(result.unwrap().unwrap(), "echo is a shell builtin\n");
     }
 
     #[test]
     fn test_type_no_args() {
-        let result = handle_type(&[]);
+        let result = handle_type(&[], None, &BTreeMap::new());
         assert!(result.is_err());
         assert_eq!(result.unwrap_err(), "type: missing argument");
     }
 
     #[test]
     fn test_type_too_many_args() {
-        let result = handle_type(&["echo".to_string(), "pwd".to_string()]);
+        let result = handle_type(
+            &["echo".to_string(), "pwd".to_string()],
+            None,
+            &BTreeMap::new(),
+        );
         assert!(result.is_err());
         assert_eq!(result.unwrap_err(), "type: too many arguments");
     }
@@ -223,10 +673,235 @@ mod tests {
 
     #[test]
     fn test_type_info_string() {
-        assert_eq!(type_info_string("echo"), "echo is a shell builtin");
+        let functions = BTreeMap::new();
+        assert_eq!(
+            type_info_string("echo", None, &functions),
+            "echo is a shell builtin"
+        );
         assert_eq!(
-            type_info_string("nonexistent_command_xyz"),
+            type_info_string("nonexistent_command_xyz", Some("/bin:/usr/bin"), &functions),
             "nonexistent_command_xyz: not found"
         );
+        assert_eq!(
+            type_info_string("env", Some("/bin:/usr/bin"), &functions),
+            format!(
+                "env is {}",
+                find_exec_in_path("env", Some("/bin:/usr/bin")).unwrap()
+            )
+        );
+        assert_eq!(
+            type_info_string("env", None, &functions),
+            "env is a shell builtin"
+        );
+    }
+
+    #[test]
+    fn test_type_function() {
+        let Ok(Some(Node::Pipeline(mut commands))) = parse_command_line("echo() { pwd; }") else {
+            unreachable!()
+        };
+        let Command::Function(name, body) = commands.remove(0) else {
+            unreachable!()
+        };
+        let functions = BTreeMap::from([(name, *body)]);
+        // A function shadows the built-in of the same name
+        assert_eq!(
+            handle_type(&["echo".to_string()], None, &functions)
+                .unwrap()
+                .unwrap(),
+            "echo is a function\necho() { pwd; }\n"
+        );
+    }
+
+    #[test]
+    fn test_export_and_env() {
+        let mut vars = Variables::default();
+        vars.set("LOCAL", "1");
+        let result = handle_export(&mut vars, &["GREETING=hello world".to_string()]);
+        assert_eq!(result, Ok(None));
+        assert_eq!(
+            handle_env(&vars).unwrap().unwrap(),
+            "GREETING=hello world\n"
+        );
+        assert_eq!(
+            handle_export(&mut vars, &[]).unwrap().unwrap(),
+            "export GREETING='hello world'\n"
+        );
+    }
+
+    #[test]
+    fn test_export_invalid_name() {
+        let mut vars = Variables::default();
+        let result = handle_export(&mut vars, &["1X=2".to_string(), "OK=3".to_string()]);
+        assert_eq!(
+            result.unwrap_err(),
+            "export: `1X=2': not a valid identifier"
+        );
+        assert_eq!(vars.get("OK"), Some("3"));
+    }
+
+    #[test]
+    fn test_unset() {
+        let mut vars = Variables::default();
+        vars.export("GONE", Some("1"));
+        assert_eq!(handle_unset(&mut vars, &["GONE".to_string()]), Ok(None));
+        assert_eq!(vars.get("GONE"), None);
+        assert!(handle_unset(&mut vars, &["a-b".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_set_lists_local_variables() {
+        let mut vars = Variables::default();
+        vars.set("LOCAL", "a b");
+        let mut mode = EditingMode::Emacs;
+        assert_eq!(
+            handle_set(&vars, &mut mode, &[]).unwrap().unwrap(),
+            "LOCAL='a b'\n"
+        );
+        assert_eq!(handle_env(&vars), Ok(None));
+    }
+
+    #[test]
+    fn test_history() {
+        let mut history = History::default();
+        assert_eq!(handle_history(&history, &[]), Ok(None));
+        history.add("echo one");
+        history.add("echo two");
+        assert_eq!(
+            handle_history(&history, &[]).unwrap().unwrap(),
+            "    1  echo one\n    2  echo two\n"
+        );
+        assert_eq!(
+            handle_history(&history, &["1".to_string()])
+                .unwrap()
+                .unwrap(),
+            "    2  echo two\n"
+        );
+        assert!(handle_history(&history, &["x".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_set_editing_mode() {
+        let vars = Variables::default();
+        let mut mode = EditingMode::Emacs;
+        let args = |args: &[&str]| args.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        assert_eq!(handle_set(&vars, &mut mode, &args(&["-o", "vi"])), Ok(None));
+        assert_eq!(mode, EditingMode::Vi);
+        assert_eq!(
+            handle_set(&vars, &mut mode, &args(&["-o"]))
+                .unwrap()
+                .unwrap(),
+            "emacs          \toff\nvi             \ton\n"
+        );
+        assert_eq!(
+            handle_set(&vars, &mut mode, &args(&["+o"]))
+                .unwrap()
+                .unwrap(),
+            "set +o emacs\nset -o vi\n"
+        );
+        assert_eq!(handle_set(&vars, &mut mode, &args(&["+o", "vi"])), Ok(None));
+        assert_eq!(mode, EditingMode::Emacs);
+        assert!(handle_set(&vars, &mut mode, &args(&["-o", "nano"])).is_err());
+        assert!(handle_set(&vars, &mut mode, &args(&["-e"])).is_err());
+    }
+
+    #[test]
+    fn test_job_builtins() {
+        let mut jobs = JobTable::default();
+        let args = |args: &[&str]| args.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        assert_eq!(handle_jobs(&mut jobs, &[]), Ok(None));
+        assert_eq!(
+            handle_fg(&mut jobs, &[]),
+            Err("fg: current: no such job".to_string())
+        );
+
+        #[allow(clippy::zombie_processes)] // Reaped by the job table
+        let child = std::process::Command::new("sh")
+            .args(["-c", "exit 4"])
+            .spawn()
+            .unwrap();
+        let pid = child.id() as libc::pid_t;
+        jobs.add(pid, &[pid], "sh -c 'exit 4'".to_string());
+        assert_eq!(
+            handle_bg(&mut jobs, &[]),
+            Err("bg: job 1 already in background".to_string())
+        );
+        assert_eq!(
+            handle_jobs(&mut jobs, &args(&["%2"])),
+            Err("jobs: %2: no such job".to_string())
+        );
+        assert_eq!(
+            handle_wait(&mut jobs, &args(&["x"])),
+            Err("wait: `x': not a pid or valid job spec".to_string())
+        );
+        assert_eq!(handle_wait(&mut jobs, &args(&[&pid.to_string()])), Ok(4));
+        assert_eq!(
+            handle_wait(&mut jobs, &args(&[&pid.to_string()])),
+            Err(format!("wait: pid {} is not a child of this shell", pid))
+        );
+        assert_eq!(handle_wait(&mut jobs, &[]), Ok(0));
+    }
+
+    #[test]
+    fn test_complete() {
+        let mut specs = BTreeMap::new();
+        let args = |args: &[&str]| args.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        assert_eq!(handle_complete(&mut specs, &[]), Ok(None));
+        assert_eq!(
+            handle_complete(&mut specs, &args(&["-W", "build test deploy", "mytool"])),
+            Ok(None)
+        );
+        assert_eq!(specs["mytool"].words, ["build", "test", "deploy"]);
+        assert_eq!(
+            handle_complete(&mut specs, &args(&["-C", "mytool --complete", "a", "b"])),
+            Ok(None)
+        );
+        assert_eq!(
+            handle_complete(&mut specs, &args(&["-F", "_cmd", "cmd"])),
+            Ok(None)
+        );
+        assert_eq!(specs["cmd"].function.as_deref(), Some("_cmd"));
+        assert_eq!(
+            handle_complete(&mut specs, &args(&["-p", "mytool", "a", "cmd"]))
+                .unwrap()
+                .unwrap(),
+            "complete -W 'build test deploy' mytool\ncomplete -C 'mytool --complete' a\n\
+             complete -F _cmd cmd\n"
+        );
+        assert_eq!(
+            handle_complete(&mut specs, &args(&["-r", "a", "cmd"])),
+            Ok(None)
+        );
+        assert_eq!(
+            handle_complete(&mut specs, &[])
+                .unwrap()
+                .unwrap()
+                .lines()
+                .count(),
+            2
+        );
+        assert_eq!(handle_complete(&mut specs, &args(&["-r"])), Ok(None));
+        assert!(specs.is_empty());
+    }
+
+    #[test]
+    fn test_complete_errors() {
+        let mut specs = BTreeMap::new();
+        let args = |args: &[&str]| args.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            handle_complete(&mut specs, &args(&["-F"])),
+            Err("complete: -F: option requires an argument".to_string())
+        );
+        assert_eq!(
+            handle_complete(&mut specs, &args(&["-W"])),
+            Err("complete: -W: option requires an argument".to_string())
+        );
+        assert!(handle_complete(&mut specs, &args(&["-x", "mytool"])).is_err());
+        assert!(handle_complete(&mut specs, &args(&["-W", "a"])).is_err());
+        assert_eq!(
+            handle_complete(&mut specs, &args(&["-p", "mytool"])),
+            Err("complete: mytool: no completion specification".to_string())
+        );
+        assert!(specs.is_empty());
     }
 }
@@ -0,0 +1,835 @@
+//! Tab completion module for the rust shell.
+//!
+//! This module finds the candidates offered by Tab in the line editor. The
+//! first word of a command completes to a command name: a builtin or an
+//! executable found in the directories of `PATH`. The executables are
+//! scanned once and cached until `PATH` changes. The arguments of commands
+//! registered with the `complete` builtin complete to the words, or the output
+//! of a shell function or a command, given by their spec, and other words to
+//! file names. Completed words are quoted so that they parse back into the
+//! same word. The module also formats the list of candidates shown when a
+//! word is ambiguous.
+
+use crate::builtins::BUILTINS;
+use crate::exec::{find_exec_in_path, list_execs_in_dir};
+use crate::expand::expand_words;
+use crate::parser::{
+    is_plain_char, parse_pipeline, quote_word, AMPERSAND, BACKSLASH, DOUBLE_QUOTE, PIPE, SEMICOLON,
+    SINGLE_QUOTE,
+};
+use crate::shell::Shell;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use unicode_width::UnicodeWidthStr;
+
+/// The command names offered for the first word of a line.
+#[derive(Debug, Default)]
+pub struct CommandCache {
+    /// The `PATH` the names were scanned from, None before the first scan
+    path: Option<String>,
+    /// Builtins and executables on `PATH`, sorted and without duplicates
+    names: Vec<String>,
+}
+
+impl CommandCache {
+    /// Returns the command names, scanning the directories of `path_var`
+    /// again if it changed since the last call.
+    ///
+    /// # Arguments
+    ///
+    /// * `path_var` - The value of the shell's PATH variable, if set
+    pub fn commands(&mut self, path_var: Option<&str>) -> &[String] {
+        let path = path_var.unwrap_or("");
+        if self.path.as_deref() != Some(path) {
+            self.names = scan_commands(path);
+            self.path = Some(path.to_string());
+        }
+        &self.names
+    }
+}
+
+/// Lists the builtins and the executables in the directories of `path`.
+fn scan_commands(path: &str) -> Vec<String> {
+    let mut names: Vec<String> = BUILTINS.iter().map(|name| name.to_string()).collect();
+    for dir_path in path.split(':').filter(|dir_path| !dir_path.is_empty()) {
+        names.extend(list_execs_in_dir(dir_path));
+    }
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// The result of completing the word before the cursor.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Completion {
+    /// Byte offset in the line where the completed word starts
+    pub start: usize,
+    /// The word typed so far, with quotes and escapes removed
+    pub prefix: String,
+    /// The values that may replace the word, sorted. Directories end with `/`.
+    pub candidates: Vec<String>,
+    /// The quote left open in the word, kept when it is replaced
+    quote: Option<char>,
+}
+
+impl Completion {
+    /// Returns the text that replaces the word in the line to give it `value`,
+    /// quoted like the word typed so far.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - The new value of the word
+    /// * `is_complete` - Whether the word is finished: an open quote is then
+    ///   closed and a space added after it
+    pub fn replacement(&self, value: &str, is_complete: bool) -> String {
+        // Keep `~/` unquoted, as typed
+        let (home, rest) = match value.strip_prefix("~/") {
+            Some(rest) if self.quote.is_none() => ("~/", rest),
+            _ => ("", value),
+        };
+        let mut text = format!("{}{}", home, quote_completion(rest, self.quote));
+        if is_complete {
+            text.extend(self.quote);
+            text.push(' ');
+        }
+        text
+    }
+
+    /// Returns the candidates as listed to the user: file names without
+    /// their directory.
+    pub fn display_names(&self) -> Vec<String> {
+        self.candidates
+            .iter()
+            .map(|value| {
+                let name = value.strip_suffix('/').unwrap_or(value);
+                let start = name.rfind('/').map_or(0, |index| index + 1);
+                value[start..].to_string()
+            })
+            .collect()
+    }
+}
+
+/// Quotes a completed value so that it parses back into the same word, inside
+/// the quote left open if any. Otherwise special characters are escaped with
+/// a backslash.
+fn quote_completion(value: &str, quote: Option<char>) -> String {
+    let mut text = String::new();
+    text.extend(quote);
+    for c in value.chars() {
+        match quote {
+            Some(SINGLE_QUOTE) if c == SINGLE_QUOTE => text.push_str(r"'\''"),
+            Some(SINGLE_QUOTE) => text.push(c),
+            Some(_) if DOUBLE_QUOTE_ESCAPABLE.contains(c) => {
+                text.push(BACKSLASH);
+                text.push(c);
+            }
+            Some(_) => text.push(c),
+            None if is_plain_char(c) => text.push(c),
+            None => {
+                text.push(BACKSLASH);
+                text.push(c);
+            }
+        }
+    }
+    text
+}
+
+/// Characters escaped by a backslash inside double quotes.
+const DOUBLE_QUOTE_ESCAPABLE: &str = "$`\"\\";
+
+/// Finds completions for the line editor, keeping what it learns between lines.
+#[derive(Debug, Default)]
+pub struct Completer {
+    /// The command names offered for the first word
+    commands: CommandCache,
+}
+
+impl Completer {
+    /// Completes the word that ends at the cursor. The first word of a
+    /// pipeline stage completes to a command name, unless it contains a `/`.
+    /// The arguments of a command registered with `complete` complete to the
+    /// candidates of its spec, and other words to file names.
+    ///
+    /// # Arguments
+    ///
+    /// * `line` - The line being edited
+    /// * `cursor` - Byte offset of the cursor in the line
+    /// * `shell` - The shell state, for the completion specs and the PATH and
+    ///   HOME variables
+    ///
+    /// # Returns
+    ///
+    /// Where the word starts and the candidates for it; no candidates if the
+    /// word can't be completed
+    pub fn complete(&mut self, line: &str, cursor: usize, shell: &Shell) -> Completion {
+        let word = current_word(&line[..cursor]);
+        let spec = word
+            .previous
+            .first()
+            .and_then(|command| find_spec(command, &shell.completions));
+        let candidates = match (word.previous.first(), spec) {
+            (None, _) if !word.value.contains('/') => self
+                .commands
+                .commands(shell.vars.get("PATH"))
+                .iter()
+                .filter(|name| name.starts_with(&word.value))
+                .cloned()
+                .collect(),
+            (Some(command), Some(spec)) => {
+                let context = SpecContext {
+                    command,
+                    word: &word,
+                    line,
+                    cursor,
+                };
+                spec_candidates(spec, &context, shell)
+            }
+            _ => file_candidates(&word.value, shell.vars.get("HOME")),
+        };
+        Completion {
+            start: word.start,
+            prefix: word.value,
+            candidates,
+            quote: word.quote,
+        }
+    }
+}
+
+/// A completion spec registered with the `complete` builtin, giving the
+/// candidates for the arguments of a command.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompletionSpec {
+    /// Words offered if they start with the word being completed (`-W`)
+    pub words: Vec<String>,
+    /// A shell function listing the candidates on its output, one per line (`-F`)
+    pub function: Option<String>,
+    /// A command listing the candidates on its output, one per line (`-C`)
+    pub command: Option<String>,
+}
+
+impl CompletionSpec {
+    /// Returns the `complete` command that registers this spec for `name`,
+    /// as listed by `complete -p`.
+    pub fn to_command(&self, name: &str) -> String {
+        let mut text = String::from("complete");
+        if !self.words.is_empty() {
+            text.push_str(" -W ");
+            text.push_str(&quote_word(&self.words.join(" ")));
+        }
+        if let Some(function) = &self.function {
+            text.push_str(" -F ");
+            text.push_str(&quote_word(function));
+        }
+        if let Some(command) = &self.command {
+            text.push_str(" -C ");
+            text.push_str(&quote_word(command));
+        }
+        format!("{} {}", text, quote_word(name))
+    }
+}
+
+/// Returns the spec registered for `command`, either by the name as typed
+/// or, for a path, by its file name.
+fn find_spec<'a>(
+    command: &str,
+    specs: &'a BTreeMap<String, CompletionSpec>,
+) -> Option<&'a CompletionSpec> {
+    specs.get(command).or_else(|| {
+        let name = command.rsplit('/').next()?;
+        specs.get(name)
+    })
+}
+
+/// What a completion spec is applied to.
+struct SpecContext<'a> {
+    /// The command name, as typed
+    command: &'a str,
+    /// The word being completed
+    word: &'a Word,
+    /// The whole line being edited
+    line: &'a str,
+    /// Byte offset of the cursor in the line
+    cursor: usize,
+}
+
+/// Lists the candidates of a completion spec, sorted.
+fn spec_candidates(spec: &CompletionSpec, context: &SpecContext, shell: &Shell) -> Vec<String> {
+    let mut candidates: Vec<String> = spec
+        .words
+        .iter()
+        .filter(|candidate| candidate.starts_with(&context.word.value))
+        .cloned()
+        .collect();
+    if let Some(function) = &spec.function {
+        candidates.extend(call_spec_function(function, context, shell));
+    }
+    if let Some(command) = &spec.command {
+        candidates.extend(run_spec_command(command, context, shell));
+    }
+    candidates.sort();
+    candidates.dedup();
+    candidates
+}
+
+/// Returns a copy of the shell for running the function or command of a spec,
+/// with the line and cursor position exported in `COMP_LINE` and `COMP_POINT`.
+fn spec_shell(context: &SpecContext, shell: &Shell) -> Shell {
+    let mut subshell = Shell {
+        last_status: shell.last_status,
+        pid: shell.pid,
+        vars: shell.vars.clone(),
+        functions: shell.functions.clone(),
+        run_substitution: shell.run_substitution,
+        ..Shell::default()
+    };
+    let point = context.line[..context.cursor].chars().count();
+    subshell.vars.export("COMP_LINE", Some(context.line));
+    subshell.vars.export("COMP_POINT", Some(&point.to_string()));
+    subshell
+}
+
+/// Calls the function of a `-F` spec and returns the lines of its output.
+///
+/// The function gets the same arguments and variables as the command of a
+/// `-C` spec (see `run_spec_command`), and runs in a subshell, so that it
+/// can't change the shell's state. As the shell has no arrays, it writes its
+/// candidates to stdout instead of setting `COMPREPLY`. A function that can't
+/// be called gives no candidates.
+fn call_spec_function(function: &str, context: &SpecContext, shell: &Shell) -> Vec<String> {
+    let mut subshell = spec_shell(context, shell);
+    let Some(run) = subshell.run_substitution else {
+        return Vec::new();
+    };
+    if !subshell.functions.contains_key(function) {
+        return Vec::new();
+    }
+    let previous = context.word.previous.last().map_or("", String::as_str);
+    let call: Vec<String> = [function, context.command, &context.word.value, previous]
+        .into_iter()
+        .map(quote_word)
+        .collect();
+    match run(
+        &mut subshell,
+        &format!("{} </dev/null 2>/dev/null", call.join(" ")),
+    ) {
+        Ok(output) => output
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Runs the command of a `-C` spec and returns the lines of its output.
+///
+/// Like bash, the command gets the command name, the word being completed
+/// and the word before it as arguments, and the line and cursor position in
+/// `COMP_LINE` and `COMP_POINT`. It runs with a copy of the shell variables,
+/// so expanding its words doesn't change the shell's. A command that can't be
+/// run gives no candidates.
+fn run_spec_command(command: &str, context: &SpecContext, shell: &Shell) -> Vec<String> {
+    let mut subshell = spec_shell(context, shell);
+
+    let words = match parse_pipeline(command).as_deref() {
+        Ok([words]) => expand_words(words, &mut subshell).unwrap_or_default(),
+        _ => return Vec::new(), // A single command is expected
+    };
+    let Some((name, args)) = words.split_first() else {
+        return Vec::new();
+    };
+    let Some(path) = find_exec_in_path(name, subshell.vars.get("PATH")) else {
+        return Vec::new();
+    };
+    let previous = context.word.previous.last().map_or("", String::as_str);
+    let output = Command::new(path)
+        .args(args)
+        .args([context.command, &context.word.value, previous])
+        .env_clear()
+        .envs(subshell.vars.exported())
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output();
+    match output {
+        Ok(output) => String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// The word that ends at the cursor.
+#[derive(Debug, PartialEq, Eq)]
+struct Word {
+    /// Byte offset where the word starts
+    start: usize,
+    /// The word with quotes and escapes removed
+    value: String,
+    /// The quote left open at the end of the word, if any
+    quote: Option<char>,
+    /// The words of the command before this one (since the last `|`, `;`, `&`,
+    /// `&&` or `||`), with quotes removed. If there are none, the word is the
+    /// command name.
+    previous: Vec<String>,
+}
+
+/// Finds the word ending at the end of `text`, following the quoting rules
+/// of the parser.
+fn current_word(text: &str) -> Word {
+    let mut word = Word {
+        start: 0,
+        value: String::new(),
+        quote: None,
+        previous: Vec::new(),
+    };
+    let mut chars = text.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match (c, word.quote) {
+            (SINGLE_QUOTE, Some(SINGLE_QUOTE)) | (DOUBLE_QUOTE, Some(DOUBLE_QUOTE)) => {
+                word.quote = None;
+            }
+            (SINGLE_QUOTE | DOUBLE_QUOTE, None) => word.quote = Some(c),
+            (BACKSLASH, quote) if quote != Some(SINGLE_QUOTE) => match chars.next() {
+                Some((_, next_char)) => {
+                    // Inside double quotes, only a few characters can be escaped
+                    if quote.is_some() && !DOUBLE_QUOTE_ESCAPABLE.contains(next_char) {
+                        word.value.push(c);
+                    }
+                    word.value.push(next_char);
+                }
+                None => word.value.push(c),
+            },
+            // `&>`, `>&` and `<&` are redirection operators, part of the word
+            (AMPERSAND, None)
+                if !text[index + 1..].starts_with(AMPERSAND)
+                    && (text[index + 1..].starts_with('>') || word.value.ends_with(['>', '<'])) =>
+            {
+                word.value.push(c);
+            }
+            (' ' | '\t' | PIPE | SEMICOLON | AMPERSAND, None) => {
+                // The word ends here, and the next one starts after the separator;
+                // a new command starts after an operator
+                let value = std::mem::take(&mut word.value);
+                if c != ' ' && c != '\t' {
+                    word.previous.clear();
+                } else if word.start < index {
+                    word.previous.push(value);
+                }
+                word.start = index + c.len_utf8();
+            }
+            _ => word.value.push(c),
+        }
+    }
+    word
+}
+
+/// Lists the files whose path starts with `prefix`, relative to the current
+/// directory or, for a prefix starting with `~/`, to `home`. Hidden files
+/// are only listed if the file name being completed starts with a dot.
+///
+/// # Returns
+///
+/// The matching paths, sorted, spelled like `prefix` and with a `/` after
+/// directories
+fn file_candidates(prefix: &str, home: Option<&str>) -> Vec<String> {
+    if prefix == "~" {
+        return vec!["~/".to_string()];
+    }
+    let (dir_part, name_part) = match prefix.rfind('/') {
+        Some(index) => prefix.split_at(index + 1),
+        None => ("", prefix),
+    };
+    let dir_path = match (dir_part.strip_prefix("~/"), home) {
+        (Some(rest), Some(home)) => Path::new(home).join(rest),
+        _ if dir_part.is_empty() => PathBuf::from("."),
+        _ => PathBuf::from(dir_part),
+    };
+    let Ok(entries) = fs::read_dir(dir_path) else {
+        return Vec::new();
+    };
+
+    let is_hidden = |name: &str| name.starts_with('.') && !name_part.starts_with('.');
+    let mut candidates: Vec<String> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            if !name.starts_with(name_part) || is_hidden(&name) {
+                return None;
+            }
+            // Follow symlinks to directories
+            let is_dir = fs::metadata(entry.path()).is_ok_and(|metadata| metadata.is_dir());
+            let slash = if is_dir { "/" } else { "" };
+            Some(format!("{}{}{}", dir_part, name, slash))
+        })
+        .collect();
+    candidates.sort();
+    candidates
+}
+
+/// Returns the longest prefix shared by all `candidates`.
+pub fn longest_common_prefix(candidates: &[String]) -> &str {
+    let Some((first, rest)) = candidates.split_first() else {
+        return "";
+    };
+    let mut prefix: &str = first;
+    for candidate in rest {
+        let common = prefix
+            .char_indices()
+            .zip(candidate.chars())
+            .find(|((_, a), b)| a != b)
+            .map_or(prefix.len().min(candidate.len()), |((index, _), _)| index);
+        prefix = &prefix[..common];
+    }
+    prefix
+}
+
+/// Lays out `candidates` in columns, filled top to bottom, that fit a terminal
+/// `columns` wide.
+///
+/// # Returns
+///
+/// The rows of the listing, without line endings
+pub fn format_columns(candidates: &[String], columns: usize) -> Vec<String> {
+    let width = candidates
+        .iter()
+        .map(|name| name.width())
+        .max()
+        .unwrap_or(0)
+        + 2;
+    let per_row = (columns / width).max(1);
+    let rows = candidates.len().div_ceil(per_row);
+
+    (0..rows)
+        .map(|row| {
+            let mut line = String::new();
+            let mut names = candidates.iter().skip(row).step_by(rows).peekable();
+            while let Some(name) = names.next() {
+                line.push_str(name);
+                if names.peek().is_some() {
+                    line.push_str(&" ".repeat(width - name.width()));
+                }
+            }
+            line
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expand::expand_word;
+    use crate::parser::{parse_command_line, parse_pipeline, Node};
+    use std::fs::File;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::PathBuf;
+
+    /// Creates a fresh directory in the temporary directory holding `execs`
+    /// as executable files and `others` as plain files.
+    fn bin_dir(name: &str, execs: &[&str], others: &[&str]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rush_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for file_name in execs.iter().chain(others) {
+            File::create(dir.join(file_name)).unwrap();
+        }
+        for file_name in execs {
+            fs::set_permissions(dir.join(file_name), fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        dir
+    }
+
+    /// Completes the word at the end of `line`.
+    fn complete(completer: &mut Completer, line: &str, shell: &Shell) -> Completion {
+        completer.complete(line, line.len(), shell)
+    }
+
+    fn shell_with_path(path: &str) -> Shell {
+        let mut shell = Shell::default();
+        shell.vars.set("PATH", path);
+        shell
+    }
+
+    fn word(start: usize, value: &str, quote: Option<char>, previous: &[&str]) -> Word {
+        Word {
+            start,
+            value: value.to_string(),
+            quote,
+            previous: previous.iter().map(|word| word.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_current_word() {
+        assert_eq!(current_word("ech"), word(0, "ech", None, &[]));
+        assert_eq!(current_word("  ec"), word(2, "ec", None, &[]));
+        assert_eq!(current_word("echo he"), word(5, "he", None, &["echo"]));
+        assert_eq!(current_word("echo  "), word(6, "", None, &["echo"]));
+        assert_eq!(current_word("ls -l | gr"), word(8, "gr", None, &[]));
+        assert_eq!(
+            current_word(r"cat -n my\ fi"),
+            word(7, "my fi", None, &["cat", "-n"])
+        );
+        assert_eq!(
+            current_word("cat 'my fi"),
+            word(4, "my fi", Some('\''), &["cat"])
+        );
+        assert_eq!(
+            current_word(r#"cat "a\$b\c"#),
+            word(4, r"a$b\c", Some('"'), &["cat"])
+        );
+        assert_eq!(current_word("make && ./r"), word(8, "./r", None, &[]));
+        assert_eq!(current_word("cd x; l"), word(6, "l", None, &[]));
+        assert_eq!(
+            current_word("cmd 2>&1 fi"),
+            word(9, "fi", None, &["cmd", "2>&1"])
+        );
+        assert_eq!(
+            current_word("cmd &>log fi"),
+            word(10, "fi", None, &["cmd", "&>log"])
+        );
+        assert_eq!(current_word("sleep 1 & ec"), word(10, "ec", None, &[]));
+        assert_eq!(
+            current_word("cat 'a|b' c"),
+            word(10, "c", None, &["cat", "a|b"])
+        );
+    }
+
+    #[test]
+    fn test_command_cache() {
+        let first = bin_dir("complete_first", &["mytool", "echo"], &["notes.txt"]);
+        let second = bin_dir("complete_second", &["othertool"], &[]);
+        let mut cache = CommandCache::default();
+
+        let commands = cache.commands(first.to_str());
+        assert!(commands.iter().any(|name| name == "mytool"));
+        assert!(commands.iter().any(|name| name == "history"));
+        assert!(!commands.iter().any(|name| name == "notes.txt"));
+        assert_eq!(commands.iter().filter(|name| *name == "echo").count(), 1);
+
+        // A new PATH is scanned again
+        let commands = cache.commands(second.to_str());
+        assert!(commands.iter().any(|name| name == "othertool"));
+        assert!(!commands.iter().any(|name| name == "mytool"));
+
+        let commands = cache.commands(None);
+        assert_eq!(commands.len(), BUILTINS.len());
+        fs::remove_dir_all(first).unwrap();
+        fs::remove_dir_all(second).unwrap();
+    }
+
+    #[test]
+    fn test_complete_command() {
+        let dir = bin_dir("complete_command", &["mytool"], &[]);
+        let shell = shell_with_path(dir.to_str().unwrap());
+        let mut completer = Completer::default();
+
+        let completion = complete(&mut completer, "myt", &shell);
+        assert_eq!(completion.start, 0);
+        assert_eq!(completion.candidates, ["mytool"]);
+        assert_eq!(
+            complete(&mut completer, "  ty", &shell).candidates,
+            ["type"]
+        );
+        assert_eq!(
+            complete(&mut completer, "e", &shell).candidates,
+            ["echo", "env", "exit", "export"]
+        );
+        assert!(complete(&mut completer, "nothing", &shell)
+            .candidates
+            .is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn test_longest_common_prefix() {
+        assert_eq!(longest_common_prefix(&names(&["echo", "env", "exit"])), "e");
+        assert_eq!(
+            longest_common_prefix(&names(&["export", "exports"])),
+            "export"
+        );
+        assert_eq!(longest_common_prefix(&names(&["naïve", "naïf"])), "naï");
+        assert_eq!(longest_common_prefix(&names(&["cd"])), "cd");
+        assert_eq!(longest_common_prefix(&[]), "");
+    }
+
+    #[test]
+    fn test_format_columns() {
+        let candidates = names(&["cd", "echo", "env", "exit", "export"]);
+        assert_eq!(
+            format_columns(&candidates, 80),
+            ["cd      echo    env     exit    export"]
+        );
+        assert_eq!(
+            format_columns(&candidates, 24),
+            ["cd      env     export", "echo    exit"]
+        );
+        assert_eq!(format_columns(&candidates[..2], 3), ["cd", "echo"]);
+    }
+
+    #[test]
+    fn test_complete_files() {
+        let dir = bin_dir(
+            "complete_files",
+            &["run.sh"],
+            &["my file", "notes.txt", ".hidden"],
+        );
+        fs::create_dir(dir.join("src")).unwrap();
+        let mut shell = shell_with_path("");
+        shell.vars.set("HOME", dir.to_str().unwrap());
+        let mut completer = Completer::default();
+
+        let prefix = format!("{}/", dir.display());
+        let completion = complete(&mut completer, &format!("cat {}", prefix), &shell);
+        assert_eq!(completion.start, 4);
+        let names = completion.display_names();
+        assert_eq!(names, ["my file", "notes.txt", "run.sh", "src/"]);
+
+        let completion = complete(&mut completer, "ls ~/s", &shell);
+        assert_eq!(completion.candidates, ["~/src/"]);
+        assert_eq!(
+            complete(&mut completer, "ls ~/.h", &shell).candidates,
+            ["~/.hidden"]
+        );
+        assert_eq!(complete(&mut completer, "cd ~", &shell).candidates, ["~/"]);
+        assert_eq!(
+            complete(&mut completer, "~/r", &shell).candidates,
+            ["~/run.sh"]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_replacement() {
+        let shell = shell_with_path("");
+        let mut completer = Completer::default();
+        let completion = complete(&mut completer, "cat my\\ f", &shell);
+        assert_eq!(completion.replacement("my file", true), r"my\ file ");
+        assert_eq!(completion.replacement("it's/", false), r"it\'s/");
+        assert_eq!(completion.replacement("~/a b", true), r"~/a\ b ");
+
+        let completion = complete(&mut completer, "cat 'my", &shell);
+        assert_eq!(completion.replacement("my file", true), "'my file' ");
+        assert_eq!(completion.replacement("it's/", false), r"'it'\''s/");
+
+        let completion = complete(&mut completer, "cat \"my", &shell);
+        assert_eq!(completion.replacement("a $b", true), r#""a \$b" "#);
+    }
+
+    #[test]
+    fn test_replacement_round_trip() {
+        let shell = shell_with_path("");
+        let mut completer = Completer::default();
+        let mut expanded = Shell::default();
+        for line in ["cat x", "cat 'x", "cat \"x"] {
+            let completion = complete(&mut completer, line, &shell);
+            for value in [
+                "my file",
+                "it's",
+                r#"a "quoted" $HOME \ `b`"#,
+                "tab\there|x",
+            ] {
+                let text = format!("cat {}", completion.replacement(value, true));
+                let words = parse_pipeline(&text).unwrap().remove(0);
+                assert_eq!(words.len(), 2, "{}", text);
+                assert_eq!(expand_word(&words[1], &mut expanded).unwrap(), [value]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_complete_with_spec() {
+        let dir = bin_dir("complete_spec", &["helper"], &[]);
+        let script = "#!/bin/sh\necho \"$1:$2:$3:$4:$COMP_POINT:$COMP_LINE:$MODE\"\necho second\n";
+        fs::write(dir.join("helper"), script).unwrap();
+        let mut shell = shell_with_path(&format!("{}:/bin:/usr/bin", dir.display()));
+        shell.vars.set("MODE", "quick");
+        let mut completer = Completer::default();
+
+        shell.completions.insert(
+            "mytool".to_string(),
+            CompletionSpec {
+                words: names(&["build", "test", "bench"]),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            complete(&mut completer, "mytool b", &shell).candidates,
+            ["bench", "build"]
+        );
+        assert_eq!(
+            complete(&mut completer, "./bin/mytool -v t", &shell).candidates,
+            ["test"]
+        );
+        assert!(complete(&mut completer, "other b", &shell)
+            .candidates
+            .is_empty());
+
+        shell.completions.insert(
+            "deploy".to_string(),
+            CompletionSpec {
+                command: Some("helper $MODE".to_string()),
+                ..Default::default()
+            },
+        );
+        let completion = completer.complete("deploy -n st rest", 12, &shell);
+        assert_eq!(
+            completion.candidates,
+            ["quick:deploy:st:-n:12:deploy -n st rest:", "second"]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Stands in for running the call of a completion function: outputs the
+    /// command line and `COMP_LINE`, one per line.
+    fn echo_call(shell: &mut Shell, command: &str) -> Result<String, String> {
+        Ok(format!(
+            "{}\n{}\n",
+            command,
+            shell.vars.get("COMP_LINE").unwrap()
+        ))
+    }
+
+    #[test]
+    fn test_complete_with_function() {
+        let mut shell = shell_with_path("/bin:/usr/bin");
+        let mut completer = Completer::default();
+        shell.completions.insert(
+            "mytool".to_string(),
+            CompletionSpec {
+                function: Some("_mytool".to_string()),
+                ..Default::default()
+            },
+        );
+
+        // Not defined, or commands can't be run
+        assert!(complete(&mut completer, "mytool b", &shell)
+            .candidates
+            .is_empty());
+        let Ok(Some(Node::Pipeline(mut body))) = parse_command_line("{ :; }") else {
+            unreachable!()
+        };
+        shell
+            .functions
+            .insert("_mytool".to_string(), body.remove(0));
+        assert!(complete(&mut completer, "mytool b", &shell)
+            .candidates
+            .is_empty());
+
+        shell.run_substitution = Some(echo_call);
+        assert_eq!(
+            complete(&mut completer, "mytool -v 'a b", &shell).candidates,
+            [
+                "_mytool mytool 'a b' -v </dev/null 2>/dev/null",
+                "mytool -v 'a b"
+            ]
+        );
+    }
+}
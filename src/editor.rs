@@ -0,0 +1,825 @@
+//! Line editor module for the rust shell.
+//!
+//! This module reads command lines from an interactive terminal in raw mode.
+//! The line is kept in a [`LineBuffer`] with a cursor that can be moved with
+//! Left/Right and Home/End, text is removed with Backspace and Delete, and
+//! Up/Down recall lines from the history. Ctrl-C abandons the line and Ctrl-D
+//! on an empty line ends the input. Keys are translated into editing
+//! commands by the keymap, which provides readline's emacs bindings including
+//! a kill ring shared by all lines, or by the vi keymap in vi mode, which shows
+//! the current vi mode before the prompt. Ctrl-R searches the history
+//! incrementally, showing the search in place of the prompt, and Tab completes
+//! the word before the cursor, listing the candidates when pressed twice.
+//! After each change the line is redrawn, taking the display width of wide
+//! characters and lines wrapping past the terminal width into account.
+
+use crate::completion::{format_columns, longest_common_prefix, Completer};
+use crate::history::HistoryNavigator;
+use crate::keymap::{emacs_command, EditCommand, EditingMode};
+use crate::shell::Shell;
+use crate::vi::ViState;
+use std::collections::VecDeque;
+use std::io::{stdin, stdout, Stdout, Write};
+use std::ops::Range;
+use termion::event::Key;
+use termion::input::TermRead;
+use termion::raw::{IntoRawMode, RawTerminal};
+use unicode_width::UnicodeWidthChar;
+
+/// Terminal width assumed when it cannot be queried.
+const DEFAULT_COLUMNS: usize = 80;
+/// Maximum number of entries kept in the kill ring.
+const KILL_RING_SIZE: usize = 10;
+
+/// The text of the line being edited and the position of the cursor in it.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct LineBuffer {
+    /// The line's text
+    text: String,
+    /// Byte offset of the cursor in `text`, always on a character boundary
+    cursor: usize,
+}
+
+impl LineBuffer {
+    /// Returns the line's text.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Returns the byte offset of the cursor.
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// Moves the cursor to a byte offset, which must be on a character boundary.
+    pub fn set_cursor(&mut self, cursor: usize) {
+        debug_assert!(self.text.is_char_boundary(cursor));
+        self.cursor = cursor;
+    }
+
+    /// Returns the text before the cursor.
+    pub fn before_cursor(&self) -> &str {
+        &self.text[..self.cursor]
+    }
+
+    /// Replaces the line's text, placing the cursor at its end.
+    pub fn set_text(&mut self, text: &str) {
+        self.text = text.to_string();
+        self.cursor = self.text.len();
+    }
+
+    /// Inserts text at the cursor and moves the cursor after it.
+    pub fn insert(&mut self, text: &str) {
+        self.text.insert_str(self.cursor, text);
+        self.cursor += text.len();
+    }
+
+    /// Moves the cursor one character to the left. Returns false at the start of the line.
+    pub fn move_left(&mut self) -> bool {
+        match self.before_cursor().chars().next_back() {
+            Some(c) => {
+                self.cursor -= c.len_utf8();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Moves the cursor one character to the right. Returns false at the end of the line.
+    pub fn move_right(&mut self) -> bool {
+        match self.text[self.cursor..].chars().next() {
+            Some(c) => {
+                self.cursor += c.len_utf8();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Moves the cursor to the start of the line.
+    pub fn move_home(&mut self) {
+        self.cursor = 0;
+    }
+
+    /// Moves the cursor to the end of the line.
+    pub fn move_end(&mut self) {
+        self.cursor = self.text.len();
+    }
+
+    /// Deletes the character before the cursor (Backspace).
+    /// Returns false if there is none.
+    pub fn delete_before(&mut self) -> bool {
+        if !self.move_left() {
+            return false;
+        }
+        self.text.remove(self.cursor);
+        true
+    }
+
+    /// Deletes the character under the cursor (Delete). Returns false if there is none.
+    pub fn delete_at(&mut self) -> bool {
+        if self.cursor == self.text.len() {
+            return false;
+        }
+        self.text.remove(self.cursor);
+        true
+    }
+
+    /// Removes and returns a range of the text, keeping the cursor on the same
+    /// character (or at the start of the range if it was inside it).
+    pub fn remove(&mut self, range: Range<usize>) -> String {
+        if self.cursor >= range.end {
+            self.cursor -= range.len();
+        } else if self.cursor > range.start {
+            self.cursor = range.start;
+        }
+        self.text.drain(range).collect()
+    }
+
+    /// Returns the offset of the start of the word before the cursor, where
+    /// words are runs of characters matching `is_word`.
+    pub fn previous_word_start(&self, is_word: impl Fn(char) -> bool) -> usize {
+        let before = self.before_cursor();
+        let trimmed = before.trim_end_matches(|c| !is_word(c));
+        trimmed.trim_end_matches(&is_word).len()
+    }
+
+    /// Returns the offset of the end of the word after the cursor, where words
+    /// are runs of characters matching `is_word`.
+    pub fn next_word_end(&self, is_word: impl Fn(char) -> bool) -> usize {
+        let after = &self.text[self.cursor..];
+        let trimmed = after.trim_start_matches(|c| !is_word(c));
+        self.text.len() - trimmed.trim_start_matches(&is_word).len()
+    }
+
+    /// Swaps the character before the cursor with the one under it and moves
+    /// the cursor forward; at the end of the line swaps the last two characters.
+    /// Returns false if there are not two characters to swap.
+    pub fn transpose(&mut self) -> bool {
+        if self.cursor == self.text.len() && !self.move_left() {
+            return false;
+        }
+        let Some(current) = self.text[self.cursor..].chars().next() else {
+            return false;
+        };
+        let Some(previous) = self.before_cursor().chars().next_back() else {
+            self.move_right(); // Undo the move at the end of a one-character line
+            return false;
+        };
+        let start = self.cursor - previous.len_utf8();
+        let end = self.cursor + current.len_utf8();
+        self.text
+            .replace_range(start..end, &format!("{}{}", current, previous));
+        self.cursor = end;
+        true
+    }
+}
+
+/// Returns true for characters that make up words for Alt-B/F/D: letters and digits.
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric()
+}
+
+/// Returns true for characters that make up words for Ctrl-W: anything but whitespace.
+fn is_unix_word_char(c: char) -> bool {
+    !c.is_whitespace()
+}
+
+/// Recently killed text, most recent first, for yanking back into the line.
+#[derive(Debug, Default)]
+pub struct KillRing {
+    /// The killed texts
+    entries: VecDeque<String>,
+    /// Index of the entry last yanked, advanced by yank-pop
+    yank_index: usize,
+}
+
+impl KillRing {
+    /// Records killed text. When `extend` is true (the previous command was also
+    /// a kill) the text joins the most recent entry instead, before it if
+    /// `prepend` is true.
+    pub fn kill(&mut self, text: &str, extend: bool, prepend: bool) {
+        if text.is_empty() {
+            return;
+        }
+        match self.entries.front_mut() {
+            Some(last) if extend && prepend => last.insert_str(0, text),
+            Some(last) if extend => last.push_str(text),
+            _ => {
+                self.entries.push_front(text.to_string());
+                self.entries.truncate(KILL_RING_SIZE);
+            }
+        }
+    }
+
+    /// Returns the most recent entry to yank, if any.
+    pub fn yank(&mut self) -> Option<&str> {
+        self.yank_index = 0;
+        self.entries.front().map(String::as_str)
+    }
+
+    /// Returns the entry before the one last yanked, cycling through the ring.
+    pub fn yank_pop(&mut self) -> Option<&str> {
+        if self.entries.is_empty() {
+            return None;
+        }
+        self.yank_index = (self.yank_index + 1) % self.entries.len();
+        self.entries.get(self.yank_index).map(String::as_str)
+    }
+}
+
+/// What the previous editing command did, for commands that depend on it.
+#[derive(Debug, Clone, PartialEq)]
+enum LastCommand {
+    /// Anything else
+    Other,
+    /// Killed text, which a following kill extends
+    Kill,
+    /// Yanked text, occupying this range of the line, which yank-pop replaces
+    Yank(Range<usize>),
+    /// Completed the word before the cursor, which a second Tab lists candidates for
+    Complete,
+}
+
+/// Returns the screen position (row, column), relative to the start of the
+/// prompt, of the cursor after writing `text` on a terminal `columns` wide.
+///
+/// A wide character that doesn't fit at the end of a row is moved to the next
+/// one, and text exactly filling a row leaves the cursor at the start of the
+/// next row.
+fn position_after(text: &str, columns: usize) -> (usize, usize) {
+    let (mut row, mut column) = (0, 0);
+    for c in text.chars() {
+        let width = c.width().unwrap_or(0);
+        if column + width > columns {
+            row += 1;
+            column = 0;
+        }
+        column += width;
+    }
+    if column >= columns {
+        (row + 1, 0)
+    } else {
+        (row, column)
+    }
+}
+
+/// Returns the terminal width in columns.
+fn terminal_columns() -> usize {
+    match termion::terminal_size() {
+        Ok((columns, _)) if columns > 0 => usize::from(columns),
+        _ => DEFAULT_COLUMNS,
+    }
+}
+
+/// How reading a line ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReadResult {
+    /// A line was entered
+    Line(String),
+    /// The line was abandoned with Ctrl-C
+    Interrupted,
+    /// Ctrl-D was pressed on an empty line, or stdin was closed
+    EndOfFile,
+}
+
+/// The state of an incremental history search (Ctrl-R).
+#[derive(Debug)]
+struct HistorySearch {
+    /// The text searched for
+    query: String,
+    /// Index of the matching history entry shown, None before the first match
+    index: Option<usize>,
+    /// Whether the last search found nothing
+    failed: bool,
+    /// The line as it was before the search, restored if it is cancelled
+    original: LineBuffer,
+}
+
+/// The state of reading one line: the terminal, the line and how it is displayed.
+struct EditSession<'a> {
+    /// The terminal, in raw mode until the session is dropped
+    stdout: RawTerminal<Stdout>,
+    /// The prompt displayed before the line
+    prompt: &'a str,
+    /// The line being edited
+    buffer: LineBuffer,
+    /// Screen row of the terminal cursor, relative to the row the prompt starts on
+    cursor_row: usize,
+    /// The shell state, for the history and the variables used by completion
+    shell: &'a Shell,
+    /// Position in the history, for Up/Down
+    navigator: HistoryNavigator<'a>,
+    /// Finds the candidates for Tab completion
+    completer: &'a mut Completer,
+    /// Killed text shared by all lines
+    kill_ring: &'a mut KillRing,
+    /// What the previous command did
+    last_command: LastCommand,
+    /// The vi keymap state, None in emacs mode
+    vi: Option<&'a mut ViState>,
+    /// The history search in progress, if any
+    search: Option<HistorySearch>,
+}
+
+impl EditSession<'_> {
+    /// Returns the prompt as displayed, including the vi mode indicator.
+    fn full_prompt(&self) -> String {
+        if let Some(search) = &self.search {
+            let failed = if search.failed { "failed " } else { "" };
+            return format!("({}reverse-i-search)`{}': ", failed, search.query);
+        }
+        match &self.vi {
+            Some(vi) => format!("{}{}", vi.indicator(), self.prompt),
+            None => self.prompt.to_string(),
+        }
+    }
+
+    /// Redraws the prompt and line, and places the terminal cursor at the
+    /// buffer's cursor.
+    fn refresh(&mut self) {
+        let columns = terminal_columns();
+        let mut output = String::new();
+
+        // Go back to the start of the prompt and clear everything after it
+        if self.cursor_row > 0 {
+            output.push_str(&termion::cursor::Up(self.cursor_row as u16).to_string());
+        }
+        output.push('\r');
+        output.push_str(termion::clear::AfterCursor.as_ref());
+
+        let prompt = self.full_prompt();
+        let line = format!("{}{}", prompt, self.buffer.text());
+        output.push_str(&line);
+        let end = position_after(&line, columns);
+        if end.1 == 0 && end.0 > 0 {
+            // The terminal holds the cursor on the last column of a full row;
+            // move it to the next row to match `end`
+            output.push_str("\r\n");
+        }
+
+        // Move from the end of the line to the cursor
+        let before_cursor = format!("{}{}", prompt, self.buffer.before_cursor());
+        let (row, column) = position_after(&before_cursor, columns);
+        if end.0 > row {
+            output.push_str(&termion::cursor::Up((end.0 - row) as u16).to_string());
+        }
+        output.push('\r');
+        if column > 0 {
+            output.push_str(&termion::cursor::Right(column as u16).to_string());
+        }
+        self.cursor_row = row;
+
+        write!(self.stdout, "{}", output).unwrap();
+        self.stdout.flush().unwrap();
+    }
+
+    /// Returns true if the line exactly fills its last row, which leaves the
+    /// cursor at the start of the following row once drawn.
+    fn ends_at_row_start(&self) -> bool {
+        let line = format!("{}{}", self.full_prompt(), self.buffer.text());
+        position_after(&line, terminal_columns()) == (self.cursor_row, 0)
+    }
+
+    /// Completes the word before the cursor. A single candidate is inserted
+    /// followed by a space (or a `/` for a directory), several extend the word
+    /// to their longest common prefix. If the word can't be extended the bell
+    /// rings, and a second Tab lists the candidates below the line.
+    fn complete(&mut self) -> LastCommand {
+        let completion =
+            self.completer
+                .complete(self.buffer.text(), self.buffer.cursor(), self.shell);
+        let word = completion.start..self.buffer.cursor();
+        match completion.candidates.as_slice() {
+            [] => self.ring_bell(),
+            [candidate] => {
+                let is_complete = !candidate.ends_with('/');
+                self.replace_word(word, &completion.replacement(candidate, is_complete));
+            }
+            candidates => {
+                let prefix = longest_common_prefix(candidates);
+                if prefix.len() > completion.prefix.len() {
+                    self.replace_word(word, &completion.replacement(prefix, false));
+                } else if self.last_command == LastCommand::Complete {
+                    self.list_candidates(&completion.display_names());
+                } else {
+                    self.ring_bell();
+                }
+            }
+        }
+        LastCommand::Complete
+    }
+
+    /// Replaces the word in `range`, which ends at the cursor, with `text`.
+    fn replace_word(&mut self, range: Range<usize>, text: &str) {
+        self.buffer.remove(range);
+        self.buffer.insert(text);
+    }
+
+    /// Rings the terminal bell.
+    fn ring_bell(&mut self) {
+        write!(self.stdout, "\x07").unwrap();
+        self.stdout.flush().unwrap();
+    }
+
+    /// Prints completion candidates in columns below the line. The prompt and
+    /// line are drawn again after them.
+    fn list_candidates(&mut self, candidates: &[String]) {
+        let cursor = self.buffer.cursor();
+        self.finish("");
+        self.buffer.set_cursor(cursor);
+        for row in format_columns(candidates, terminal_columns()) {
+            write!(self.stdout, "{}\r\n", row).unwrap();
+        }
+        self.cursor_row = 0;
+    }
+
+    /// Shows a line recalled from the history, if any.
+    fn recall(&mut self, line: Option<String>) {
+        if let Some(line) = line {
+            self.buffer.set_text(&line);
+        }
+    }
+
+    /// Kills the text in `range` into the kill ring.
+    fn kill(&mut self, range: Range<usize>) -> LastCommand {
+        let prepend = range.end <= self.buffer.cursor();
+        let killed = self.buffer.remove(range);
+        let extend = self.last_command == LastCommand::Kill;
+        self.kill_ring.kill(&killed, extend, prepend);
+        LastCommand::Kill
+    }
+
+    /// Inserts yanked text at the cursor, remembering where it went.
+    fn insert_yanked(&mut self, text: Option<String>) -> LastCommand {
+        match text {
+            Some(text) => {
+                let start = self.buffer.cursor();
+                self.buffer.insert(&text);
+                LastCommand::Yank(start..self.buffer.cursor())
+            }
+            None => LastCommand::Other,
+        }
+    }
+
+    /// Clears the screen and draws the line at the top.
+    fn clear_screen(&mut self) {
+        write!(
+            self.stdout,
+            "{}{}",
+            termion::clear::All,
+            termion::cursor::Goto(1, 1)
+        )
+        .unwrap();
+        self.cursor_row = 0;
+    }
+
+    /// Finishes reading: moves the terminal cursor below the line, after
+    /// writing `marker` at its end.
+    fn finish(&mut self, marker: &str) {
+        self.buffer.move_end();
+        self.refresh();
+        write!(self.stdout, "{}", marker).unwrap();
+        if !marker.is_empty() || self.buffer.text().is_empty() || !self.ends_at_row_start() {
+            write!(self.stdout, "\r\n").unwrap();
+        }
+        self.stdout.flush().unwrap();
+    }
+
+    /// Runs one editing command. Returns the result once reading the line is over.
+    fn execute(&mut self, command: EditCommand) -> Option<ReadResult> {
+        use EditCommand::*;
+
+        let cursor = self.buffer.cursor();
+        let end = self.buffer.text().len();
+        let mut last_command = LastCommand::Other;
+        match command {
+            AcceptLine => {
+                self.finish("");
+                return Some(ReadResult::Line(self.buffer.text().to_string()));
+            }
+            Interrupt => {
+                self.finish("^C");
+                return Some(ReadResult::Interrupted);
+            }
+            DeleteCharOrEof if self.buffer.text().is_empty() => {
+                self.finish("");
+                return Some(ReadResult::EndOfFile);
+            }
+            Complete => last_command = self.complete(),
+            Insert(c) => self.buffer.insert(c.encode_utf8(&mut [0; 4])),
+            BackwardChar => {
+                self.buffer.move_left();
+            }
+            ForwardChar => {
+                self.buffer.move_right();
+            }
+            BeginningOfLine => self.buffer.move_home(),
+            EndOfLine => self.buffer.move_end(),
+            BackwardWord => {
+                let start = self.buffer.previous_word_start(is_word_char);
+                self.buffer.set_cursor(start);
+            }
+            ForwardWord => {
+                let word_end = self.buffer.next_word_end(is_word_char);
+                self.buffer.set_cursor(word_end);
+            }
+            BackwardDeleteChar => {
+                self.buffer.delete_before();
+            }
+            DeleteChar | DeleteCharOrEof => {
+                self.buffer.delete_at();
+            }
+            KillLine => last_command = self.kill(cursor..end),
+            UnixLineDiscard => last_command = self.kill(0..cursor),
+            UnixWordRubout => {
+                let start = self.buffer.previous_word_start(is_unix_word_char);
+                last_command = self.kill(start..cursor);
+            }
+            KillWord => {
+                let word_end = self.buffer.next_word_end(is_word_char);
+                last_command = self.kill(cursor..word_end);
+            }
+            BackwardKillWord => {
+                let start = self.buffer.previous_word_start(is_word_char);
+                last_command = self.kill(start..cursor);
+            }
+            Yank => {
+                let text = self.kill_ring.yank().map(str::to_string);
+                last_command = self.insert_yanked(text);
+            }
+            YankPop => {
+                // Only valid right after a yank: replace the yanked text
+                if let LastCommand::Yank(range) = self.last_command.clone() {
+                    let text = self.kill_ring.yank_pop().map(str::to_string);
+                    self.buffer.remove(range.clone());
+                    self.buffer.set_cursor(range.start);
+                    last_command = self.insert_yanked(text);
+                }
+            }
+            TransposeChars => {
+                self.buffer.transpose();
+            }
+            ClearScreen => self.clear_screen(),
+            ReverseSearchHistory => {
+                self.search = Some(HistorySearch {
+                    query: String::new(),
+                    index: None,
+                    failed: false,
+                    original: self.buffer.clone(),
+                });
+            }
+            PreviousHistory => {
+                let line = self
+                    .navigator
+                    .previous(self.buffer.text())
+                    .map(str::to_string);
+                self.recall(line);
+            }
+            NextHistory => {
+                let line = self.navigator.next().map(str::to_string);
+                self.recall(line);
+            }
+        }
+        self.last_command = last_command;
+        if let Some(vi) = &self.vi {
+            vi.clamp_cursor(&mut self.buffer);
+        }
+        self.refresh();
+        None
+    }
+
+    /// Searches the history for the query of the search in progress, among the
+    /// entries before index `before`, and shows the match.
+    fn search_history(&mut self, before: usize) {
+        let Some(search) = &mut self.search else {
+            return;
+        };
+        if search.query.is_empty() {
+            search.index = None;
+            search.failed = false;
+            self.buffer = search.original.clone();
+            return;
+        }
+        match self.shell.history.search_backward(&search.query, before) {
+            Some((index, offset)) => {
+                search.index = Some(index);
+                search.failed = false;
+                self.buffer.set_text(&self.shell.history.entries()[index]);
+                self.buffer.set_cursor(offset);
+            }
+            None => search.failed = true,
+        }
+    }
+
+    /// Handles a key during a history search. Returns the key if it ends the
+    /// search and must still be handled as usual (e.g. Enter accepts the line).
+    fn handle_search_key(&mut self, key: Key) -> Option<Key> {
+        let search = self.search.as_mut()?;
+        let current = search.index.unwrap_or(self.shell.history.len());
+        match key {
+            Key::Char(c) if c != '\n' && c != '\t' => {
+                search.query.push(c);
+                self.search_history(current + 1); // The current match may still match
+            }
+            Key::Backspace => {
+                search.query.pop();
+                self.search_history(self.shell.history.len());
+            }
+            Key::Ctrl('r') => self.search_history(current),
+            Key::Ctrl('g') => {
+                // Cancel: back to the line as it was
+                self.buffer = search.original.clone();
+                self.search = None;
+            }
+            Key::Esc => self.search = None,
+            key => {
+                self.search = None;
+                return Some(key);
+            }
+        }
+        self.refresh();
+        None
+    }
+
+    /// Handles one key press: translates it with the keymap of the editing
+    /// mode and runs the resulting command. Returns the result once reading
+    /// the line is over.
+    fn handle_key(&mut self, key: Key) -> Option<ReadResult> {
+        let key = if self.search.is_some() {
+            self.handle_search_key(key)?
+        } else {
+            key
+        };
+        let command = match &mut self.vi {
+            Some(vi) => {
+                let command = vi.handle_key(key, &mut self.buffer, self.kill_ring);
+                if command.is_none() {
+                    self.refresh(); // Normal mode commands edit the line directly
+                }
+                command
+            }
+            None => emacs_command(key),
+        };
+        command.and_then(|command| self.execute(command))
+    }
+}
+
+/// The line editor: reads lines from the terminal, keeping state such as the
+/// kill ring from one line to the next.
+#[derive(Debug, Default)]
+pub struct LineEditor {
+    /// Killed text, yanked back with Ctrl-Y
+    kill_ring: KillRing,
+    /// State of the vi keymap, such as the change repeated by `.`
+    vi: ViState,
+    /// Tab completion, with the command names found on PATH
+    completer: Completer,
+}
+
+impl LineEditor {
+    /// Reads one line from an interactive terminal, key by key, in raw mode.
+    /// The terminal is switched back to cooked mode before returning, so that
+    /// commands run with a normally configured terminal.
+    ///
+    /// # Arguments
+    ///
+    /// * `prompt` - The prompt displayed before the line
+    /// * `shell` - The shell state: the history recalled with the Up and Down
+    ///   arrow keys, the editing mode and the variables used by completion
+    ///
+    /// # Returns
+    ///
+    /// The line entered, `Interrupted` for Ctrl-C, or `EndOfFile` for Ctrl-D on
+    /// an empty line or if stdin was closed
+    pub fn read_line(&mut self, prompt: &str, shell: &Shell) -> ReadResult {
+        self.vi.start_line();
+        let mut session = EditSession {
+            stdout: stdout().into_raw_mode().unwrap(),
+            prompt,
+            buffer: LineBuffer::default(),
+            cursor_row: 0,
+            shell,
+            navigator: HistoryNavigator::new(&shell.history),
+            completer: &mut self.completer,
+            kill_ring: &mut self.kill_ring,
+            last_command: LastCommand::Other,
+            vi: (shell.editing_mode == EditingMode::Vi).then_some(&mut self.vi),
+            search: None,
+        };
+        session.refresh();
+
+        for key in stdin().keys().flatten() {
+            if let Some(result) = session.handle_key(key) {
+                return result;
+            }
+        }
+        ReadResult::EndOfFile // Raw mode is left when the session's terminal is dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer(text: &str) -> LineBuffer {
+        let mut buffer = LineBuffer::default();
+        buffer.set_text(text);
+        buffer
+    }
+
+    #[test]
+    fn test_insert_and_move() {
+        let mut line = buffer("ech");
+        line.insert("o");
+        assert_eq!(line.text(), "echo");
+        line.move_home();
+        assert!(!line.move_left());
+        line.insert("> ");
+        assert_eq!(line.text(), "> echo");
+        assert_eq!(line.before_cursor(), "> ");
+        line.move_end();
+        assert!(!line.move_right());
+    }
+
+    #[test]
+    fn test_multibyte_characters() {
+        let mut line = buffer("naïve 日本");
+        assert!(line.move_left());
+        assert_eq!(line.before_cursor(), "naïve 日");
+        assert!(line.delete_before());
+        assert_eq!(line.text(), "naïve 本");
+        line.move_home();
+        line.move_right();
+        line.move_right();
+        assert!(line.delete_at());
+        assert_eq!(line.text(), "nave 本");
+    }
+
+    #[test]
+    fn test_delete_at_edges() {
+        let mut line = buffer("ab");
+        assert!(!line.delete_at());
+        line.move_home();
+        assert!(!line.delete_before());
+        assert!(line.delete_at());
+        assert_eq!(line.text(), "b");
+    }
+
+    #[test]
+    fn test_position_after() {
+        assert_eq!(position_after("$ echo", 80), (0, 6));
+        assert_eq!(position_after("0123456789", 10), (1, 0));
+        assert_eq!(position_after("0123456789ab", 10), (1, 2));
+        // Wide characters take two columns and never straddle rows
+        assert_eq!(position_after("日本", 80), (0, 4));
+        assert_eq!(position_after("012345678日", 10), (1, 2));
+    }
+
+    #[test]
+    fn test_words() {
+        let mut line = buffer("cd ~/src/rust-shell  ");
+        assert_eq!(line.previous_word_start(is_word_char), 14);
+        assert_eq!(line.previous_word_start(is_unix_word_char), 3);
+        line.move_home();
+        assert_eq!(line.next_word_end(is_word_char), 2);
+        line.set_cursor(2);
+        assert_eq!(line.next_word_end(is_word_char), 8);
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut line = buffer("hello brave world");
+        assert_eq!(line.remove(6..12), "brave ");
+        assert_eq!((line.text(), line.cursor()), ("hello world", 11));
+        line.set_cursor(2);
+        assert_eq!(line.remove(0..5), "hello");
+        assert_eq!(line.cursor(), 0);
+    }
+
+    #[test]
+    fn test_transpose() {
+        let mut line = buffer("ab");
+        assert!(line.transpose());
+        assert_eq!(line.text(), "ba");
+        line.set_cursor(1);
+        assert!(line.transpose());
+        assert_eq!((line.text(), line.cursor()), ("ab", 2));
+        let mut line = buffer("a");
+        assert!(!line.transpose());
+        assert_eq!(line.cursor(), 1);
+    }
+
+    #[test]
+    fn test_kill_ring() {
+        let mut ring = KillRing::default();
+        assert_eq!(ring.yank(), None);
+        ring.kill("one", false, false);
+        ring.kill(" more", true, false);
+        ring.kill("two", false, false);
+        ring.kill("really ", true, true);
+        assert_eq!(ring.yank(), Some("really two"));
+        assert_eq!(ring.yank_pop(), Some("one more"));
+        assert_eq!(ring.yank_pop(), Some("really two"));
+    }
+}
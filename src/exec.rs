@@ -1,17 +1,327 @@
 //! External command execution module for the rust shell.
 //!
 //! This module handles finding executables in the PATH and executing
-//! external commands with proper I/O redirection and error handling.
+//! external commands with proper I/O redirection and error handling, as well
+//! as forking the shell into subshells and redirecting the shell's own
+//! descriptors for brace groups.
 
-use crate::redirect::{RedirectionMode, Redirections};
-use std::env;
-use std::fs::{self, File, OpenOptions};
-use std::io::{self, ErrorKind, Read};
+use crate::redirect::{FdSource, OpenRedirection};
+use crate::terminal;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
 #[cfg(unix)]
 use std::os::unix::fs::PermissionsExt; // For execute bits
 #[cfg(unix)]
-use std::os::unix::process::CommandExt; // For arg0
-use std::process::{Command, Stdio};
+use std::os::unix::process::{CommandExt, ExitStatusExt}; // For arg0 and signals
+use std::path::Path;
+use std::process::{Child, ChildStdout, Command, ExitStatus, Stdio};
+use std::thread;
+
+/// Where a spawned command reads its standard input from.
+pub enum PipeInput {
+    /// Inherit the shell's own stdin
+    Inherit,
+    /// Read from the stdout of the previous pipeline stage
+    Child(ChildStdout),
+    /// Read nothing, from `/dev/null`
+    Null,
+}
+
+/// How a spawned command is connected to the other stages of its pipeline.
+pub struct StageIo {
+    /// Where the command reads its standard input from
+    pub stdin: PipeInput,
+    /// Whether stdout feeds the next stage of a pipeline
+    pub pipe_stdout: bool,
+    /// Process group to join when interactive (0 starts a new group)
+    pub pgid: libc::pid_t,
+    /// Whether the command gets the terminal, rather than running in the background
+    pub foreground: bool,
+}
+
+impl StageIo {
+    /// A command running on its own, reading the shell's stdin in a new process group.
+    pub fn standalone() -> Self {
+        Self {
+            stdin: PipeInput::Inherit,
+            pipe_stdout: false,
+            pgid: 0,
+            foreground: true,
+        }
+    }
+}
+
+/// Descriptors at or above this one are used by the shell to hold redirection
+/// targets while a child applies its redirections, out of the way of the ones
+/// being redirected.
+const FIRST_PRIVATE_FD: RawFd = 10;
+
+/// The redirections of an external command, ready to be applied in the child.
+struct ChildRedirections {
+    /// `dup2(source, target)` operations, in order; a None source closes the target
+    operations: Vec<(Option<RawFd>, RawFd)>,
+    /// The descriptors used as sources, closed on exec and by the shell once
+    /// the child is spawned
+    files: Vec<File>,
+}
+
+impl ChildRedirections {
+    /// Gives the opened targets of the redirections descriptors of their own,
+    /// and turns each redirection into an operation for the child. Text is fed
+    /// through a pipe by a thread, so a large document can't block the shell.
+    fn prepare(redirections: &[OpenRedirection]) -> io::Result<Self> {
+        let mut prepared = Self {
+            operations: Vec::new(),
+            files: Vec::new(),
+        };
+        for redirection in redirections {
+            let source = match &redirection.source {
+                FdSource::File(file) => Some(prepared.keep(duplicate_private(file)?)),
+                FdSource::Text(text) => {
+                    let (reader, writer) = cloexec_pipe()?;
+                    let text = text.clone();
+                    thread::spawn(move || {
+                        // The reader may not read it all; a broken pipe is not an error
+                        let _ = (&writer).write_all(text.as_bytes());
+                    });
+                    Some(prepared.keep(duplicate_private(&reader)?))
+                }
+                FdSource::Fd(fd) => Some(*fd),
+                FdSource::Closed => None,
+            };
+            prepared.operations.push((source, redirection.fd));
+        }
+        Ok(prepared)
+    }
+
+    /// Keeps `file` open until the child is spawned, returning its descriptor.
+    fn keep(&mut self, file: File) -> RawFd {
+        let fd = file.as_raw_fd();
+        self.files.push(file);
+        fd
+    }
+
+    /// Applies the redirections in the child, after its stdio is set up, or
+    /// in the shell itself for a brace group.
+    ///
+    /// Only async-signal-safe functions are called, as required after `fork`.
+    fn apply(operations: &[(Option<RawFd>, RawFd)]) -> io::Result<()> {
+        for &(source, target) in operations {
+            match source {
+                Some(source) if source == target => {} // Already in place
+                Some(source) => {
+                    // SAFETY: dup2 only affects the descriptor table of the process.
+                    if unsafe { libc::dup2(source, target) } == -1 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                None => {
+                    // SAFETY: as above; closing a descriptor that isn't open is harmless.
+                    unsafe { libc::close(target) };
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Duplicates `file` to a descriptor of at least `FIRST_PRIVATE_FD`, closed on exec.
+fn duplicate_private(file: &File) -> io::Result<File> {
+    // SAFETY: F_DUPFD_CLOEXEC returns a new descriptor, owned by the returned file.
+    let fd = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_DUPFD_CLOEXEC, FIRST_PRIVATE_FD) };
+    if fd == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: `fd` is a new descriptor that nothing else owns.
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+/// The shell's own descriptors replaced by the redirections of a brace group,
+/// to be put back once the group has run.
+pub struct SavedFds {
+    /// Each redirected descriptor with a private copy of what it was, None if
+    /// it wasn't open
+    saved: Vec<(RawFd, Option<File>)>,
+}
+
+impl SavedFds {
+    /// Puts back the descriptors replaced by `redirect_shell`.
+    pub fn restore(self) {
+        let _ = io::stdout().flush(); // Output of the group goes to its redirections
+        for (fd, copy) in self.saved {
+            // SAFETY: dup2 and close only change the shell's own descriptor table.
+            unsafe {
+                match copy {
+                    Some(copy) => libc::dup2(copy.as_raw_fd(), fd),
+                    None => libc::close(fd),
+                };
+            }
+        }
+    }
+}
+
+/// Applies redirections to the shell's own descriptors, so that they apply to
+/// all the commands of a brace group, built-in or external.
+///
+/// # Arguments
+///
+/// * `redirections` - The redirections, with their targets opened
+///
+/// # Returns
+///
+/// * `Ok(saved)` - The replaced descriptors, to restore once the group has run
+/// * `Err(message)` - A descriptor couldn't be redirected
+pub fn redirect_shell(redirections: &[OpenRedirection]) -> Result<SavedFds, String> {
+    let prepared = ChildRedirections::prepare(redirections).map_err(|e| e.to_string())?;
+    let _ = io::stdout().flush(); // Output written so far goes to the old stdout
+    let mut saved = SavedFds { saved: Vec::new() };
+    for &(_, target) in &prepared.operations {
+        if saved.saved.iter().any(|(fd, _)| *fd == target) {
+            continue;
+        }
+        // SAFETY: F_DUPFD_CLOEXEC returns a new descriptor, owned by the returned
+        // file, or fails if `target` isn't open.
+        let copy = unsafe { libc::fcntl(target, libc::F_DUPFD_CLOEXEC, FIRST_PRIVATE_FD) };
+        let copy = (copy != -1).then(|| unsafe { File::from_raw_fd(copy) });
+        saved.saved.push((target, copy));
+    }
+    if let Err(e) = ChildRedirections::apply(&prepared.operations) {
+        saved.restore();
+        return Err(e.to_string());
+    }
+    Ok(saved)
+}
+
+/// The result of `fork_shell`, in each of the two processes.
+pub enum Fork {
+    /// In the subshell, with its stdio connected
+    Child,
+    /// In the shell: the process id of the subshell, and the read end of its
+    /// stdout when it feeds the next stage of a pipeline
+    Parent(libc::pid_t, Option<ChildStdout>),
+}
+
+/// Forks the shell into a subshell, connected to the rest of its pipeline as
+/// given by `io`, like an external command (see `spawn_external_command`).
+/// The subshell runs its commands without job control, and closes the
+/// descriptors the shell keeps for itself, as a program would on exec.
+///
+/// # Returns
+///
+/// * `Ok(fork)` - The process this returns in, the subshell or the shell
+/// * `Err(message)` - Error setting up stdin or stdout, or forking
+pub fn fork_shell(io: StageIo) -> Result<Fork, String> {
+    let StageIo {
+        stdin,
+        pipe_stdout,
+        pgid,
+        foreground,
+    } = io;
+    let fork_error = |e: io::Error| format!("fork: {}", e);
+
+    let stdin_fd: Option<OwnedFd> = match stdin {
+        PipeInput::Inherit => None,
+        PipeInput::Child(child_stdout) => Some(child_stdout.into()),
+        PipeInput::Null => Some(File::open("/dev/null").map_err(fork_error)?.into()),
+    };
+    let stdout_pipe = if pipe_stdout {
+        Some(cloexec_pipe().map_err(fork_error)?)
+    } else {
+        None
+    };
+
+    // Buffered output would be written by both processes
+    let _ = io::stdout().flush();
+    // SAFETY: the child goes on running the shell with its own copy of the state;
+    // the shell's other threads only write text into pipes, holding no lock it needs.
+    match unsafe { libc::fork() } {
+        -1 => Err(fork_error(io::Error::last_os_error())),
+        0 => {
+            if let Err(e) = terminal::setup_child_process(pgid, foreground) {
+                eprintln!("shell: {}", e);
+            }
+            terminal::disable_job_control();
+            // SAFETY: dup2 only changes the subshell's own descriptor table.
+            unsafe {
+                if let Some(fd) = &stdin_fd {
+                    libc::dup2(fd.as_raw_fd(), libc::STDIN_FILENO);
+                }
+                if let Some((_, writer)) = &stdout_pipe {
+                    libc::dup2(writer.as_raw_fd(), libc::STDOUT_FILENO);
+                }
+            }
+            drop((stdin_fd, stdout_pipe));
+            close_private_fds();
+            Ok(Fork::Child)
+        }
+        pid => {
+            let stdout = stdout_pipe.map(|(reader, _)| ChildStdout::from(OwnedFd::from(reader)));
+            Ok(Fork::Parent(pid, stdout))
+        }
+    }
+}
+
+/// Closes the descriptors the shell keeps for itself (those closed on exec) in
+/// a subshell: held open, they would keep the pipes of other commands from
+/// reaching their end.
+fn close_private_fds() {
+    let Ok(entries) = fs::read_dir("/dev/fd") else {
+        return;
+    };
+    let fds: Vec<RawFd> = entries
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+        .collect();
+    for fd in fds.into_iter().filter(|&fd| fd > libc::STDERR_FILENO) {
+        // SAFETY: fcntl and close only change the subshell's own descriptor table.
+        unsafe {
+            let flags = libc::fcntl(fd, libc::F_GETFD);
+            if flags != -1 && flags & libc::FD_CLOEXEC != 0 {
+                libc::close(fd);
+            }
+        }
+    }
+}
+
+/// Creates a pipe whose ends are closed on exec, returning (reader, writer).
+fn cloexec_pipe() -> io::Result<(File, File)> {
+    let mut fds: [RawFd; 2] = [0; 2];
+    // SAFETY: `fds` has room for the two descriptors written by pipe.
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: pipe returned two new descriptors that nothing else owns.
+    let (reader, writer) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+    for file in [&reader, &writer] {
+        // SAFETY: F_SETFD only changes the flags of a descriptor we own.
+        if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok((reader, writer))
+}
+
+/// Returns true if `path` is a file the user may execute. Follows symlinks
+/// (e.g. /bin/sh) and checks execute bits on Unix.
+fn is_executable_file(path: &Path) -> bool {
+    // Ignore errors getting metadata
+    let Ok(metadata) = fs::metadata(path) else {
+        return false;
+    };
+    if !metadata.is_file() {
+        return false;
+    }
+    #[cfg(unix)]
+    {
+        // Check execute permission (user, group, or other)
+        (metadata.permissions().mode() & 0o111) != 0
+    }
+    #[cfg(not(unix))]
+    {
+        true // Assume file is executable on non-Unix
+    }
+}
 
 /// Searches a single directory for an executable file name. Checks execute bits on Unix.
 /// Skips directories that are NotFound or inaccessible, returns other IO errors.
@@ -33,38 +343,35 @@ pub fn find_exec_in_dir(dir_path: &str, name: &str) -> io::Result<Option<String>
         Err(e) => return Err(e),                                      // Propagate other errors
     };
 
-    for entry_result in entries {
-        if let Ok(entry) = entry_result {
-            // Ignore errors reading specific entries
-            if entry.file_name().to_string_lossy() == name {
-                if let Ok(metadata) = entry.metadata() {
-                    // Ignore errors getting metadata
-                    if metadata.is_file() {
-                        #[cfg(unix)]
-                        {
-                            // Check execute permission (user, group, or other)
-                            if (metadata.permissions().mode() & 0o111) != 0 {
-                                return Ok(Some(entry.path().to_string_lossy().into_owned()));
-                            }
-                        }
-                        #[cfg(not(unix))]
-                        {
-                            // Assume file is executable on non-Unix
-                            return Ok(Some(entry.path().to_string_lossy().into_owned()));
-                        }
-                    }
-                }
-            }
+    // Ignore errors reading specific entries
+    for entry in entries.flatten() {
+        if entry.file_name().to_string_lossy() == name && is_executable_file(&entry.path()) {
+            return Ok(Some(entry.path().to_string_lossy().into_owned()));
         }
     }
     Ok(None) // Not found in this directory
 }
 
-/// Finds an executable: checks direct path if `name` contains '/', otherwise searches PATH env var.
+/// Lists the names of the executable files in a directory, for completion.
+/// A directory that can't be read has none.
+pub fn list_execs_in_dir(dir_path: &str) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(dir_path) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter(|entry| is_executable_file(&entry.path()))
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect()
+}
+
+/// Finds an executable: checks direct path if `name` contains '/', otherwise searches
+/// the directories of the shell's PATH variable.
 ///
 /// # Arguments
 ///
 /// * `name` - The command name or path to search for
+/// * `path_var` - The value of the PATH variable, None if it is unset
 ///
 /// # Returns
 ///
@@ -77,36 +384,23 @@ pub fn find_exec_in_dir(dir_path: &str, name: &str) -> io::Result<Option<String>
 /// use codecrafters_shell::exec::find_exec_in_path;
 ///
 /// // Direct path
-/// let result = find_exec_in_path("/bin/ls");
+/// let result = find_exec_in_path("/bin/ls", None);
 ///
 /// // Search in PATH
-/// let result = find_exec_in_path("ls");
+/// let result = find_exec_in_path("ls", Some("/usr/bin:/bin"));
 /// ```
-pub fn find_exec_in_path(name: &str) -> Option<String> {
+pub fn find_exec_in_path(name: &str, path_var: Option<&str>) -> Option<String> {
     if name.contains('/') {
         // Direct path check
-        if let Ok(metadata) = fs::metadata(name) {
-            if metadata.is_file() {
-                #[cfg(unix)]
-                {
-                    // Check execute permission
-                    if (metadata.permissions().mode() & 0o111) != 0 {
-                        return Some(name.to_string());
-                    }
-                }
-                #[cfg(not(unix))]
-                {
-                    // Assume executable on non-Unix
-                    return Some(name.to_string());
-                }
-            }
+        if is_executable_file(Path::new(name)) {
+            return Some(name.to_string());
         }
         return None; // Direct path not found or not executable
     }
 
-    // Search PATH environment variable
-    if let Ok(path_env) = env::var("PATH") {
-        for dir_path in path_env.split(':') {
+    // Search PATH variable
+    if let Some(path_var) = path_var {
+        for dir_path in path_var.split(':') {
             if let Ok(Some(full_path)) = find_exec_in_dir(dir_path, name) {
                 return Some(full_path);
             }
@@ -116,152 +410,78 @@ pub fn find_exec_in_path(name: &str) -> Option<String> {
     None // Not found in PATH or PATH not set
 }
 
-/// Executes an external command, handling args, stdio redirection, and waiting.
-/// Returns Ok(None) on success (exit 0), Err("") on failure (non-zero exit),
-/// or Err(message) on spawn/wait errors.
+/// Spawns an external command with its args, environment and redirections,
+/// without waiting. Stdin is read from `io.stdin`; stdout is piped to the next
+/// pipeline stage when `io.pipe_stdout` is set, otherwise inherited like stderr
+/// so the child writes straight to the terminal. The redirections are then
+/// applied on top, in order, so that `2>&1` copies the pipe into stderr.
 ///
 /// # Arguments
 ///
 /// * `command_name` - The command name for error messages and arg0
 /// * `command_path` - The full path to the executable
 /// * `args` - The command arguments
-/// * `redirections` - The I/O redirection configuration
+/// * `redirections` - The redirections, with their targets opened
+/// * `env` - The complete environment of the command
+/// * `io` - How the command is connected to the rest of its pipeline
 ///
 /// # Returns
 ///
-/// * `Ok(None)` - Command succeeded (exit code 0)
-/// * `Err("")` - Command failed with non-zero exit code
-/// * `Err(message)` - Error spawning or waiting for command
-pub fn execute_external_command(
+/// * `Ok(child)` - The spawned command, to be waited for as a job (see `JobTable`)
+/// * `Err(message)` - Error setting up the redirections or spawning the command
+pub fn spawn_external_command(
     command_name: &str, // For arg0 and errors
     command_path: &str, // Full path to exec
     args: &[String],
-    redirections: &Redirections,
-) -> Result<Option<String>, String> {
+    redirections: &[OpenRedirection],
+    env: &BTreeMap<&str, &str>,
+    io: StageIo,
+) -> Result<Child, String> {
+    let StageIo {
+        stdin,
+        pipe_stdout,
+        pgid,
+        foreground,
+    } = io;
+    let prepared =
+        ChildRedirections::prepare(redirections).map_err(|e| format!("{}: {}", command_name, e))?;
+    let operations = prepared.operations.clone();
+
     let mut command = Command::new(command_path);
+    command.env_clear().envs(env); // Exactly the given environment
     #[cfg(unix)]
     {
         command.arg0(command_name);
-    } // Set argv[0] on Unix
+        // Run in the pipeline's process group, owning the terminal while in the foreground,
+        // then redirect
+        // SAFETY: `setup_child_process` and `apply` only make async-signal-safe calls.
+        unsafe {
+            command.pre_exec(move || {
+                terminal::setup_child_process(pgid, foreground)?;
+                ChildRedirections::apply(&operations)
+            });
+        }
+    } // Set argv[0], process group and redirections on Unix
     command.args(args);
 
     // --- Configure Stdio ---
-    let mut stdout_handle: Option<File> = None; // Keep handles alive until wait()
-    let mut stderr_handle: Option<File> = None;
-
-    // Stdout: Redirect to file or pipe
-    let stdout_stdio = match &redirections.stdout_redirect {
-        Some(stdout) => {
-            // Check if the target is an existing directory
-            if let Ok(metadata) = fs::metadata(&stdout.filename) {
-                if metadata.is_dir() {
-                    return Err(format!(
-                        "{cmd}: {}: Is a directory",
-                        stdout.filename,
-                        cmd = command_name
-                    ));
-                }
-            }
-
-            match OpenOptions::new()
-                .read(false)
-                .write(true)
-                .create(true)
-                .truncate(stdout.mode == RedirectionMode::Overwrite)
-                .append(stdout.mode == RedirectionMode::Append)
-                .open(&stdout.filename)
-            {
-                Ok(file) => match file.try_clone() {
-                    Ok(cloned) => {
-                        stdout_handle = Some(file);
-                        Stdio::from(cloned)
-                    }
-                    Err(e) => {
-                        return Err(format!(
-                            "{cmd}: {}: {}",
-                            stdout.filename,
-                            e,
-                            cmd = command_name
-                        ))
-                    }
-                },
-                Err(e) => {
-                    let msg: String = match &e.kind() {
-                        ErrorKind::NotFound => "No such file or directory".to_owned(),
-                        ErrorKind::PermissionDenied => "Permission denied".to_owned(),
-                        // ErrorKind::IsADirectory => "Is a directory",
-                        _ => e.to_string(),
-                    };
-                    return Err(format!(
-                        "{cmd}: {}: {}",
-                        stdout.filename,
-                        msg,
-                        cmd = command_name
-                    ));
-                }
-            }
-        }
-        None => Stdio::piped(), // Pipe if not redirecting
+    // Stdin: inherit, previous stage's stdout, or nothing
+    let stdin_stdio = match stdin {
+        PipeInput::Inherit => Stdio::inherit(),
+        PipeInput::Child(child_stdout) => Stdio::from(child_stdout),
+        PipeInput::Null => Stdio::null(),
     };
-    command.stdout(stdout_stdio);
-
-    // Stderr: Redirect to file or inherit
-    let stderr_stdio = match &redirections.stderr_redirect {
-        Some(stderr) => {
-            // Check if the target is an existing directory
-            if let Ok(metadata) = fs::metadata(&stderr.filename) {
-                if metadata.is_dir() {
-                    return Err(format!(
-                        "{cmd}: {}: Is a directory",
-                        stderr.filename,
-                        cmd = command_name
-                    ));
-                }
-            }
-
-            match OpenOptions::new()
-                .write(true)
-                .create(true)
-                .truncate(stderr.mode == RedirectionMode::Overwrite)
-                .append(stderr.mode == RedirectionMode::Append)
-                .open(&stderr.filename)
-            {
-                Ok(file) => match file.try_clone() {
-                    Ok(cloned) => {
-                        stderr_handle = Some(file);
-                        Stdio::from(cloned)
-                    }
-                    Err(e) => {
-                        return Err(format!(
-                            "{cmd}: {}: {}",
-                            stderr.filename,
-                            e,
-                            cmd = command_name
-                        ))
-                    }
-                },
-                Err(e) => {
-                    let msg = match e.kind() {
-                        ErrorKind::NotFound => "No such file or directory".to_string(),
-                        ErrorKind::PermissionDenied => "Permission denied".to_string(),
-                        // ErrorKind::IsADirectory => "Is a directory".to_string(),
-                        _ => e.to_string(),
-                    };
-                    return Err(format!(
-                        "{cmd}: {}: {}",
-                        stderr.filename,
-                        msg,
-                        cmd = command_name
-                    ));
-                }
-            }
-        }
-        None => Stdio::piped(), // Pipe stderr for raw mode formatting
-    };
-    command.stderr(stderr_stdio);
+    command.stdin(stdin_stdio);
+    // Stdout: pipe to the next stage, or write directly to the terminal
+    command.stdout(if pipe_stdout {
+        Stdio::piped()
+    } else {
+        Stdio::inherit()
+    });
+    command.stderr(Stdio::inherit());
 
-    // --- Spawn and Wait ---
-    let mut child = command.spawn().map_err(|e| {
+    // --- Spawn ---
+    let child = command.spawn().map_err(|e| {
         match e.kind() {
             ErrorKind::NotFound => format!("{}: command not found (spawn error)", command_name), // Should be rare
             ErrorKind::PermissionDenied => format!("{}: Permission denied", command_name),
@@ -269,76 +489,74 @@ pub fn execute_external_command(
         }
     })?;
 
-    // Capture stdout only if it was piped
-    let mut captured_stdout = String::new();
-    if redirections.stdout_redirect.is_none() {
-        if let Some(mut child_stdout) = child.stdout.take() {
-            if let Err(e) = child_stdout.read_to_string(&mut captured_stdout) {
-                // TODO: return error, not print
-                // Non-fatal error reading pipe, warn but proceed
-                crate::raw_eprintln!("shell: warning: error reading command stdout pipe: {}", e);
-            }
-        }
-    }
-
-    // Capture stderr only if it was piped (not redirected)
-    let mut captured_stderr = String::new();
-    if redirections.stderr_redirect.is_none() {
-        if let Some(mut child_stderr) = child.stderr.take() {
-            if let Err(e) = child_stderr.read_to_string(&mut captured_stderr) {
-                // Non-fatal error reading pipe, warn but proceed
-                crate::raw_eprintln!("shell: warning: error reading command stderr pipe: {}", e);
-            }
-        }
-    }
-
-    // Wait for the command to finish and get exit status
-    let status = child
-        .wait()
-        .map_err(|e| format!("failed to wait for command '{}': {}", command_name, e))?;
+    // The child has its own copies of the redirection targets; closing ours lets
+    // the writers see a broken pipe if the child exits without reading
+    drop(prepared);
 
-    // Ensure handles are dropped *after* wait()
-    drop(stdout_handle);
-    drop(stderr_handle);
+    Ok(child)
+}
 
-    // TODO: enhance caret replacement
-    // Print captured stdout if any *before* checking status
-    let formatted_output = captured_stdout.replace('\n', "\r\n");
-    if !formatted_output.is_empty() {
-        crate::raw_print!("{}", formatted_output);
+/// Converts a process exit status to the numeric status reported by the shell:
+/// the exit code, or 128 + the signal number if the process was killed by a signal.
+pub fn exit_code(status: ExitStatus) -> i32 {
+    if let Some(code) = status.code() {
+        return code;
     }
+    #[cfg(unix)]
+    {
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+    1 // Unknown termination, report a generic failure
+}
 
-    // Print captured stderr if any
-    let formatted_stderr = captured_stderr.replace('\n', "\r\n");
-    if !formatted_stderr.is_empty() {
-        crate::raw_eprint!("{}", formatted_stderr);
+/// Waits for the child process `pid` to exit, and returns its status as given
+/// by `exit_code`. Used for subshells, which are not started as `Child`ren.
+pub fn wait_for_exit(pid: libc::pid_t) -> i32 {
+    let mut status = 0;
+    // SAFETY: waitpid only writes the status of our own child into `status`.
+    while unsafe { libc::waitpid(pid, &mut status, 0) } == -1 {
+        if io::Error::last_os_error().kind() != ErrorKind::Interrupted {
+            return 1; // Not our child any more, report a generic failure
+        }
     }
+    exit_code(ExitStatus::from_raw(status))
+}
 
-    // --- Return status ---
-    if status.success() {
-        Ok(None) // Success, output handled
-    } else {
-        Err(String::new()) // Failure (non-zero exit), signal shell not to print more errors
+/// Ends the line after a foreground command was interrupted with Ctrl-C: the
+/// terminal echoes `^C` without a newline, and the prompt would follow it.
+///
+/// Returns true if the command was killed by SIGINT.
+pub fn finish_interrupted_line(status: ExitStatus) -> bool {
+    #[cfg(unix)]
+    {
+        if status.signal() == Some(libc::SIGINT) {
+            eprintln!();
+            return true;
+        }
     }
+    false
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::env;
     use std::path::Path;
 
     #[test]
     fn test_find_exec_in_path_direct() {
         // Test with a known executable (if it exists)
         if Path::new("/bin/ls").exists() {
-            let result = find_exec_in_path("/bin/ls");
+            let result = find_exec_in_path("/bin/ls", None);
             assert_eq!(result, Some("/bin/ls".to_string()));
         }
     }
 
     #[test]
     fn test_find_exec_in_path_nonexistent() {
-        let result = find_exec_in_path("/nonexistent/command");
+        let result = find_exec_in_path("/nonexistent/command", None);
         assert_eq!(result, None);
     }
 
@@ -346,7 +564,18 @@ mod tests {
     fn test_find_exec_in_path_search() {
         // This test might be environment-dependent
         // Just ensure it doesn't panic
-        let _result = find_exec_in_path("ls");
+        let _result = find_exec_in_path("ls", env::var("PATH").ok().as_deref());
+    }
+
+    #[test]
+    fn test_exit_code() {
+        let status = Command::new("sh").args(["-c", "exit 3"]).status().unwrap();
+        assert_eq!(exit_code(status), 3);
+        let status = Command::new("sh")
+            .args(["-c", "kill -9 $$"])
+            .status()
+            .unwrap();
+        assert_eq!(exit_code(status), 128 + 9);
     }
 
     #[test]
@@ -0,0 +1,775 @@
+//! Word expansion module for the rust shell.
+//!
+//! This module turns the raw words produced by the parser into command
+//! arguments. It performs parameter expansion (`$NAME`, `${NAME}`,
+//! `${NAME:-default}`, `${NAME:=value}`, `${#NAME}`, `$?`, `$1`, `$#`) and
+//! command substitution (`$(command)` and `` `command` ``), removes quotes
+//! and backslash escapes, and splits the results of unquoted expansions into
+//! separate fields. Nothing is expanded inside single quotes. The bodies of
+//! here-documents are expanded with their own rules, where quotes are literal.
+
+use crate::parser::{
+    read_backquoted, read_command_substitution, BACKSLASH, BACKTICK, CLOSE_BRACE, DOLLAR,
+    DOUBLE_QUOTE, OPEN_BRACE, OPEN_PAREN, SINGLE_QUOTE,
+};
+use crate::shell::Shell;
+use crate::variables::is_valid_name;
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// Field separators used when `IFS` is unset.
+const DEFAULT_IFS: &str = " \t\n";
+
+/// Characters a backslash escapes inside double quotes. Before any other
+/// character the backslash is kept literally.
+const DOUBLE_QUOTE_ESCAPABLE: [char; 5] = ['$', '`', '"', '\\', '\n'];
+
+/// Characters a backslash escapes in the body of a here-document, and in a
+/// backquoted command substitution.
+const HEREDOC_ESCAPABLE: [char; 3] = ['$', '`', '\\'];
+
+/// Collects the fields produced while expanding a word.
+struct Fields {
+    /// Completed fields
+    done: Vec<String>,
+    /// The field being built
+    current: String,
+    /// Whether a field is in progress, even if still empty (e.g. after `""`)
+    started: bool,
+    /// Separators splitting unquoted expansion results, None to never split
+    ifs: Option<String>,
+}
+
+impl Fields {
+    /// Creates an empty field list splitting on the given separators.
+    fn new(ifs: Option<String>) -> Self {
+        Self {
+            done: Vec::new(),
+            current: String::new(),
+            started: false,
+            ifs,
+        }
+    }
+
+    /// Marks the current field as present, so an empty quoted word still yields a field.
+    fn start(&mut self) {
+        self.started = true;
+    }
+
+    /// Appends a literal character to the current field.
+    fn push_char(&mut self, c: char) {
+        self.current.push(c);
+        self.started = true;
+    }
+
+    /// Appends an expansion result, splitting it into fields unless it was quoted.
+    fn push_expansion(&mut self, text: &str, quoted: bool) {
+        if quoted {
+            self.current.push_str(text);
+            self.started = true;
+            return;
+        }
+        for c in text.chars() {
+            if self.is_separator(c) {
+                self.end_field();
+            } else {
+                self.push_char(c);
+            }
+        }
+    }
+
+    /// Handles unquoted whitespace inside a `${...}` word, which separates fields.
+    fn push_blank(&mut self, c: char) {
+        if self.ifs.is_some() {
+            self.end_field();
+        } else {
+            self.push_char(c);
+        }
+    }
+
+    /// Returns true if `c` separates fields in unquoted expansion results.
+    fn is_separator(&self, c: char) -> bool {
+        self.ifs.as_ref().is_some_and(|ifs| ifs.contains(c))
+    }
+
+    /// Completes the current field, if one was started.
+    fn end_field(&mut self) {
+        if self.started {
+            self.done.push(std::mem::take(&mut self.current));
+            self.started = false;
+        }
+    }
+
+    /// Completes expansion and returns all fields.
+    fn finish(mut self) -> Vec<String> {
+        self.end_field();
+        self.done
+    }
+}
+
+/// Expands a raw word into zero or more fields.
+///
+/// # Arguments
+///
+/// * `word` - The raw word as produced by the parser
+/// * `shell` - The shell state providing (and receiving, for `${NAME:=value}`) variables
+///
+/// # Returns
+///
+/// * `Ok(fields)` - The resulting fields; an unquoted empty expansion yields none
+/// * `Err(message)` - A malformed or failing expansion (e.g. `${NAME:?}`)
+///
+/// # Examples
+///
+/// ```
+/// use codecrafters_shell::expand::expand_word;
+/// use codecrafters_shell::shell::Shell;
+///
+/// let mut shell = Shell::default();
+/// shell.vars.set("GREETING", "hello world");
+/// assert_eq!(expand_word("$GREETING", &mut shell).unwrap(), vec!["hello", "world"]);
+/// assert_eq!(expand_word("\"$GREETING\"", &mut shell).unwrap(), vec!["hello world"]);
+/// ```
+pub fn expand_word(word: &str, shell: &mut Shell) -> Result<Vec<String>, String> {
+    let ifs = shell.vars.get("IFS").unwrap_or(DEFAULT_IFS).to_string();
+    let mut fields = Fields::new(Some(ifs));
+    expand_into(word, false, shell, &mut fields)?;
+    Ok(fields.finish())
+}
+
+/// Expands a list of raw words, concatenating the fields of each.
+pub fn expand_words(words: &[String], shell: &mut Shell) -> Result<Vec<String>, String> {
+    let mut expanded = Vec::new();
+    for word in words {
+        expanded.extend(expand_word(word, shell)?);
+    }
+    Ok(expanded)
+}
+
+/// Expands the target of a redirection, which must produce exactly one field.
+pub fn expand_redirect_target(word: &str, shell: &mut Shell) -> Result<String, String> {
+    let mut fields = expand_word(word, shell)?;
+    match fields.len() {
+        1 => Ok(fields.remove(0)),
+        _ => Err(format!("{}: ambiguous redirect", word)),
+    }
+}
+
+/// Expands the word of a here-string (`<<<word`) into a single string, without
+/// field splitting.
+pub fn expand_here_string(word: &str, shell: &mut Shell) -> Result<String, String> {
+    expand_to_string(word, shell)
+}
+
+/// Expands the body of a here-document with an unquoted delimiter. Parameters
+/// are expanded and a backslash escapes `$`, `` ` ``, `\` and newline (a line
+/// continuation), while quotes are ordinary characters.
+pub fn expand_heredoc(body: &str, shell: &mut Shell) -> Result<String, String> {
+    let mut fields = Fields::new(None);
+    fields.start(); // An empty body is still a (empty) document
+    let mut chars = body.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            BACKSLASH => match chars.peek() {
+                Some('\n') => {
+                    chars.next(); // Line continuation
+                }
+                Some(&next_char) if HEREDOC_ESCAPABLE.contains(&next_char) => {
+                    chars.next();
+                    fields.push_char(next_char);
+                }
+                _ => fields.push_char(c),
+            },
+            DOLLAR => expand_parameter(&mut chars, true, shell, &mut fields)?,
+            BACKTICK => expand_backquoted(&mut chars, true, shell, &mut fields)?,
+            _ => fields.push_char(c),
+        }
+    }
+    Ok(fields.finish().pop().unwrap_or_default())
+}
+
+/// Returns the name and raw value of a `NAME=value` assignment word, or None if
+/// the word is not an assignment. The name must be unquoted.
+pub fn split_assignment(word: &str) -> Option<(&str, &str)> {
+    word.split_once('=').filter(|(name, _)| is_valid_name(name))
+}
+
+/// Splits the raw words of a command into its leading assignments and the rest.
+pub fn split_assignments(words: &[String]) -> (&[String], &[String]) {
+    let count = words
+        .iter()
+        .take_while(|word| split_assignment(word).is_some())
+        .count();
+    words.split_at(count)
+}
+
+/// Expands the value of a `NAME=value` assignment word, returning the name and
+/// the value. The value is not split into fields.
+pub fn expand_assignment(word: &str, shell: &mut Shell) -> Result<(String, String), String> {
+    let (name, raw_value) =
+        split_assignment(word).ok_or_else(|| format!("{}: not an assignment", word))?;
+    let value = expand_to_string(raw_value, shell)?;
+    Ok((name.to_string(), value))
+}
+
+/// Expands a word into a single string without field splitting, as done for
+/// the value assigned by `${NAME:=value}`.
+fn expand_to_string(word: &str, shell: &mut Shell) -> Result<String, String> {
+    let mut fields = Fields::new(None);
+    expand_into(word, false, shell, &mut fields)?;
+    Ok(fields.finish().pop().unwrap_or_default())
+}
+
+/// Expands `text` into `fields`, removing quotes and escapes.
+/// `in_double_quotes` is the quoting context the text starts in, which is
+/// double-quoted for the default word of a `"${NAME:-default}"` expansion.
+fn expand_into(
+    text: &str,
+    mut in_double_quotes: bool,
+    shell: &mut Shell,
+    fields: &mut Fields,
+) -> Result<(), String> {
+    let mut in_single_quotes = false;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            // Handle backslash escapes
+            BACKSLASH => {
+                if in_single_quotes {
+                    // Inside single quotes, backslashes are literal
+                    fields.push_char(c);
+                } else {
+                    match chars.next() {
+                        // Inside double quotes, only a few characters can be escaped
+                        Some(next_char) if in_double_quotes => {
+                            if !DOUBLE_QUOTE_ESCAPABLE.contains(&next_char) {
+                                fields.push_char(c);
+                            }
+                            fields.push_char(next_char);
+                        }
+                        // Outside quotes, escape the next character
+                        Some(next_char) => fields.push_char(next_char),
+                        // Backslash at end of input - treat as literal
+                        None => fields.push_char(c),
+                    }
+                }
+            }
+            // Handle single quotes
+            SINGLE_QUOTE if !in_double_quotes => {
+                in_single_quotes = !in_single_quotes;
+                fields.start();
+            }
+            // Handle double quotes
+            DOUBLE_QUOTE if !in_single_quotes => {
+                in_double_quotes = !in_double_quotes;
+                fields.start();
+            }
+            // Handle parameter expansion
+            DOLLAR if !in_single_quotes => {
+                expand_parameter(&mut chars, in_double_quotes, shell, fields)?;
+            }
+            // Handle backquoted command substitution
+            BACKTICK if !in_single_quotes => {
+                expand_backquoted(&mut chars, in_double_quotes, shell, fields)?;
+            }
+            // Unquoted whitespace (only possible inside `${...}`) separates fields
+            ' ' | '\t' | '\n' if !in_single_quotes && !in_double_quotes => {
+                fields.push_blank(c);
+            }
+            // Regular characters
+            _ => fields.push_char(c),
+        }
+    }
+    Ok(())
+}
+
+/// Expands the parameter or command substitution following a `$`. A `$` that
+/// doesn't start an expansion is kept literally.
+fn expand_parameter(
+    chars: &mut Peekable<Chars>,
+    quoted: bool,
+    shell: &mut Shell,
+    fields: &mut Fields,
+) -> Result<(), String> {
+    match chars.peek().copied() {
+        // `${...}`
+        Some(OPEN_BRACE) => {
+            chars.next(); // Consume '{'
+            let expression = read_braced(chars)?;
+            expand_braced(&expression, quoted, shell, fields)
+        }
+        // `$(command)`
+        Some(OPEN_PAREN) => {
+            chars.next(); // Consume '('
+            let command = read_command_substitution(chars)?;
+            substitute_command(&command, quoted, shell, fields)
+        }
+        // Special parameters `$?`, `$$`, `$!`, `$#` and positional parameters `$0`..`$9`
+        Some(c) if is_special_parameter(c) => {
+            chars.next();
+            let value = lookup(&c.to_string(), shell).unwrap_or_default();
+            fields.push_expansion(&value, quoted);
+            Ok(())
+        }
+        // `$NAME`
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            let mut name = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_ascii_alphanumeric() || c == '_' {
+                    name.push(c);
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            let value = lookup(&name, shell).unwrap_or_default();
+            fields.push_expansion(&value, quoted);
+            Ok(())
+        }
+        // A lone `$`
+        _ => {
+            fields.push_char(DOLLAR);
+            Ok(())
+        }
+    }
+}
+
+/// Expands the `` `command` `` substitution following a backquote. Inside it,
+/// a backslash escapes `$`, `` ` `` and `\`, so nested substitutions have their
+/// backquotes escaped.
+fn expand_backquoted(
+    chars: &mut Peekable<Chars>,
+    quoted: bool,
+    shell: &mut Shell,
+    fields: &mut Fields,
+) -> Result<(), String> {
+    let raw_command = read_backquoted(chars)?;
+    let mut body = raw_command.chars().peekable();
+    let mut command = String::new();
+    while let Some(c) = body.next() {
+        match body.peek() {
+            Some(&next_char) if c == BACKSLASH && HEREDOC_ESCAPABLE.contains(&next_char) => {
+                command.push(next_char);
+                body.next();
+            }
+            _ => command.push(c),
+        }
+    }
+    substitute_command(&command, quoted, shell, fields)
+}
+
+/// Runs the command line of a command substitution and adds its output, without
+/// trailing newlines, to the fields, split into fields unless it was quoted.
+fn substitute_command(
+    command: &str,
+    quoted: bool,
+    shell: &mut Shell,
+    fields: &mut Fields,
+) -> Result<(), String> {
+    let run = shell
+        .run_substitution
+        .ok_or_else(|| "command substitution: commands can't be run here".to_string())?;
+    let output = run(shell, command)?;
+    fields.push_expansion(output.trim_end_matches('\n'), quoted);
+    Ok(())
+}
+
+/// Reads the contents of a `${...}` expansion up to its matching closing brace,
+/// skipping over quotes, escapes and nested expansions.
+fn read_braced(chars: &mut Peekable<Chars>) -> Result<String, String> {
+    let mut expression = String::new();
+    let mut depth = 0;
+    let mut in_single_quotes = false;
+    let mut in_double_quotes = false;
+
+    while let Some(c) = chars.next() {
+        match c {
+            BACKSLASH if !in_single_quotes => {
+                expression.push(c);
+                if let Some(next_char) = chars.next() {
+                    expression.push(next_char);
+                }
+                continue;
+            }
+            SINGLE_QUOTE if !in_double_quotes => in_single_quotes = !in_single_quotes,
+            DOUBLE_QUOTE if !in_single_quotes => in_double_quotes = !in_double_quotes,
+            DOLLAR if !in_single_quotes && chars.peek() == Some(&OPEN_BRACE) => {
+                expression.push(c);
+                expression.push(OPEN_BRACE);
+                chars.next();
+                depth += 1;
+                continue;
+            }
+            CLOSE_BRACE if !in_single_quotes => {
+                if depth == 0 {
+                    return Ok(expression);
+                }
+                depth -= 1;
+            }
+            _ => {}
+        }
+        expression.push(c);
+    }
+    Err(format!("${{{}: bad substitution", expression))
+}
+
+/// Expands the contents of a `${...}` expansion: `NAME`, `#NAME`, or `NAME`
+/// followed by one of the operators `-`, `=`, `+`, `?`, optionally preceded by
+/// `:` to also treat an empty value as unset.
+fn expand_braced(
+    expression: &str,
+    quoted: bool,
+    shell: &mut Shell,
+    fields: &mut Fields,
+) -> Result<(), String> {
+    let bad_substitution = || format!("${{{}}}: bad substitution", expression);
+
+    // `${#NAME}`: length of the value
+    if let Some(name) = expression.strip_prefix('#').filter(|name| !name.is_empty()) {
+        if !is_parameter_name(name) {
+            return Err(bad_substitution());
+        }
+        let length = lookup(name, shell).unwrap_or_default().chars().count();
+        fields.push_expansion(&length.to_string(), quoted);
+        return Ok(());
+    }
+
+    let name_len = match expression.chars().next() {
+        Some(c) if is_special_parameter(c) => 1,
+        _ => expression
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(expression.len()),
+    };
+    let (name, operation) = expression.split_at(name_len);
+    if !is_parameter_name(name) {
+        return Err(bad_substitution());
+    }
+    let value = lookup(name, shell);
+
+    // `${NAME}`
+    if operation.is_empty() {
+        fields.push_expansion(&value.unwrap_or_default(), quoted);
+        return Ok(());
+    }
+
+    // `${NAME<op>word}` or `${NAME:<op>word}`
+    let (check_empty, operation) = match operation.strip_prefix(':') {
+        Some(rest) => (true, rest),
+        None => (false, operation),
+    };
+    let mut op_chars = operation.chars();
+    let op = op_chars.next().ok_or_else(bad_substitution)?;
+    let word = op_chars.as_str();
+    let is_unset = match &value {
+        Some(v) => check_empty && v.is_empty(),
+        None => true,
+    };
+
+    match op {
+        // Use the default word if unset
+        '-' => match value {
+            Some(v) if !is_unset => fields.push_expansion(&v, quoted),
+            _ => expand_into(word, quoted, shell, fields)?,
+        },
+        // Assign the default word if unset
+        '=' => match value {
+            Some(v) if !is_unset => fields.push_expansion(&v, quoted),
+            _ => {
+                if !is_valid_name(name) {
+                    return Err(format!("${}: cannot assign in this way", name));
+                }
+                let assigned = expand_to_string(word, shell)?;
+                shell.vars.set(name, &assigned);
+                fields.push_expansion(&assigned, quoted);
+            }
+        },
+        // Use the alternate word if set
+        '+' => {
+            if !is_unset {
+                expand_into(word, quoted, shell, fields)?;
+            }
+        }
+        // Fail with the given message if unset
+        '?' => match value {
+            Some(v) if !is_unset => fields.push_expansion(&v, quoted),
+            _ => {
+                let message = expand_to_string(word, shell)?;
+                return Err(if message.is_empty() {
+                    format!("{}: parameter null or not set", name)
+                } else {
+                    format!("{}: {}", name, message)
+                });
+            }
+        },
+        _ => return Err(bad_substitution()),
+    }
+    Ok(())
+}
+
+/// Returns true for the single-character special parameters `?`, `$`, `!`, `#`
+/// and `0`..`9`.
+fn is_special_parameter(c: char) -> bool {
+    "?$!#".contains(c) || c.is_ascii_digit()
+}
+
+/// Returns true if `name` can be expanded: a variable name or a special parameter.
+fn is_parameter_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if is_special_parameter(c) => true,
+        _ => is_valid_name(name),
+    }
+}
+
+/// Returns the value of a parameter, or None if it is unset.
+fn lookup(name: &str, shell: &Shell) -> Option<String> {
+    match name {
+        "?" => Some(shell.last_status.to_string()),
+        "$" => Some(shell.pid.to_string()),
+        "!" => shell.jobs.last_pid.map(|pid| pid.to_string()),
+        "#" => Some(shell.positional.len().to_string()),
+        _ => match name.parse::<usize>() {
+            Ok(0) | Err(_) => shell.vars.get(name).map(str::to_string),
+            Ok(index) => shell.positional.get(index - 1).cloned(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::quote_word;
+
+    fn expand(word: &str, shell: &mut Shell) -> Vec<String> {
+        expand_word(word, shell).unwrap()
+    }
+
+    #[test]
+    fn test_quote_removal() {
+        let mut shell = Shell::default();
+        assert_eq!(expand(r#""hello world""#, &mut shell), vec!["hello world"]);
+        assert_eq!(expand("'hello world'", &mut shell), vec!["hello world"]);
+        assert_eq!(expand(r"hello\ world", &mut shell), vec!["hello world"]);
+        assert_eq!(expand(r#""a\b\$c""#, &mut shell), vec![r"a\b$c"]);
+        assert_eq!(expand(r#""""#, &mut shell), vec![""]);
+    }
+
+    #[test]
+    fn test_quoted_word_round_trip() {
+        let mut shell = Shell::default();
+        for text in ["it's $HOME", "a  b", "", r#"back\slash "quoted""#] {
+            assert_eq!(expand(&quote_word(text), &mut shell), vec![text]);
+        }
+    }
+
+    #[test]
+    fn test_simple_variables() {
+        let mut shell = Shell::default();
+        shell.vars.set("NAME", "world");
+        assert_eq!(expand("$NAME", &mut shell), vec!["world"]);
+        assert_eq!(expand("${NAME}s", &mut shell), vec!["worlds"]);
+        assert_eq!(expand("hello_$NAME!", &mut shell), vec!["hello_world!"]);
+        assert_eq!(expand("$UNSET", &mut shell), Vec::<String>::new());
+        assert_eq!(expand(r#""$UNSET""#, &mut shell), vec![""]);
+        assert_eq!(expand("$", &mut shell), vec!["$"]);
+    }
+
+    #[test]
+    fn test_quoting_rules() {
+        let mut shell = Shell::default();
+        shell.vars.set("NAME", "world");
+        assert_eq!(expand("'$NAME'", &mut shell), vec!["$NAME"]);
+        assert_eq!(expand(r#""$NAME""#, &mut shell), vec!["world"]);
+        assert_eq!(expand(r"\$NAME", &mut shell), vec!["$NAME"]);
+        assert_eq!(expand(r#""'$NAME'""#, &mut shell), vec!["'world'"]);
+    }
+
+    #[test]
+    fn test_last_status() {
+        let mut shell = Shell {
+            last_status: 127,
+            ..Default::default()
+        };
+        assert_eq!(expand("$?", &mut shell), vec!["127"]);
+        assert_eq!(expand(r#""$?""#, &mut shell), vec!["127"]);
+        assert_eq!(expand("'$?'", &mut shell), vec!["$?"]);
+        assert_eq!(expand(r"\$?", &mut shell), vec!["$?"]);
+
+        // `$!` is unset until a job is started in the background
+        assert_eq!(expand("${!-none}", &mut shell), vec!["none"]);
+        shell.jobs.last_pid = Some(4321);
+        assert_eq!(expand("$!", &mut shell), vec!["4321"]);
+    }
+
+    #[test]
+    fn test_field_splitting() {
+        let mut shell = Shell::default();
+        shell.vars.set("LIST", "  a  b c ");
+        assert_eq!(expand("$LIST", &mut shell), vec!["a", "b", "c"]);
+        assert_eq!(expand(r#""$LIST""#, &mut shell), vec!["  a  b c "]);
+        assert_eq!(
+            expand("x${LIST}y", &mut shell),
+            vec!["x", "a", "b", "c", "y"]
+        );
+    }
+
+    #[test]
+    fn test_default_values() {
+        let mut shell = Shell::default();
+        shell.vars.set("EMPTY", "");
+        assert_eq!(expand("${UNSET:-fallback}", &mut shell), vec!["fallback"]);
+        assert_eq!(expand("${EMPTY:-fallback}", &mut shell), vec!["fallback"]);
+        assert_eq!(
+            expand("${EMPTY-fallback}", &mut shell),
+            Vec::<String>::new()
+        );
+        assert_eq!(expand("${UNSET:-a b}", &mut shell), vec!["a", "b"]);
+        assert_eq!(expand(r#""${UNSET:-a b}""#, &mut shell), vec!["a b"]);
+        assert_eq!(expand("${UNSET:-$EMPTY}", &mut shell), Vec::<String>::new());
+        assert_eq!(expand("${EMPTY:+set}", &mut shell), Vec::<String>::new());
+        assert_eq!(expand("${EMPTY+set}", &mut shell), vec!["set"]);
+    }
+
+    #[test]
+    fn test_assign_default() {
+        let mut shell = Shell::default();
+        assert_eq!(expand("${NEW:=first}", &mut shell), vec!["first"]);
+        assert_eq!(shell.vars.get("NEW"), Some("first"));
+        assert_eq!(expand("${NEW:=second}", &mut shell), vec!["first"]);
+        assert!(expand_word("${?:=x}", &mut shell).is_ok()); // `$?` is always set
+    }
+
+    #[test]
+    fn test_length() {
+        let mut shell = Shell::default();
+        shell.vars.set("WORD", "héllo");
+        assert_eq!(expand("${#WORD}", &mut shell), vec!["5"]);
+        assert_eq!(expand("${#UNSET}", &mut shell), vec!["0"]);
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut shell = Shell::default();
+        assert!(expand_word("${}", &mut shell).is_err());
+        assert!(expand_word("${A B}", &mut shell).is_err());
+        assert!(expand_word("${UNSET", &mut shell).is_err());
+        assert_eq!(
+            expand_word("${UNSET:?is required}", &mut shell).unwrap_err(),
+            "UNSET: is required"
+        );
+    }
+
+    #[test]
+    fn test_redirect_target() {
+        let mut shell = Shell::default();
+        shell.vars.set("FILE", "out.txt");
+        shell.vars.set("FILES", "a b");
+        assert_eq!(
+            expand_redirect_target("$FILE", &mut shell).unwrap(),
+            "out.txt"
+        );
+        assert!(expand_redirect_target("$FILES", &mut shell).is_err());
+    }
+
+    #[test]
+    fn test_assignments() {
+        let words: Vec<String> = ["FOO=1", "BAR=\"a b\"", "cmd", "BAZ=2"]
+            .iter()
+            .map(|word| word.to_string())
+            .collect();
+        let (assignments, rest) = split_assignments(&words);
+        assert_eq!(assignments, &words[..2]);
+        assert_eq!(rest, &words[2..]);
+        assert_eq!(split_assignment("'FOO'=1"), None);
+        assert_eq!(split_assignment("=1"), None);
+
+        let mut shell = Shell::default();
+        shell.vars.set("WORDS", "x  y");
+        assert_eq!(
+            expand_assignment("BAR=\"a b\"", &mut shell).unwrap(),
+            ("BAR".to_string(), "a b".to_string())
+        );
+        assert_eq!(
+            expand_assignment("V=$WORDS", &mut shell).unwrap(),
+            ("V".to_string(), "x  y".to_string())
+        );
+    }
+
+    #[test]
+    fn test_heredoc_and_here_string() {
+        let mut shell = Shell::default();
+        shell.vars.set("NAME", "a  b");
+        assert_eq!(
+            expand_heredoc("hi $NAME \"${NAME}\" '$NAME'\n", &mut shell).unwrap(),
+            "hi a  b \"a  b\" 'a  b'\n"
+        );
+        assert_eq!(
+            expand_heredoc(r"\$NAME \\ \x cost: $ 5", &mut shell).unwrap(),
+            r"$NAME \ \x cost: $ 5"
+        );
+        assert_eq!(
+            expand_heredoc("one \\\ntwo\n", &mut shell).unwrap(),
+            "one two\n"
+        );
+        assert_eq!(expand_heredoc("", &mut shell).unwrap(), "");
+        assert_eq!(
+            expand_here_string("\"$NAME\"c", &mut shell).unwrap(),
+            "a  bc"
+        );
+        assert_eq!(expand_here_string("$NAME", &mut shell).unwrap(), "a  b");
+    }
+
+    #[test]
+    fn test_positional_parameters() {
+        let mut shell = Shell {
+            positional: vec!["a b".to_string(), "c".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(expand("$1", &mut shell), vec!["a", "b"]);
+        assert_eq!(expand(r#""$1"-$2"#, &mut shell), vec!["a b-c"]);
+        assert_eq!(expand("$3", &mut shell), Vec::<String>::new());
+        assert_eq!(expand("$# ${#} ${#1}", &mut shell), vec!["2", "2", "3"]);
+    }
+
+    /// Stands in for running a command: outputs the command line itself,
+    /// followed by blank lines.
+    fn echo_command(_: &mut Shell, command: &str) -> Result<String, String> {
+        Ok(format!("{}\n\n", command))
+    }
+
+    #[test]
+    fn test_command_substitution() {
+        let mut shell = Shell {
+            run_substitution: Some(echo_command),
+            ..Default::default()
+        };
+        assert_eq!(expand("$(a  b)", &mut shell), vec!["a", "b"]);
+        assert_eq!(expand(r#""$(a  b)""#, &mut shell), vec!["a  b"]);
+        assert_eq!(expand("x$(a b)y", &mut shell), vec!["xa", "by"]);
+        assert_eq!(expand("$( )", &mut shell), Vec::<String>::new());
+        assert_eq!(
+            expand("$(echo $(inner) ')')", &mut shell),
+            vec!["echo", "$(inner)", "')'"]
+        );
+        assert_eq!(expand("'$(a)'", &mut shell), vec!["$(a)"]);
+        assert_eq!(expand(r"\$(a)", &mut shell), vec!["$(a)"]);
+
+        // Backquotes, where a backslash escapes nested backquotes
+        assert_eq!(expand("`a  b`", &mut shell), vec!["a", "b"]);
+        assert_eq!(expand(r#""`a  b`""#, &mut shell), vec!["a  b"]);
+        assert_eq!(
+            expand(r"`x \`y\` \$z \n`", &mut shell),
+            vec!["x", "`y`", "$z", r"\n"]
+        );
+        assert_eq!(expand_heredoc("$(a) `b`\n", &mut shell).unwrap(), "a b\n");
+        assert_eq!(
+            expand_assignment("V=$(a  b)", &mut shell).unwrap(),
+            ("V".to_string(), "a  b".to_string())
+        );
+
+        assert!(expand_word("$(a", &mut shell).is_err());
+        assert!(expand_word("`a", &mut shell).is_err());
+        assert!(expand_word("$(a)", &mut Shell::default()).is_err());
+    }
+}
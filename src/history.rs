@@ -0,0 +1,390 @@
+//! Command history module for the rust shell.
+//!
+//! This module keeps the lines entered during the session and lets the line
+//! editor walk through them with the Up and Down arrow keys or search them
+//! with Ctrl-R. History is loaded from `$HISTFILE` (by default
+//! `~/.rush_history`) at startup and the new entries are appended to it on
+//! exit. `HISTSIZE` limits the number of entries kept in memory and
+//! `HISTFILESIZE` the number of lines kept in the file.
+
+use crate::variables::Variables;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+
+/// Name of the history file in the home directory, used when `HISTFILE` is unset.
+const DEFAULT_HISTFILE: &str = ".rush_history";
+/// Number of entries kept when `HISTSIZE` is unset or invalid.
+const DEFAULT_HISTSIZE: usize = 500;
+
+/// The list of command lines entered in the shell, oldest first.
+#[derive(Debug)]
+pub struct History {
+    /// The recorded lines
+    entries: Vec<String>,
+    /// Number of entries at the end not yet written to the history file
+    unsaved: usize,
+    /// The history file, None if history is not persisted
+    file: Option<PathBuf>,
+    /// Maximum number of entries kept in memory
+    max_entries: usize,
+    /// Maximum number of lines kept in the history file
+    max_file_entries: usize,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            entries: Vec::new(),
+            unsaved: 0,
+            file: None,
+            max_entries: DEFAULT_HISTSIZE,
+            max_file_entries: DEFAULT_HISTSIZE,
+        }
+    }
+}
+
+/// Parses a history size variable. A negative number means no limit.
+fn parse_size(value: Option<&str>) -> Option<usize> {
+    let value = value?.trim().parse::<i64>().ok()?;
+    Some(usize::try_from(value).unwrap_or(usize::MAX))
+}
+
+impl History {
+    /// Creates an empty history configured from the shell variables `HISTFILE`,
+    /// `HOME`, `HISTSIZE` and `HISTFILESIZE`. An empty `HISTFILE` disables the
+    /// history file.
+    pub fn from_vars(vars: &Variables) -> Self {
+        let file = match vars.get("HISTFILE") {
+            Some("") => None,
+            Some(path) => Some(PathBuf::from(path)),
+            None => vars
+                .get("HOME")
+                .map(|home| PathBuf::from(home).join(DEFAULT_HISTFILE)),
+        };
+        let max_entries = parse_size(vars.get("HISTSIZE")).unwrap_or(DEFAULT_HISTSIZE);
+        let max_file_entries = parse_size(vars.get("HISTFILESIZE")).unwrap_or(max_entries);
+        Self {
+            file,
+            max_entries,
+            max_file_entries,
+            ..Self::default()
+        }
+    }
+
+    /// Records a line at the end of the history. Lines that are blank, begin
+    /// with a space, or repeat the previous entry are not recorded.
+    pub fn add(&mut self, line: &str) {
+        if line.trim().is_empty() || line.starts_with(' ') {
+            return;
+        }
+        if self.entries.last().is_some_and(|last| last == line) {
+            return;
+        }
+        self.entries.push(line.to_string());
+        self.unsaved += 1;
+        self.truncate();
+    }
+
+    /// Returns all recorded lines, oldest first.
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    /// Returns the number of recorded lines.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Searches backwards for the newest entry containing `query`, among the
+    /// entries before index `before`.
+    ///
+    /// # Returns
+    ///
+    /// The index of the entry and the byte offset of the (last) match in it,
+    /// or None if no entry matches
+    pub fn search_backward(&self, query: &str, before: usize) -> Option<(usize, usize)> {
+        self.entries[..before.min(self.entries.len())]
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(index, entry)| Some((index, entry.rfind(query)?)))
+    }
+
+    /// Drops the oldest entries beyond the in-memory limit.
+    fn truncate(&mut self) {
+        let excess = self.entries.len().saturating_sub(self.max_entries);
+        self.entries.drain(..excess);
+        self.unsaved = self.unsaved.min(self.entries.len());
+    }
+
+    /// Reads the entries of the history file, if any. A missing file is not an error.
+    pub fn load(&mut self) -> io::Result<()> {
+        let Some(path) = &self.file else {
+            return Ok(());
+        };
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        self.entries.extend(contents.lines().map(str::to_string));
+        self.unsaved = 0;
+        self.truncate();
+        Ok(())
+    }
+
+    /// Stops the entries from being written to the history file, in a subshell:
+    /// the lines it recorded are saved by the parent shell.
+    pub fn stop_saving(&mut self) {
+        self.file = None;
+    }
+
+    /// Appends the entries recorded since the last save to the history file,
+    /// then trims the file to its size limit.
+    ///
+    /// The file is locked while it is updated and only new entries are
+    /// appended, so shells sharing a history file don't overwrite each
+    /// other's entries.
+    pub fn save(&mut self) -> io::Result<()> {
+        let Some(path) = &self.file else {
+            return Ok(());
+        };
+        if self.unsaved == 0 {
+            return Ok(());
+        }
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        lock_exclusive(&file)?;
+
+        let new_entries = &self.entries[self.entries.len() - self.unsaved..];
+        let mut appended = String::new();
+        for entry in new_entries {
+            appended.push_str(entry);
+            appended.push('\n');
+        }
+        file.write_all(appended.as_bytes())?;
+        self.unsaved = 0;
+
+        trim_file(&mut file, self.max_file_entries)
+        // The lock is released when `file` is closed
+    }
+}
+
+/// Takes an exclusive lock on `file`, waiting for other shells to release it.
+fn lock_exclusive(file: &File) -> io::Result<()> {
+    // SAFETY: flock only operates on the open file descriptor.
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Keeps only the last `max_lines` lines of the (locked) history file.
+fn trim_file(file: &mut File, max_lines: usize) -> io::Result<()> {
+    let mut contents = String::new();
+    file.seek(SeekFrom::Start(0))?;
+    file.read_to_string(&mut contents)?;
+    let lines: Vec<&str> = contents.lines().collect();
+    if lines.len() <= max_lines {
+        return Ok(());
+    }
+
+    let mut kept = lines[lines.len() - max_lines..].join("\n");
+    if !kept.is_empty() {
+        kept.push('\n');
+    }
+    file.set_len(0)?;
+    file.write_all(kept.as_bytes()) // Appended at the (new) end of the file
+}
+
+/// Position of the line editor within the history while a line is being read.
+///
+/// The position one past the newest entry stands for the line being typed,
+/// which is kept aside while older entries are shown so that it can be
+/// returned to.
+#[derive(Debug)]
+pub struct HistoryNavigator<'a> {
+    /// The history being navigated
+    history: &'a History,
+    /// Index of the entry shown, `history.len()` for the line being typed
+    position: usize,
+    /// The line being typed, saved when first moving into the history
+    draft: String,
+}
+
+impl<'a> HistoryNavigator<'a> {
+    /// Creates a navigator positioned on the (new) line being typed.
+    pub fn new(history: &'a History) -> Self {
+        Self {
+            history,
+            position: history.len(),
+            draft: String::new(),
+        }
+    }
+
+    /// Moves to the previous (older) entry.
+    ///
+    /// # Arguments
+    ///
+    /// * `current` - The contents of the line being edited
+    ///
+    /// # Returns
+    ///
+    /// The line to show, or None if already at the oldest entry
+    pub fn previous(&mut self, current: &str) -> Option<&str> {
+        if self.position == 0 {
+            return None;
+        }
+        if self.position == self.history.len() {
+            self.draft = current.to_string();
+        }
+        self.position -= 1;
+        Some(&self.history.entries[self.position])
+    }
+
+    /// Moves to the next (newer) entry, returning to the line being typed
+    /// after the newest entry.
+    ///
+    /// # Returns
+    ///
+    /// The line to show, or None if already on the line being typed
+    pub fn next(&mut self) -> Option<&str> {
+        if self.position >= self.history.len() {
+            return None;
+        }
+        self.position += 1;
+        match self.history.entries.get(self.position) {
+            Some(entry) => Some(entry),
+            None => Some(&self.draft),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(lines: &[&str]) -> History {
+        let mut history = History::default();
+        for line in lines {
+            history.add(line);
+        }
+        history
+    }
+
+    /// Creates a history persisted to a fresh file in the temporary directory.
+    fn file_history(name: &str, max_entries: usize, max_file_entries: usize) -> History {
+        let path = std::env::temp_dir().join(format!("rush_{}_{}", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        History {
+            file: Some(path),
+            max_entries,
+            max_file_entries,
+            ..History::default()
+        }
+    }
+
+    #[test]
+    fn test_add() {
+        let history = history(&["echo one", "echo two"]);
+        assert_eq!(history.len(), 2);
+        assert_eq!(history.entries(), ["echo one", "echo two"]);
+    }
+
+    #[test]
+    fn test_add_skips_ignored_lines() {
+        let history = history(&["ls", "ls", " secret", "", "pwd", "ls"]);
+        assert_eq!(history.entries(), ["ls", "pwd", "ls"]);
+    }
+
+    #[test]
+    fn test_search_backward() {
+        let history = history(&["cargo build", "ls", "cargo test --all", "pwd"]);
+        assert_eq!(history.search_backward("cargo", 4), Some((2, 0)));
+        assert_eq!(history.search_backward("cargo", 2), Some((0, 0)));
+        assert_eq!(history.search_backward("t", 3), Some((2, 9)));
+        assert_eq!(history.search_backward("cargo", 0), None);
+        assert_eq!(history.search_backward("make", 4), None);
+    }
+
+    #[test]
+    fn test_size_limit() {
+        let mut history = History {
+            max_entries: 2,
+            ..History::default()
+        };
+        for line in ["one", "two", "three"] {
+            history.add(line);
+        }
+        assert_eq!(history.entries(), ["two", "three"]);
+    }
+
+    #[test]
+    fn test_from_vars() {
+        let mut vars = Variables::default();
+        vars.set("HOME", "/home/user");
+        vars.set("HISTSIZE", "10");
+        let history = History::from_vars(&vars);
+        assert_eq!(
+            history.file,
+            Some(PathBuf::from("/home/user/.rush_history"))
+        );
+        assert_eq!((history.max_entries, history.max_file_entries), (10, 10));
+
+        vars.set("HISTFILE", "");
+        vars.set("HISTFILESIZE", "-1");
+        let history = History::from_vars(&vars);
+        assert_eq!(history.file, None);
+        assert_eq!(history.max_file_entries, usize::MAX);
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let mut first = file_history("save_load", 100, 3);
+        let mut second = file_history("save_load_other", 100, 3);
+        second.file = first.file.clone();
+
+        // Two shells sharing the file both keep their entries
+        first.add("one");
+        second.add("two");
+        first.save().unwrap();
+        second.save().unwrap();
+        first.add("three");
+        first.add("four");
+        first.save().unwrap();
+        first.save().unwrap(); // Nothing new to append
+
+        let mut loaded = file_history("save_load_reader", 100, 3);
+        loaded.file = first.file.clone();
+        loaded.load().unwrap();
+        assert_eq!(loaded.entries(), ["two", "three", "four"]);
+        fs::remove_file(first.file.unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_navigation() {
+        let history = history(&["first", "second"]);
+        let mut navigator = HistoryNavigator::new(&history);
+        assert_eq!(navigator.next(), None);
+        assert_eq!(navigator.previous("typed"), Some("second"));
+        assert_eq!(navigator.previous("second"), Some("first"));
+        assert_eq!(navigator.previous("first"), None);
+        assert_eq!(navigator.next(), Some("second"));
+        assert_eq!(navigator.next(), Some("typed"));
+        assert_eq!(navigator.next(), None);
+    }
+
+    #[test]
+    fn test_navigation_empty_history() {
+        let history = History::default();
+        let mut navigator = HistoryNavigator::new(&history);
+        assert_eq!(navigator.previous("typed"), None);
+        assert_eq!(navigator.next(), None);
+    }
+}
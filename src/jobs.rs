@@ -0,0 +1,569 @@
+//! Job table module for the rust shell.
+//!
+//! This module keeps track of the jobs started in the background with `&`, and
+//! of foreground jobs stopped with Ctrl-Z: their processes, process group and
+//! command text. Their states are updated with `waitpid` on their own
+//! processes, without blocking before each prompt and when listing them, so
+//! that finished jobs can be reported as Done, or blocking when the shell waits
+//! for a job in the foreground or with `wait`. Waiting stops once the job has
+//! finished or stopped.
+//!
+//! Jobs are named by job specs: `%N` for job number N, `%%` or `%+` for the
+//! current job (the most recently started, stopped or resumed in the
+//! background), `%-` for the previous one, and `%name` for the job whose
+//! command starts with `name`.
+
+use crate::exec::{exit_code, finish_interrupted_line};
+use crate::terminal::{self, TerminalModes};
+use std::io;
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+
+/// The state of a job.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobState {
+    /// At least one of its processes is still running
+    Running,
+    /// Its unfinished processes are all stopped, by this signal
+    Stopped(libc::c_int),
+    /// All its processes have exited; the status of the last one
+    Done(ExitStatus),
+}
+
+impl JobState {
+    /// Returns the state as shown by `jobs`: Running, Stopped, Done, `Exit N`,
+    /// or the signal that killed the job.
+    fn describe(self) -> String {
+        let status = match self {
+            JobState::Running => return "Running".to_string(),
+            JobState::Stopped(libc::SIGSTOP) => return "Stopped (signal)".to_string(),
+            JobState::Stopped(libc::SIGTTIN) => return "Stopped (tty input)".to_string(),
+            JobState::Stopped(libc::SIGTTOU) => return "Stopped (tty output)".to_string(),
+            JobState::Stopped(_) => return "Stopped".to_string(),
+            JobState::Done(status) => status,
+        };
+        match (status.code(), status.signal()) {
+            (Some(0), _) => "Done".to_string(),
+            (Some(code), _) => format!("Exit {}", code),
+            (None, Some(libc::SIGINT)) => "Interrupt".to_string(),
+            (None, Some(libc::SIGKILL)) => "Killed".to_string(),
+            (None, Some(libc::SIGTERM)) => "Terminated".to_string(),
+            (None, Some(signal)) => format!("Signal {}", signal),
+            (None, None) => "Done".to_string(),
+        }
+    }
+}
+
+/// How long `Job::update` waits for the processes of a job.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Wait {
+    /// Not at all: only collects the changes that already happened
+    NoHang,
+    /// Until each process has exited or stopped
+    UntilStopped,
+    /// Until each process has exited, even if stopped in the meantime
+    UntilExited,
+}
+
+/// A process of a job, with its status once it has exited.
+#[derive(Debug)]
+struct Process {
+    /// The process id
+    pid: libc::pid_t,
+    /// The wait status, None while the process runs
+    status: Option<ExitStatus>,
+    /// The signal that stopped the process, None unless it is stopped
+    stopped: Option<libc::c_int>,
+}
+
+/// A pipeline or list started in the background.
+#[derive(Debug)]
+pub struct Job {
+    /// The job number, as in `%1`
+    pub id: usize,
+    /// The process group of the job's processes
+    pub pgid: libc::pid_t,
+    /// The processes of the job, in pipeline order
+    processes: Vec<Process>,
+    /// The command line of the job, as shown by `jobs`
+    pub command: String,
+    /// The terminal modes the job had when it was stopped in the foreground
+    modes: Option<TerminalModes>,
+}
+
+impl Job {
+    /// Returns the state of the job, as last updated.
+    pub fn state(&self) -> JobState {
+        let mut unfinished = self
+            .processes
+            .iter()
+            .filter(|process| process.status.is_none())
+            .peekable();
+        if unfinished.peek().is_some() {
+            return unfinished
+                .map(|process| process.stopped)
+                .collect::<Option<Vec<_>>>()
+                .map_or(JobState::Running, |signals| JobState::Stopped(signals[0]));
+        }
+        let last = self.processes.last().and_then(|process| process.status);
+        JobState::Done(last.unwrap_or_else(|| ExitStatus::from_raw(0)))
+    }
+
+    /// Returns the id of the last process of the job, whose status is the job's.
+    pub fn last_pid(&self) -> libc::pid_t {
+        self.processes.last().map_or(0, |process| process.pid)
+    }
+
+    /// Collects the status of the processes that have exited, stopped or
+    /// been continued, waiting for them as given by `wait`.
+    fn update(&mut self, wait: Wait) {
+        let options = libc::WUNTRACED
+            | libc::WCONTINUED
+            | if wait == Wait::NoHang {
+                libc::WNOHANG
+            } else {
+                0
+            };
+        for process in self.processes.iter_mut() {
+            while process.status.is_none()
+                && !(wait == Wait::UntilStopped && process.stopped.is_some())
+            {
+                let mut status = 0;
+                // SAFETY: waitpid only writes the status of the given child process.
+                match unsafe { libc::waitpid(process.pid, &mut status, options) } {
+                    0 => break, // No change since the last update
+                    -1 if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => {}
+                    // Not our child any more: nothing left to wait for
+                    -1 => process.status = Some(ExitStatus::from_raw(0)),
+                    _ if libc::WIFSTOPPED(status) => {
+                        process.stopped = Some(libc::WSTOPSIG(status));
+                        if wait == Wait::NoHang {
+                            break;
+                        }
+                    }
+                    _ if libc::WIFCONTINUED(status) => process.stopped = None,
+                    _ => process.status = Some(ExitStatus::from_raw(status)),
+                }
+            }
+        }
+    }
+
+    /// Continues the stopped processes of the job, with SIGCONT.
+    fn resume(&mut self) {
+        if !matches!(self.state(), JobState::Stopped(_)) {
+            return;
+        }
+        // SAFETY: plain libc calls signalling the job's own processes.
+        unsafe {
+            // Without job control the processes share the shell's process group
+            if self.pgid <= 0 || libc::kill(-self.pgid, libc::SIGCONT) != 0 {
+                for process in &self.processes {
+                    libc::kill(process.pid, libc::SIGCONT);
+                }
+            }
+        }
+        for process in &mut self.processes {
+            process.stopped = None;
+        }
+    }
+
+    /// Returns the exit status of the job once finished (see `exit_code`),
+    /// 128 + the signal that stopped it, or 0 while it runs.
+    fn status_code(&self) -> i32 {
+        match self.state() {
+            JobState::Done(status) => exit_code(status),
+            JobState::Stopped(signal) => 128 + signal,
+            JobState::Running => 0,
+        }
+    }
+}
+
+/// The jobs of the shell, by job number.
+#[derive(Debug, Default)]
+pub struct JobTable {
+    /// The jobs, the current one last: in the order they were started,
+    /// stopped or resumed in the background
+    jobs: Vec<Job>,
+    /// The pid of the last process of the most recent job, expanded as `$!`
+    pub last_pid: Option<libc::pid_t>,
+    /// Whether the last job waited for in the foreground was killed by
+    /// Ctrl-C, which abandons the rest of the command line
+    pub interrupted: bool,
+}
+
+impl JobTable {
+    /// Adds a job started in the background, numbered one more than the
+    /// highest job number in use. It becomes the current job, and its last
+    /// process is expanded as `$!`.
+    ///
+    /// # Arguments
+    ///
+    /// * `pgid` - The process group of the job
+    /// * `pids` - The processes of the job, the last one giving its status
+    /// * `command` - The command line of the job
+    ///
+    /// # Returns
+    ///
+    /// The new job
+    pub fn add(&mut self, pgid: libc::pid_t, pids: &[libc::pid_t], command: String) -> &Job {
+        self.last_pid = pids.last().copied();
+        self.insert(pgid, pids, command);
+        &self.jobs[self.jobs.len() - 1]
+    }
+
+    /// Adds a job as the current job, and returns its number.
+    fn insert(&mut self, pgid: libc::pid_t, pids: &[libc::pid_t], command: String) -> usize {
+        let id = self.jobs.iter().map(|job| job.id).max().unwrap_or(0) + 1;
+        let processes = pids
+            .iter()
+            .map(|&pid| Process {
+                pid,
+                status: None,
+                stopped: None,
+            })
+            .collect();
+        self.jobs.push(Job {
+            id,
+            pgid,
+            processes,
+            command,
+            modes: None,
+        });
+        id
+    }
+
+    /// Waits for a job just started in the foreground (see `foreground`). It
+    /// only stays in the table, as the current job, if it gets stopped.
+    ///
+    /// # Arguments
+    ///
+    /// * `pgid` - The process group of the job, which has the terminal
+    /// * `pids` - The processes of the job, the last one giving its status
+    /// * `command` - The command line of the job
+    ///
+    /// # Returns
+    ///
+    /// The exit status of the job, or 128 + the signal that stopped it
+    pub fn run_in_foreground(
+        &mut self,
+        pgid: libc::pid_t,
+        pids: &[libc::pid_t],
+        command: String,
+    ) -> i32 {
+        let id = self.insert(pgid, pids, command);
+        self.foreground(id)
+    }
+
+    /// Gives a job the terminal, continues it if it is stopped, and waits for
+    /// it to finish or stop before taking the terminal back. A finished job is
+    /// removed, and `interrupted` records if SIGINT killed it; a stopped one
+    /// becomes the current job and is reported, keeping its terminal modes for
+    /// when it is resumed.
+    ///
+    /// Returns the exit status of the job, or 128 + the signal that stopped it.
+    pub fn foreground(&mut self, id: usize) -> i32 {
+        let job = self.get_mut(id);
+        terminal::give_terminal(job.pgid, job.modes.as_ref());
+        job.resume();
+        // A subshell, without job control, is stopped along with its commands
+        job.update(if terminal::job_control_enabled() {
+            Wait::UntilStopped
+        } else {
+            Wait::UntilExited
+        });
+        if matches!(job.state(), JobState::Stopped(_)) {
+            job.modes = terminal::terminal_modes();
+        }
+        terminal::reclaim_terminal();
+        let state = job.state();
+        let status = self.finish(id);
+        self.interrupted = false;
+        match state {
+            JobState::Stopped(_) => eprintln!("\n{}", self.format(self.get(id))), // After the echoed ^Z
+            JobState::Done(status) => self.interrupted = finish_interrupted_line(status),
+            JobState::Running => {}
+        }
+        status
+    }
+
+    /// Continues a stopped job in the background, where it becomes the
+    /// current job.
+    ///
+    /// Returns its line as reported by `bg`, e.g. `[1]+ sleep 10 &`, or None
+    /// if the job isn't stopped.
+    pub fn resume_in_background(&mut self, id: usize) -> Option<String> {
+        let job = self.get_mut(id);
+        if !matches!(job.state(), JobState::Stopped(_)) {
+            return None;
+        }
+        job.resume();
+        self.make_current(id);
+        let job = self.get(id);
+        Some(format!("[{}]{} {} &", id, self.mark(id), job.command))
+    }
+
+    /// Waits for a job to finish or stop, removing it once finished.
+    ///
+    /// Returns the exit status of the job, or 128 + the signal that stopped it.
+    pub fn wait(&mut self, id: usize) -> i32 {
+        self.get_mut(id).update(Wait::UntilStopped);
+        self.finish(id)
+    }
+
+    /// Returns the status of a job after waiting for it. A stopped job becomes
+    /// the current job; a finished one is removed, as it needn't be reported
+    /// as Done.
+    fn finish(&mut self, id: usize) -> i32 {
+        let status = self.get(id).status_code();
+        match self.get(id).state() {
+            JobState::Stopped(_) => self.make_current(id),
+            _ => {
+                self.remove(id);
+            }
+        }
+        status
+    }
+
+    /// Returns a job of the table.
+    ///
+    /// # Panics
+    ///
+    /// If there is no job `id`; ids come from `find` or `ids`.
+    pub fn get(&self, id: usize) -> &Job {
+        self.jobs.iter().find(|job| job.id == id).unwrap()
+    }
+
+    /// Returns a job of the table, to update it (see `get`).
+    fn get_mut(&mut self, id: usize) -> &mut Job {
+        self.jobs.iter_mut().find(|job| job.id == id).unwrap()
+    }
+
+    /// Makes a job the current job.
+    fn make_current(&mut self, id: usize) {
+        let job = self.remove(id);
+        self.jobs.push(job);
+    }
+
+    /// Returns the numbers of all jobs, in order.
+    pub fn ids(&self) -> Vec<usize> {
+        let mut ids: Vec<usize> = self.jobs.iter().map(|job| job.id).collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    /// Updates the state of all jobs without blocking.
+    pub fn poll(&mut self) {
+        for job in &mut self.jobs {
+            job.update(Wait::NoHang);
+        }
+    }
+
+    /// Returns the `+` mark of the current job, `-` of the previous one, or a space.
+    fn mark(&self, id: usize) -> char {
+        let mut recent = self.jobs.iter().rev();
+        if recent.next().is_some_and(|job| job.id == id) {
+            '+'
+        } else if recent.next().is_some_and(|job| job.id == id) {
+            '-'
+        } else {
+            ' '
+        }
+    }
+
+    /// Formats a job as listed by `jobs`, e.g. `[1]+  Running                 sleep 10 &`.
+    fn format(&self, job: &Job) -> String {
+        let state = job.state();
+        let background = if state == JobState::Running { " &" } else { "" };
+        format!(
+            "[{}]{}  {:<24}{}{}",
+            job.id,
+            self.mark(job.id),
+            state.describe(),
+            job.command,
+            background
+        )
+    }
+
+    /// Lists the given jobs (all if `ids` is None) with their states as last
+    /// updated, and removes those that have finished: they are reported once.
+    pub fn list(&mut self, ids: Option<&[usize]>) -> Vec<String> {
+        let listed = |job: &Job| ids.map_or(true, |ids| ids.contains(&job.id));
+        let mut jobs: Vec<&Job> = self.jobs.iter().filter(|job| listed(job)).collect();
+        jobs.sort_unstable_by_key(|job| job.id);
+        let lines = jobs.into_iter().map(|job| self.format(job)).collect();
+        self.jobs
+            .retain(|job| !listed(job) || !matches!(job.state(), JobState::Done(_)));
+        lines
+    }
+
+    /// Removes the jobs that have finished, returning their lines as listed by
+    /// `jobs`, to notify the user before the next prompt.
+    pub fn take_finished(&mut self) -> Vec<String> {
+        let finished: Vec<usize> = self
+            .jobs
+            .iter()
+            .filter(|job| matches!(job.state(), JobState::Done(_)))
+            .map(|job| job.id)
+            .collect();
+        if finished.is_empty() {
+            return Vec::new();
+        }
+        self.list(Some(&finished))
+    }
+
+    /// Finds the job named by a job spec, or the current job if there is none.
+    /// Job numbers may be given without the `%`.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(id)` - The number of the job
+    /// * `Err(message)` - No job, or more than one, matches the spec
+    pub fn find(&self, spec: Option<&str>) -> Result<usize, String> {
+        let no_such_job = || format!("{}: no such job", spec.unwrap_or("current"));
+        let name = spec.map(|spec| spec.strip_prefix('%').unwrap_or(spec));
+        let job = match name {
+            None | Some("" | "%" | "+") => self.jobs.last(),
+            Some("-") => self.jobs.iter().rev().nth(1),
+            Some(name) if name.bytes().all(|b| b.is_ascii_digit()) => {
+                let id = name.parse::<usize>().map_err(|_| no_such_job())?;
+                self.jobs.iter().find(|job| job.id == id)
+            }
+            Some(name) => {
+                let mut matches = self.jobs.iter().filter(|job| job.command.starts_with(name));
+                let job = matches.next();
+                if matches.next().is_some() {
+                    return Err(format!("{}: ambiguous job spec", spec.unwrap_or(name)));
+                }
+                job
+            }
+        };
+        job.map(|job| job.id).ok_or_else(no_such_job)
+    }
+
+    /// Finds the job a process belongs to.
+    pub fn find_pid(&self, pid: libc::pid_t) -> Option<usize> {
+        self.jobs
+            .iter()
+            .find(|job| job.processes.iter().any(|process| process.pid == pid))
+            .map(|job| job.id)
+    }
+
+    /// Removes a job from the table (see `get`).
+    fn remove(&mut self, id: usize) -> Job {
+        let index = self.jobs.iter().position(|job| job.id == id).unwrap();
+        self.jobs.remove(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    /// Starts `sh -c script` and returns its pid, leaving it to the job table to reap.
+    #[allow(clippy::zombie_processes)]
+    fn spawn(script: &str) -> libc::pid_t {
+        let child = Command::new("sh").args(["-c", script]).spawn().unwrap();
+        child.id() as libc::pid_t
+    }
+
+    #[test]
+    fn test_add_and_find() {
+        let mut jobs = JobTable::default();
+        assert_eq!(jobs.find(None), Err("current: no such job".to_string()));
+        for command in ["sleep 10", "make all", "make test"] {
+            jobs.add(0, &[1], command.to_string());
+        }
+        assert_eq!(jobs.find(None), Ok(3));
+        assert_eq!(jobs.find(Some("%%")), Ok(3));
+        assert_eq!(jobs.find(Some("%-")), Ok(2));
+        assert_eq!(jobs.find(Some("%1")), Ok(1));
+        assert_eq!(jobs.find(Some("1")), Ok(1));
+        assert_eq!(jobs.find(Some("%sl")), Ok(1));
+        assert_eq!(jobs.find(Some("%4")), Err("%4: no such job".to_string()));
+        assert_eq!(
+            jobs.find(Some("%make")),
+            Err("%make: ambiguous job spec".to_string())
+        );
+
+        // Numbers continue from the highest one in use
+        jobs.remove(1);
+        assert_eq!(jobs.add(0, &[7, 8], "cat | wc".to_string()).id, 4);
+        assert_eq!(jobs.last_pid, Some(8));
+        assert_eq!(jobs.find_pid(7), Some(4));
+        assert_eq!(jobs.ids(), vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn test_format() {
+        let mut jobs = JobTable::default();
+        jobs.add(0, &[spawn("sleep 5")], "sleep 5".to_string());
+        jobs.add(0, &[spawn("exit 3")], "false".to_string());
+        assert_eq!(
+            jobs.format(jobs.get(1)),
+            "[1]-  Running                 sleep 5 &"
+        );
+        unsafe { libc::kill(jobs.get(1).last_pid(), libc::SIGTERM) };
+        assert_eq!(jobs.wait(1), 128 + libc::SIGTERM);
+
+        let id = jobs.find(None).unwrap();
+        let mut second = jobs.remove(id);
+        second.update(Wait::UntilExited);
+        assert_eq!(jobs.format(&second), "[2]   Exit 3                  false");
+    }
+
+    #[test]
+    fn test_finished_jobs_are_reported_once() {
+        let mut jobs = JobTable::default();
+        jobs.add(0, &[spawn("exit 0")], "true".to_string());
+        jobs.add(0, &[spawn("sleep 5")], "sleep 5".to_string());
+        let first = jobs.find(Some("%1")).unwrap();
+        let pid = jobs.jobs[0].last_pid();
+        jobs.jobs[0].update(Wait::UntilExited);
+        assert_eq!(jobs.find_pid(pid), Some(first));
+
+        jobs.poll();
+        assert_eq!(
+            jobs.take_finished(),
+            vec!["[1]-  Done                    true"]
+        );
+        assert!(jobs.take_finished().is_empty());
+        assert_eq!(
+            jobs.list(None),
+            vec!["[2]+  Running                 sleep 5 &"]
+        );
+
+        unsafe { libc::kill(jobs.get(2).last_pid(), libc::SIGKILL) };
+        assert_eq!(jobs.wait(2), 128 + libc::SIGKILL);
+        assert!(jobs.ids().is_empty());
+    }
+
+    #[test]
+    fn test_stopped_jobs() {
+        let mut jobs = JobTable::default();
+        let pid = spawn("sleep 5");
+        jobs.add(pid, &[pid], "sleep 5".to_string());
+        jobs.add(0, &[spawn("exit 0")], "true".to_string());
+        assert_eq!(jobs.resume_in_background(1), None);
+
+        // Waiting returns once the job stops, and keeps it as the current job
+        unsafe { libc::kill(pid, libc::SIGSTOP) };
+        assert_eq!(jobs.wait(1), 128 + libc::SIGSTOP);
+        assert_eq!(jobs.get(1).state(), JobState::Stopped(libc::SIGSTOP));
+        assert_eq!(jobs.find(None), Ok(1));
+        assert_eq!(
+            jobs.list(Some(&[1])),
+            vec!["[1]+  Stopped (signal)        sleep 5"]
+        );
+        assert!(jobs.take_finished().is_empty());
+
+        assert_eq!(
+            jobs.resume_in_background(1),
+            Some("[1]+ sleep 5 &".to_string())
+        );
+        assert_eq!(jobs.get(1).state(), JobState::Running);
+        unsafe { libc::kill(pid, libc::SIGTERM) };
+        assert_eq!(jobs.wait(1), 128 + libc::SIGTERM);
+        assert_eq!(jobs.ids(), vec![2]);
+    }
+}
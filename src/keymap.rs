@@ -0,0 +1,198 @@
+//! Key bindings module for the rust shell.
+//!
+//! This module maps key presses to the editing commands of the line editor.
+//! Commands are named after their readline counterparts, and the default
+//! bindings follow readline's emacs mode. In vi mode, this module only provides
+//! the bindings of insert mode; normal mode is handled by the `vi` module.
+
+use termion::event::Key;
+
+/// The keymap used by the line editor, selected with `set -o emacs` or `set -o vi`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EditingMode {
+    /// Readline's emacs bindings
+    #[default]
+    Emacs,
+    /// Vi bindings, with insert and normal modes
+    Vi,
+}
+
+impl EditingMode {
+    /// All editing modes, in the order `set -o` lists them.
+    pub const ALL: [EditingMode; 2] = [EditingMode::Emacs, EditingMode::Vi];
+
+    /// Returns the option name of the mode, as used by `set -o`.
+    pub fn name(self) -> &'static str {
+        match self {
+            EditingMode::Emacs => "emacs",
+            EditingMode::Vi => "vi",
+        }
+    }
+}
+
+/// An editing command of the line editor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditCommand {
+    /// Insert a character at the cursor
+    Insert(char),
+    /// Accept the line (Enter)
+    AcceptLine,
+    /// Complete the word before the cursor (Tab)
+    Complete,
+    /// Move one character left
+    BackwardChar,
+    /// Move one character right
+    ForwardChar,
+    /// Move to the start of the line
+    BeginningOfLine,
+    /// Move to the end of the line
+    EndOfLine,
+    /// Move to the start of the current or previous word
+    BackwardWord,
+    /// Move to the end of the current or next word
+    ForwardWord,
+    /// Delete the character before the cursor
+    BackwardDeleteChar,
+    /// Delete the character under the cursor
+    DeleteChar,
+    /// Delete the character under the cursor, or end the input if the line is empty
+    DeleteCharOrEof,
+    /// Abandon the line being edited
+    Interrupt,
+    /// Kill from the cursor to the end of the line
+    KillLine,
+    /// Kill from the start of the line to the cursor
+    UnixLineDiscard,
+    /// Kill the whitespace-delimited word before the cursor
+    UnixWordRubout,
+    /// Kill from the cursor to the end of the word
+    KillWord,
+    /// Kill from the start of the word to the cursor
+    BackwardKillWord,
+    /// Insert the most recently killed text
+    Yank,
+    /// Replace the text just yanked with the previous kill ring entry
+    YankPop,
+    /// Swap the characters around the cursor
+    TransposeChars,
+    /// Clear the screen and redraw the line at the top
+    ClearScreen,
+    /// Recall the previous history entry
+    PreviousHistory,
+    /// Recall the next history entry
+    NextHistory,
+    /// Start an incremental search backwards through the history
+    ReverseSearchHistory,
+}
+
+/// Returns the command bound to `key` in emacs mode, or None if it is unbound.
+pub fn emacs_command(key: Key) -> Option<EditCommand> {
+    use EditCommand::*;
+
+    let command = match key {
+        Key::Char('\n') => AcceptLine,
+        Key::Char('\t') => Complete,
+        Key::Char(c) => Insert(c),
+        Key::Left | Key::Ctrl('b') => BackwardChar,
+        Key::Right | Key::Ctrl('f') => ForwardChar,
+        Key::Home | Key::Ctrl('a') => BeginningOfLine,
+        Key::End | Key::Ctrl('e') => EndOfLine,
+        Key::Alt('b') => BackwardWord,
+        Key::Alt('f') => ForwardWord,
+        Key::Backspace | Key::Ctrl('h') => BackwardDeleteChar,
+        Key::Delete => DeleteChar,
+        Key::Ctrl('d') => DeleteCharOrEof,
+        Key::Ctrl('c') => Interrupt,
+        Key::Ctrl('k') => KillLine,
+        Key::Ctrl('u') => UnixLineDiscard,
+        Key::Ctrl('w') => UnixWordRubout,
+        Key::Alt('d') => KillWord,
+        Key::Alt('\x7f') => BackwardKillWord,
+        Key::Ctrl('y') => Yank,
+        Key::Alt('y') => YankPop,
+        Key::Ctrl('t') => TransposeChars,
+        Key::Ctrl('l') => ClearScreen,
+        Key::Up | Key::Ctrl('p') => PreviousHistory,
+        Key::Down | Key::Ctrl('n') => NextHistory,
+        Key::Ctrl('r') => ReverseSearchHistory,
+        _ => return None,
+    };
+    Some(command)
+}
+
+/// Returns the command bound to `key` in vi insert mode, or None if it is
+/// unbound. Esc, which switches to normal mode, is handled by the vi keymap.
+pub fn vi_insert_command(key: Key) -> Option<EditCommand> {
+    use EditCommand::*;
+
+    let command = match key {
+        Key::Char('\n') => AcceptLine,
+        Key::Char('\t') => Complete,
+        Key::Char(c) => Insert(c),
+        Key::Left => BackwardChar,
+        Key::Right => ForwardChar,
+        Key::Home => BeginningOfLine,
+        Key::End => EndOfLine,
+        Key::Backspace | Key::Ctrl('h') => BackwardDeleteChar,
+        Key::Delete => DeleteChar,
+        Key::Ctrl('d') => DeleteCharOrEof,
+        Key::Ctrl('c') => Interrupt,
+        Key::Ctrl('u') => UnixLineDiscard,
+        Key::Ctrl('w') => UnixWordRubout,
+        Key::Ctrl('l') => ClearScreen,
+        Key::Up => PreviousHistory,
+        Key::Down => NextHistory,
+        Key::Ctrl('r') => ReverseSearchHistory,
+        _ => return None,
+    };
+    Some(command)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_emacs_bindings() {
+        assert_eq!(
+            emacs_command(Key::Char('a')),
+            Some(EditCommand::Insert('a'))
+        );
+        assert_eq!(
+            emacs_command(Key::Char('\n')),
+            Some(EditCommand::AcceptLine)
+        );
+        assert_eq!(
+            emacs_command(Key::Ctrl('a')),
+            Some(EditCommand::BeginningOfLine)
+        );
+        assert_eq!(emacs_command(Key::Home), Some(EditCommand::BeginningOfLine));
+        assert_eq!(emacs_command(Key::Alt('f')), Some(EditCommand::ForwardWord));
+        assert_eq!(emacs_command(Key::Ctrl('y')), Some(EditCommand::Yank));
+        assert_eq!(emacs_command(Key::Ctrl('c')), Some(EditCommand::Interrupt));
+        assert_eq!(
+            emacs_command(Key::Ctrl('d')),
+            Some(EditCommand::DeleteCharOrEof)
+        );
+    }
+
+    #[test]
+    fn test_vi_insert_bindings() {
+        assert_eq!(
+            vi_insert_command(Key::Char('x')),
+            Some(EditCommand::Insert('x'))
+        );
+        assert_eq!(
+            vi_insert_command(Key::Ctrl('w')),
+            Some(EditCommand::UnixWordRubout)
+        );
+        assert_eq!(vi_insert_command(Key::Ctrl('a')), None);
+        assert_eq!(vi_insert_command(Key::Esc), None);
+    }
+
+    #[test]
+    fn test_unbound_keys() {
+        assert_eq!(emacs_command(Key::Ctrl('q')), None);
+        assert_eq!(emacs_command(Key::F(1)), None);
+    }
+}
@@ -1,235 +1,946 @@
-#![allow(clippy::comparison_to_empty)] // Allow Err("") for external command failure status
-
 mod builtins;
+mod completion;
+mod editor;
 mod exec;
+mod expand;
+mod history;
+mod jobs;
+mod keymap;
 mod parser;
 mod redirect;
-mod utils;
-
-use std::fs::{File, OpenOptions};
-use std::io::{stdin, stdout, Write};
-use termion::event::Key;
-use termion::input::TermRead;
-use termion::raw::IntoRawMode;
-
-use builtins::{handle_cd, handle_echo, handle_exit, handle_pwd, handle_type};
-use exec::{execute_external_command, find_exec_in_path};
-use parser::parse_tokens;
-use redirect::{parse_redirections, RedirectionMode, Redirections};
-
-// Convention: Result<Option<String>, String>
-// Ok(Some(output)): Success, print output (unless redirected)
-// Ok(None):          Success, no output to print (cd, redirected external)
-// Err(message):      Failure (built-in/shell), print message to stderr (unless redirected)
-// Err(""):           Failure (external non-zero exit), shell prints nothing further.
-
-/// Dispatches the command to the appropriate handler (built-in or external).
-fn dispatch_command(
+mod shell;
+mod terminal;
+mod variables;
+mod vi;
+
+use std::io::{self, stdin, Read, Write};
+use std::os::fd::RawFd;
+use std::process::ChildStdout;
+
+use builtins::{
+    handle_bg, handle_cd, handle_complete, handle_echo, handle_env, handle_exit, handle_export,
+    handle_fg, handle_history, handle_jobs, handle_pwd, handle_set, handle_type, handle_unset,
+    handle_wait, runs_as_builtin,
+};
+use editor::{LineEditor, ReadResult};
+use exec::{
+    find_exec_in_path, fork_shell, redirect_shell, spawn_external_command, wait_for_exit, Fork,
+    PipeInput, StageIo,
+};
+use expand::{
+    expand_assignment, expand_here_string, expand_heredoc, expand_redirect_target, expand_words,
+    split_assignments,
+};
+use history::History;
+use parser::{parse_command_line, pipeline_text, Command, Node};
+use redirect::{
+    error_reason, open_redirections, parse_redirections, read_heredocs, resolve_fds, FdSource,
+    FdTarget, InputSource, OpenRedirection, RedirectAction, RedirectFile,
+};
+use shell::Shell;
+use variables::SavedVariables;
+
+// Convention for built-ins: Result<Option<String>, String>
+// Ok(Some(output)): Success, print output (unless redirected), status 0
+// Ok(None):          Success, no output to print (cd), status 0
+// Err(message):      Failure (built-in/shell), print message to stderr (unless redirected), status 1
+//
+// External commands report the status from `wait()`: the exit code, or 128 + the
+// signal number if killed by a signal. A command that is not found has status 127.
+
+/// Exit status of a command that could not be found.
+const STATUS_NOT_FOUND: i32 = 127;
+/// Exit status of a line that could not be parsed.
+const STATUS_SYNTAX_ERROR: i32 = 2;
+/// Exit status after a line is abandoned with Ctrl-C (128 + SIGINT).
+const STATUS_INTERRUPTED: i32 = 130;
+
+/// Runs a built-in command, or returns None if `command_name` is not a built-in.
+/// `env` with arguments is left to the external command, which runs them.
+fn dispatch_builtin(
+    shell: &mut Shell,
     command_name: &str,
     command_args: &[String],
-    redirections: &Redirections,
-) -> Result<Option<String>, String> {
+) -> Option<Result<Option<String>, String>> {
     match command_name {
-        // --- Built-in Commands ---
-        "exit" => handle_exit(command_args),
-        "echo" => handle_echo(command_args),
-        "pwd" => handle_pwd(command_args),
-        "cd" => handle_cd(command_args),
-        "type" => handle_type(command_args),
-        // --- External Command ---
-        cmd => match find_exec_in_path(cmd) {
-            Some(full_path) => {
-                execute_external_command(cmd, &full_path, command_args, redirections)
-            }
-            None => Err(format!("{}: command not found", cmd)),
-        },
+        "exit" => {
+            save_history(shell);
+            handle_exit(command_args, shell.last_status)
+        }
+        "echo" => Some(handle_echo(command_args)),
+        "pwd" => Some(handle_pwd(command_args)),
+        "cd" => Some(handle_cd(command_args)),
+        "type" => Some(handle_type(
+            command_args,
+            shell.vars.get("PATH"),
+            &shell.functions,
+        )),
+        "export" => Some(handle_export(&mut shell.vars, command_args)),
+        "unset" => Some(handle_unset(&mut shell.vars, command_args)),
+        "env" if command_args.is_empty() => Some(handle_env(&shell.vars)),
+        "set" => Some(handle_set(
+            &shell.vars,
+            &mut shell.editing_mode,
+            command_args,
+        )),
+        "history" => Some(handle_history(&shell.history, command_args)),
+        "complete" => Some(handle_complete(&mut shell.completions, command_args)),
+        "jobs" => Some(handle_jobs(&mut shell.jobs, command_args)),
+        "bg" => Some(handle_bg(&mut shell.jobs, command_args)),
+        _ => None,
     }
 }
 
-/// Creates a file with the appropriate mode (overwrite/append) for redirection.
-fn create_redirect_file(filename: &str, mode: RedirectionMode) -> Result<File, std::io::Error> {
-    // Check if the target is an existing directory
-    if let Ok(metadata) = std::fs::metadata(filename) {
-        if metadata.is_dir() {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("'{}' is a directory", filename),
-            ));
+/// Runs a built-in command and handles its output (see `handle_command_result`),
+/// or returns None if `command_name` is not a built-in.
+///
+/// Returns the exit status of the built-in; for `fg` and `wait`, that of the
+/// job they waited for.
+fn run_builtin(
+    shell: &mut Shell,
+    command_name: &str,
+    command_args: &[String],
+    redirections: &[OpenRedirection],
+) -> Option<i32> {
+    let waited = match command_name {
+        "fg" => handle_fg(&mut shell.jobs, command_args),
+        "wait" => handle_wait(&mut shell.jobs, command_args),
+        _ => {
+            let result = dispatch_builtin(shell, command_name, command_args)?;
+            return Some(handle_command_result(result, redirections));
         }
-    }
+    };
+    Some(match waited {
+        Ok(status) => status,
+        Err(e) => handle_command_result(Err(e), redirections),
+    })
+}
 
-    OpenOptions::new()
-        .create(true)
-        .write(true)
-        .truncate(mode == RedirectionMode::Overwrite)
-        .append(mode == RedirectionMode::Append)
-        .open(filename)
+/// Runs a shell function or a built-in command, or returns None if
+/// `command_name` is neither. A function takes precedence over a built-in.
+fn run_shell_command(
+    shell: &mut Shell,
+    command_name: &str,
+    command_args: &[String],
+    redirections: &[OpenRedirection],
+) -> Option<i32> {
+    match shell.functions.get(command_name) {
+        Some(body) => {
+            let body = body.clone();
+            Some(call_function(shell, body, command_args, redirections))
+        }
+        None => run_builtin(shell, command_name, command_args, redirections),
+    }
 }
 
-/// Ensures a redirect file exists, ignoring errors (used for "touch" behavior).
-fn ensure_redirect_file_exists(filename: &str, mode: RedirectionMode) {
-    let _ = create_redirect_file(filename, mode);
+/// Calls a shell function: runs its body with the arguments as the positional
+/// parameters `$1`, `$2`, ..., and with the redirections of the call applied
+/// to the shell's own descriptors, as for a brace group.
+///
+/// Returns the status of the body, or 1 if a redirection fails.
+fn call_function(
+    shell: &mut Shell,
+    body: Command,
+    command_args: &[String],
+    redirections: &[OpenRedirection],
+) -> i32 {
+    let saved = match redirect_shell(redirections) {
+        Ok(saved) => saved,
+        Err(e) => return handle_command_result(Err(format!("shell: {}", e)), &[]),
+    };
+    let positional = std::mem::replace(&mut shell.positional, command_args.to_vec());
+    let status = execute_node(shell, &Node::Pipeline(vec![body]));
+    shell.positional = positional;
+    saved.restore();
+    status
 }
 
-/// Writes output to stdout, either to a redirect file or terminal.
-fn write_stdout(output: &str, redirections: &Redirections) {
-    if let Some(stdout_redirect) = &redirections.stdout_redirect {
-        match create_redirect_file(&stdout_redirect.filename, stdout_redirect.mode) {
-            Ok(mut file) => {
-                if let Err(e) = file.write_all(output.as_bytes()) {
-                    raw_eprintln!(
-                        "shell: error writing built-in stdout to '{}': {}",
-                        &stdout_redirect.filename,
-                        e
-                    );
+/// Dispatches the command to the appropriate handler (function, built-in or external),
+/// handles its output and returns its exit status. An external command runs
+/// as a foreground job, which becomes a stopped job named `command` on Ctrl-Z.
+fn dispatch_command(
+    shell: &mut Shell,
+    command_name: &str,
+    command_args: &[String],
+    redirections: &[OpenRedirection],
+    command: &str,
+) -> i32 {
+    // --- Functions and Built-in Commands ---
+    if let Some(status) = run_shell_command(shell, command_name, command_args, redirections) {
+        return status;
+    }
+    // --- External Command ---
+    match find_exec_in_path(command_name, shell.vars.get("PATH")) {
+        Some(full_path) => {
+            let env = shell.vars.exported();
+            match spawn_external_command(
+                command_name,
+                &full_path,
+                command_args,
+                redirections,
+                &env,
+                StageIo::standalone(),
+            ) {
+                Ok(child) => {
+                    let pid = child.id() as libc::pid_t;
+                    shell
+                        .jobs
+                        .run_in_foreground(pid, &[pid], command.to_string())
                 }
+                Err(e) => handle_command_result(Err(e), redirections),
             }
-            Err(e) => raw_eprintln!(
-                "shell: failed to open stdout redirect file '{}': {}",
-                &stdout_redirect.filename,
-                e
-            ),
         }
+        None => {
+            let not_found = Err(format!("{}: command not found", command_name));
+            handle_command_result(not_found, redirections);
+            STATUS_NOT_FOUND
+        }
+    }
+}
+
+/// Expands the raw words of a command into its name and arguments, and
+/// separates out its redirections with their targets expanded and opened, in
+/// order, before the command is looked up.
+fn expand_command(
+    shell: &mut Shell,
+    raw_words: &[String],
+) -> Result<(Vec<String>, Vec<OpenRedirection>), String> {
+    let (raw_args, mut redirections) = parse_redirections(raw_words);
+    let words = expand_words(&raw_args, shell)?;
+    for redirection in &mut redirections {
+        match &mut redirection.action {
+            RedirectAction::Output(RedirectFile { filename, .. })
+            | RedirectAction::Input(InputSource::File(filename))
+            | RedirectAction::Duplicate(filename) => {
+                *filename = expand_redirect_target(filename, shell)?;
+            }
+            RedirectAction::Input(InputSource::HereDoc { body, expand }) if *expand => {
+                *body = expand_heredoc(body, shell)?;
+                *expand = false;
+            }
+            RedirectAction::Input(InputSource::HereDoc { .. }) => {}
+            RedirectAction::Input(InputSource::HereString(word)) => {
+                *word = expand_here_string(word, shell)?;
+            }
+        }
+    }
+    Ok((words, open_redirections(&redirections)?))
+}
+
+/// Expands and performs the `NAME=value` assignments preceding a command, in order.
+/// With `saved` they are temporary (exported for the command only), otherwise they
+/// set shell variables.
+fn perform_assignments(
+    shell: &mut Shell,
+    assignments: &[String],
+    mut saved: Option<&mut SavedVariables>,
+) -> Result<(), String> {
+    for assignment in assignments {
+        let (name, value) = expand_assignment(assignment, shell)?;
+        match saved.as_deref_mut() {
+            Some(saved) => shell.vars.set_temporarily(&name, &value, saved),
+            None => shell.vars.set(&name, &value),
+        }
+    }
+    Ok(())
+}
+
+/// Expands and runs a single command given as raw words, returning its exit status.
+/// A command that expands to no words only performs its assignments and redirections,
+/// and has the status of the last command substitution it ran, or 0.
+/// The command line is named `command` if it gets stopped.
+fn execute_simple_command(shell: &mut Shell, raw_words: &[String], command: &str) -> i32 {
+    let (assignments, raw_words) = split_assignments(raw_words);
+    let mut saved = SavedVariables::default();
+    shell.substitution_status = None;
+    let expanded = expand_command(shell, raw_words).and_then(|(words, redirections)| {
+        let temporary = (!words.is_empty()).then_some(&mut saved);
+        perform_assignments(shell, assignments, temporary)?;
+        Ok((words, redirections))
+    });
+    let status = match expanded {
+        Ok((words, redirections)) => match words.split_first() {
+            Some((command_name, command_args)) => {
+                dispatch_command(shell, command_name, command_args, &redirections, command)
+            }
+            None => shell.substitution_status.unwrap_or(0),
+        },
+        Err(e) => {
+            let expansion_error = Err(format!("shell: {}", e));
+            handle_command_result(expansion_error, &[])
+        }
+    };
+    shell.vars.restore(saved);
+    status
+}
+
+/// How a pipeline stage ended up after being started.
+enum StageStart {
+    /// The stage didn't start (no command, or an error) and has this status
+    Finished(i32),
+    /// The stage is running as this process: an external command or a subshell
+    Spawned(libc::pid_t),
+}
+
+/// Expands and starts one pipeline stage, connected as given by `io`. An
+/// external command is spawned; a function, a built-in or a compound command
+/// is forked into a subshell, so that it can't change the shell's state (`cd`,
+/// `exit`).
+///
+/// Assignments before the command are exported to it only.
+fn start_pipeline_stage(
+    shell: &mut Shell,
+    command: &Command,
+    io: StageIo,
+    next_input: &mut PipeInput,
+) -> StageStart {
+    let raw_words = match command {
+        Command::Simple(raw_words) => raw_words,
+        Command::Subshell(body, raw_redirections) | Command::Group(body, raw_redirections) => {
+            let spawned = spawn_subshell(shell, body, raw_redirections, io);
+            return stage_started(spawned, &[], next_input);
+        }
+        Command::Function(..) => {
+            let definition = Node::Pipeline(vec![command.clone()]);
+            let spawned = spawn_subshell(shell, &definition, &[], io);
+            return stage_started(spawned, &[], next_input);
+        }
+    };
+    let (assignments, raw_words) = split_assignments(raw_words);
+    let mut saved = SavedVariables::default();
+    shell.substitution_status = None;
+    let expanded = expand_command(shell, raw_words).and_then(|expanded| {
+        perform_assignments(shell, assignments, Some(&mut saved))?;
+        Ok(expanded)
+    });
+    let start = match expanded {
+        Ok((words, redirections)) => {
+            start_stage_command(shell, &words, &redirections, io, next_input)
+        }
+        Err(e) => {
+            let expansion_error = Err(format!("shell: {}", e));
+            let status = handle_command_result(expansion_error, &[]);
+            StageStart::Finished(status)
+        }
+    };
+    shell.vars.restore(saved);
+    start
+}
+
+/// Starts the expanded command of a pipeline stage (see `start_pipeline_stage`).
+fn start_stage_command(
+    shell: &mut Shell,
+    words: &[String],
+    redirections: &[OpenRedirection],
+    io: StageIo,
+    next_input: &mut PipeInput,
+) -> StageStart {
+    let Some((command_name, command_args)) = words.split_first() else {
+        return StageStart::Finished(shell.substitution_status.unwrap_or(0));
+    };
+    let spawned = if shell.functions.contains_key(command_name)
+        || runs_as_builtin(command_name, command_args)
+    {
+        spawn_shell_command(shell, command_name, command_args, redirections, io)
+    } else if let Some(full_path) = find_exec_in_path(command_name, shell.vars.get("PATH")) {
+        let env = shell.vars.exported();
+        spawn_external_command(
+            command_name,
+            &full_path,
+            command_args,
+            redirections,
+            &env,
+            io,
+        )
+        .map(|mut child| (child.id() as libc::pid_t, child.stdout.take()))
     } else {
-        raw_print!("{}", output);
+        let not_found = Err(format!("{}: command not found", command_name));
+        handle_command_result(not_found, redirections);
+        return StageStart::Finished(STATUS_NOT_FOUND);
+    };
+    stage_started(spawned, redirections, next_input)
+}
+
+/// Returns how a stage started as a process ended up, storing the read end of
+/// its stdout in `next_input` for the following stage. A stage that failed to
+/// start has its error reported where its `redirections` point stderr.
+fn stage_started(
+    spawned: Result<(libc::pid_t, Option<ChildStdout>), String>,
+    redirections: &[OpenRedirection],
+    next_input: &mut PipeInput,
+) -> StageStart {
+    match spawned {
+        Ok((pid, stdout)) => {
+            if let Some(stdout) = stdout {
+                *next_input = PipeInput::Child(stdout);
+            }
+            StageStart::Spawned(pid)
+        }
+        Err(e) => StageStart::Finished(handle_command_result(Err(e), redirections)),
     }
 }
 
-/// Writes error message to stderr, either to a redirect file or terminal.
-fn write_stderr(error_msg: &str, redirections: &Redirections) {
-    if let Some(stderr_redirect) = &redirections.stderr_redirect {
-        match File::create(&stderr_redirect.filename) {
-            Ok(mut file) => {
-                if let Err(e) = writeln!(file, "{}", error_msg) {
-                    raw_eprintln!(
-                        "shell: error writing error to stderr redirect file '{}': {}",
-                        &stderr_redirect.filename,
-                        e
-                    );
+/// Runs a function or built-in pipeline stage in a subshell: a forked copy of
+/// the shell, connected as given by `io`, which exits with the command's status.
+///
+/// # Returns
+///
+/// * `Ok((pid, stdout))` - The process id of the subshell, and the read end of
+///   its stdout when piped
+/// * `Err(message)` - The subshell couldn't be started
+fn spawn_shell_command(
+    shell: &mut Shell,
+    command_name: &str,
+    command_args: &[String],
+    redirections: &[OpenRedirection],
+    io: StageIo,
+) -> Result<(libc::pid_t, Option<ChildStdout>), String> {
+    match fork_shell(io)? {
+        Fork::Child => {
+            shell.enter_subshell();
+            let status = run_shell_command(shell, command_name, command_args, redirections)
+                .unwrap_or(STATUS_NOT_FOUND);
+            exit_subshell(shell, status)
+        }
+        Fork::Parent(pid, stdout) => Ok((pid, stdout)),
+    }
+}
+
+/// Exits a subshell with `status`, or by SIGINT if Ctrl-C killed the last job
+/// it waited for, so that the parent shell abandons its line too.
+fn exit_subshell(shell: &Shell, status: i32) -> ! {
+    if shell.jobs.interrupted {
+        let _ = io::stdout().flush();
+        terminal::raise_interrupt();
+    }
+    std::process::exit(status); // Flushes stdout
+}
+
+/// A pipeline whose stages have all been started.
+struct StartedPipeline {
+    /// The processes of the stages, in order
+    pids: Vec<libc::pid_t>,
+    /// The status of the last stage, if it didn't start
+    last_status: Option<i32>,
+    /// The process group of the pipeline, led by its first stage
+    pgid: libc::pid_t,
+}
+
+/// Returns the input of a job: the shell's own, except that without job
+/// control a background job reads nothing.
+fn job_input(foreground: bool) -> PipeInput {
+    if foreground || terminal::job_control_enabled() {
+        PipeInput::Inherit
+    } else {
+        PipeInput::Null
+    }
+}
+
+/// Starts all the stages of a pipeline, connecting each stage's stdout to the
+/// next stage's stdin. The stages are started in the pipeline's process group,
+/// with the terminal if it runs in the `foreground`.
+fn start_pipeline(shell: &mut Shell, stages: &[Command], foreground: bool) -> StartedPipeline {
+    let mut started = StartedPipeline {
+        pids: Vec::new(),
+        last_status: None,
+        pgid: 0,
+    };
+    let mut input = job_input(foreground);
+
+    for (index, stage) in stages.iter().enumerate() {
+        let is_last = index + 1 == stages.len();
+        // The next stage reads nothing unless this stage produces output for it
+        let stage_input = std::mem::replace(&mut input, PipeInput::Null);
+        let io = StageIo {
+            stdin: stage_input,
+            pipe_stdout: !is_last,
+            pgid: started.pgid,
+            foreground,
+        };
+
+        match start_pipeline_stage(shell, stage, io, &mut input) {
+            StageStart::Finished(status) => {
+                if is_last {
+                    started.last_status = Some(status);
                 }
             }
-            Err(e) => {
-                raw_eprintln!(
-                    "shell: failed to open stderr redirect file '{}': {}",
-                    &stderr_redirect.filename,
-                    e
-                );
-                raw_eprintln!("{}", error_msg);
+            StageStart::Spawned(pid) => {
+                if started.pgid == 0 {
+                    started.pgid = pid;
+                }
+                started.pids.push(pid); // Status is known once waited on
             }
         }
-    } else {
-        raw_eprintln!("{}", error_msg);
     }
+    started
 }
 
-/// Ensures both stdout and stderr redirect files exist if specified.
-fn ensure_redirect_files_exist(redirections: &Redirections) {
-    if let Some(stdout_redirect) = &redirections.stdout_redirect {
-        ensure_redirect_file_exists(&stdout_redirect.filename, stdout_redirect.mode);
+/// Executes a pipeline in the foreground: all stages run concurrently,
+/// and are waited for as a job, which becomes a stopped job named `command` on
+/// Ctrl-Z.
+///
+/// Returns the exit status of the last stage, which is the status of the pipeline.
+fn execute_pipeline(shell: &mut Shell, stages: &[Command], command: &str) -> i32 {
+    let started = start_pipeline(shell, stages, true);
+    if started.pids.is_empty() {
+        return started.last_status.unwrap(); // The last stage always produces a status
     }
-    if let Some(stderr_redirect) = &redirections.stderr_redirect {
-        ensure_redirect_file_exists(&stderr_redirect.filename, stderr_redirect.mode);
+    let status = shell
+        .jobs
+        .run_in_foreground(started.pgid, &started.pids, command.to_string());
+    // A last stage that didn't start has already given the status of the pipeline
+    started.last_status.unwrap_or(status)
+}
+
+/// Starts `body` in a subshell: a forked copy of the shell, connected as given
+/// by `io`, which runs it with its redirections like a brace group (see
+/// `execute_group`) and exits with its status.
+///
+/// # Returns
+///
+/// * `Ok((pid, stdout))` - The process id of the subshell, and the read end of
+///   its stdout when piped
+/// * `Err(message)` - The subshell couldn't be started
+fn spawn_subshell(
+    shell: &mut Shell,
+    body: &Node,
+    raw_redirections: &[String],
+    io: StageIo,
+) -> Result<(libc::pid_t, Option<ChildStdout>), String> {
+    match fork_shell(io)? {
+        Fork::Child => {
+            shell.enter_subshell();
+            let status = execute_group(shell, body, raw_redirections);
+            exit_subshell(shell, status)
+        }
+        Fork::Parent(pid, stdout) => Ok((pid, stdout)),
     }
 }
 
-/// Handles the result from dispatch_command, printing output/errors appropriately
-/// respecting redirection settings.
-fn handle_command_result(result: Result<Option<String>, String>, redirections: &Redirections) {
-    match result {
-        Ok(Some(output_str)) => {
-            // Success with output (built-in, or external without '>')
-            write_stdout(&output_str, redirections);
-            // Ensure stderr file exists if 2> also used
-            if let Some(stderr_redirect) = &redirections.stderr_redirect {
-                ensure_redirect_file_exists(&stderr_redirect.filename, stderr_redirect.mode);
+/// Runs the command line of a `$(...)` or `` `...` `` substitution in a
+/// subshell, and returns what it writes to stdout. The subshell is part of the
+/// command being expanded: it stays in the shell's process group, so Ctrl-C
+/// interrupts it, and it ignores Ctrl-Z.
+///
+/// # Returns
+///
+/// * `Ok(output)` - The output of the command line, whatever its status, which
+///   is kept in `substitution_status`
+/// * `Err(message)` - The command line can't be parsed, or the subshell started
+fn run_command_substitution(shell: &mut Shell, command: &str) -> Result<String, String> {
+    let node = match parse_command_line(command) {
+        Ok(Some(node)) => node,
+        Ok(None) => return Ok(String::new()),
+        Err(e) => return Err(format!("command substitution: {}", e)),
+    };
+    let io = StageIo {
+        stdin: PipeInput::Inherit,
+        pipe_stdout: true,
+        pgid: terminal::shell_pgid(),
+        foreground: false,
+    };
+    match fork_shell(io)? {
+        Fork::Child => {
+            terminal::ignore_stop_signal();
+            shell.enter_subshell();
+            let status = execute_node(shell, &node);
+            exit_subshell(shell, status)
+        }
+        Fork::Parent(pid, stdout) => {
+            let mut output = Vec::new();
+            if let Some(mut stdout) = stdout {
+                let _ = stdout.read_to_end(&mut output);
             }
+            shell.substitution_status = Some(wait_for_exit(pid));
+            Ok(String::from_utf8_lossy(&output).into_owned())
         }
-        Ok(None) => {
-            // Success, no direct output string (cd, external with '>', external no output)
-            ensure_redirect_files_exist(redirections);
+    }
+}
+
+/// Runs the list of a brace group in the shell itself, with its redirections
+/// applied to the shell's own descriptors while the list runs, so that they
+/// apply to all of its commands.
+///
+/// Returns the status of the list, or 1 if a redirection fails.
+fn execute_group(shell: &mut Shell, body: &Node, raw_redirections: &[String]) -> i32 {
+    if raw_redirections.is_empty() {
+        return execute_node(shell, body);
+    }
+    let redirected = expand_command(shell, raw_redirections)
+        .and_then(|(_, redirections)| redirect_shell(&redirections));
+    let saved = match redirected {
+        Ok(saved) => saved,
+        Err(e) => return handle_command_result(Err(format!("shell: {}", e)), &[]),
+    };
+    let status = execute_node(shell, body);
+    saved.restore();
+    status
+}
+
+/// Starts a command as a background job and adds it to the job table. A
+/// pipeline is started directly; a list runs in a subshell. With job control,
+/// the job number and process id are reported, as `[1] 12345`.
+///
+/// Returns 0, or 1 if the subshell can't be started.
+fn start_background_job(shell: &mut Shell, node: &Node) -> i32 {
+    let (pgid, pids) = match node {
+        Node::Pipeline(stages) => {
+            let started = start_pipeline(shell, stages, false);
+            (started.pgid, started.pids)
         }
-        Err(err_msg) => {
-            // Command failed
-            if !err_msg.is_empty() {
-                // Built-in or shell error (e.g., "not found", "cd failed")
-                write_stderr(&err_msg, redirections);
-                // Ensure stdout file exists if > was used with a failed built-in/shell command
-                if let Some(stdout_redirect) = &redirections.stdout_redirect {
-                    ensure_redirect_file_exists(&stdout_redirect.filename, stdout_redirect.mode);
+        _ => {
+            let io = StageIo {
+                stdin: job_input(false),
+                pipe_stdout: false,
+                pgid: 0,
+                foreground: false,
+            };
+            match spawn_subshell(shell, node, &[], io) {
+                Ok((pid, _)) => (pid, vec![pid]),
+                Err(e) => {
+                    eprintln!("shell: {}", e);
+                    return 1;
                 }
             }
-            // else: err_msg is empty, indicating external command failed (non-zero exit).
-            // Stderr/stdout were already handled by execute_external_command. Do nothing here.
+        }
+    };
+    if pids.is_empty() {
+        return 0; // No stage could be started
+    }
+    let job = shell.jobs.add(pgid, &pids, node.to_string());
+    if terminal::job_control_enabled() {
+        eprintln!("[{}] {}", job.id, job.last_pid());
+    }
+    0
+}
+
+/// Executes a parsed command line, setting `$?` after each pipeline, and
+/// returns the status of the last pipeline run.
+///
+/// A pipeline killed by Ctrl-C abandons the rest of the line, as if the shell
+/// was interrupted too; a command merely exiting with status 130 doesn't.
+fn execute_node(shell: &mut Shell, node: &Node) -> i32 {
+    match node {
+        Node::Pipeline(stages) => {
+            let command = pipeline_text(stages);
+            shell.jobs.interrupted = false;
+            shell.last_status = match stages.as_slice() {
+                [Command::Simple(raw_words)] => execute_simple_command(shell, raw_words, &command),
+                [Command::Group(body, raw_redirections)] => {
+                    execute_group(shell, body, raw_redirections)
+                }
+                [Command::Function(name, body)] => {
+                    shell.functions.insert(name.clone(), (**body).clone());
+                    0
+                }
+                _ => execute_pipeline(shell, stages, &command),
+            };
+            shell.last_status
+        }
+        Node::And(left, right) => match execute_node(shell, left) {
+            0 => execute_node(shell, right),
+            status => status,
+        },
+        Node::Or(left, right) => match execute_node(shell, left) {
+            0 => 0,
+            _ if shell.jobs.interrupted => shell.last_status,
+            _ => execute_node(shell, right),
+        },
+        Node::Sequence(nodes) => {
+            for node in nodes {
+                execute_node(shell, node);
+                if shell.jobs.interrupted {
+                    break;
+                }
+            }
+            shell.last_status
+        }
+        Node::Background(node) => {
+            shell.last_status = start_background_job(shell, node);
+            shell.last_status
+        }
+    }
+}
+
+/// Writes text to descriptor `fd` of a built-in, wherever its redirections
+/// point it.
+fn write_to_fd(text: &str, fd: RawFd, redirections: &[OpenRedirection]) -> Result<(), String> {
+    let bad_fd = || io::Error::from_raw_os_error(libc::EBADF);
+    let result = match resolve_fds(redirections)?.get(&fd) {
+        Some(FdTarget::Shell(1)) => {
+            let mut stdout = io::stdout();
+            stdout
+                .write_all(text.as_bytes())
+                .and_then(|()| stdout.flush())
+        }
+        Some(FdTarget::Shell(2)) => io::stderr().write_all(text.as_bytes()),
+        Some(FdTarget::Redirection(index)) => match &redirections[*index].source {
+            FdSource::File(file) => (&*file).write_all(text.as_bytes()),
+            _ => Err(bad_fd()), // Open for reading only
+        },
+        _ => Err(bad_fd()), // Closed, or the shell's stdin
+    };
+    result.map_err(|e| format!("write error: {}", error_reason(&e)))
+}
+
+/// Handles the result of a built-in (or a shell error): writes its output to
+/// stdout and its error message to stderr, where its redirections point them
+/// (see `write_to_fd`).
+///
+/// Returns the exit status of the command: 0 on success, 1 on failure or if
+/// the output can't be written.
+fn handle_command_result(
+    result: Result<Option<String>, String>,
+    redirections: &[OpenRedirection],
+) -> i32 {
+    let (text, fd, status) = match result {
+        Ok(Some(output)) if !output.is_empty() => (output, 1, 0),
+        Ok(_) => return 0, // Nothing to write (cd)
+        Err(message) => (format!("{}\n", message), 2, 1),
+    };
+    match write_to_fd(&text, fd, redirections) {
+        Ok(()) => status,
+        Err(e) => {
+            eprintln!("shell: {}", e);
+            1
         }
     }
 }
 
+/// Reads one line when stdin is not a terminal (e.g. a script piped into the shell).
+/// Returns None at end of input.
+fn read_line_non_interactive() -> Option<String> {
+    let mut input = String::new();
+    match stdin().read_line(&mut input) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(input),
+    }
+}
+
+/// Reads a line of input: with the line editor, showing `prompt`, when the shell is
+/// interactive, or directly from stdin otherwise.
+fn read_input(
+    shell: &Shell,
+    editor: &mut LineEditor,
+    interactive: bool,
+    prompt: &str,
+) -> ReadResult {
+    if interactive {
+        return editor.read_line(prompt, shell);
+    }
+    match read_line_non_interactive() {
+        Some(line) => ReadResult::Line(line),
+        None => ReadResult::EndOfFile,
+    }
+}
+
+/// Appends the lines entered in this session to the history file, if any.
+fn save_history(shell: &mut Shell) {
+    if let Err(e) = shell.history.save() {
+        eprintln!("shell: warning: cannot save history: {}", e);
+    }
+}
+
 /// Main shell loop
 fn main() {
-    let builtins = vec!["exit", "echo", "help", "cd"];
+    let mut shell = Shell::new();
+    shell.run_substitution = Some(run_command_substitution);
+    let mut editor = LineEditor::default();
+    let interactive = termion::is_tty(&stdin());
+    if interactive {
+        if let Err(e) = terminal::init_interactive() {
+            eprintln!("shell: warning: cannot take control of the terminal: {}", e);
+        }
+        shell.history = History::from_vars(&shell.vars);
+        if let Err(e) = shell.history.load() {
+            eprintln!("shell: warning: cannot read history: {}", e);
+        }
+    }
     loop {
-        // 1. Print prompt
-        let stdin = stdin();
-        let mut stdout = stdout().into_raw_mode().unwrap();
-        write!(stdout, "$ ").unwrap();
-        stdout.flush().unwrap();
-
-        // 2. Read input char by char
-        let mut input = String::new();
-        for key in stdin.keys() {
-            if let Ok(key) = key {
-                match key {
-                    Key::Char('\t') => {
-                        let matches = builtins.iter().find(|&builtin| builtin.starts_with(&input));
-                        if let Some(matched) = matches {
-                            write!(stdout, "{} ", &matched[input.len()..]).unwrap();
-                            input = matched.to_string() + " ";
-                        }
-                        stdout.flush().unwrap();
-                    }
-                    Key::Char('\n') => {
-                        write!(stdout, "\r\n").unwrap();
-                        stdout.flush().unwrap();
-                        break;
-                    }
-                    Key::Char(c) => {
-                        input.push(c);
-                        write!(stdout, "{}", c).unwrap();
-                        stdout.flush().unwrap();
-                    }
-                    _ => {}
-                }
+        // Report the background jobs that finished since the last prompt
+        shell.jobs.poll();
+        for line in shell.jobs.take_finished() {
+            if interactive {
+                eprintln!("{}", line);
             }
         }
 
+        // 1-2. Print prompt and read a line of input
+        let input = match read_input(&shell, &mut editor, interactive, "$ ") {
+            ReadResult::Line(line) => line,
+            ReadResult::Interrupted => {
+                shell.last_status = STATUS_INTERRUPTED;
+                continue;
+            }
+            ReadResult::EndOfFile => break, // End of input
+        };
+
         // 3. Basic trimming and empty check
         let trimmed_input = input.trim();
         if trimmed_input.is_empty() {
             continue;
         }
+        shell.history.add(input.trim_end()); // Leading spaces keep a line out of history
 
-        // 4. Parse input into tokens
-        let tokens: Vec<String> = match parse_tokens(trimmed_input) {
-            Ok(parsed) if parsed.is_empty() => continue, // e.g., input was `""`
-            Ok(parsed) => parsed,
+        // 4. Parse input into its syntax tree
+        let mut node = match parse_command_line(trimmed_input) {
+            Ok(Some(node)) => node,
+            Ok(None) => continue,
             Err(e) => {
-                raw_eprintln!("shell: parse error: {}", e);
+                eprintln!("shell: parse error: {}", e);
+                shell.last_status = STATUS_SYNTAX_ERROR;
                 continue;
             }
         };
-        let (command_name, args_slice) = tokens.split_first().unwrap(); // Safe due to empty check
 
-        // 5. Parse redirections from arguments
-        let (command_args, redirections) = parse_redirections(args_slice);
+        // 5. Read the bodies of here-documents from the following lines
+        let mut interrupted = false;
+        let heredocs = read_heredocs(node.words_mut(), || {
+            if interrupted {
+                return None;
+            }
+            match read_input(&shell, &mut editor, interactive, "> ") {
+                ReadResult::Line(line) => {
+                    Some(line.strip_suffix('\n').unwrap_or(&line).to_string())
+                }
+                ReadResult::Interrupted => {
+                    interrupted = true;
+                    None
+                }
+                ReadResult::EndOfFile => None,
+            }
+        });
+        match heredocs {
+            _ if interrupted => {
+                shell.last_status = STATUS_INTERRUPTED;
+                continue;
+            }
+            Ok(unterminated) => {
+                for delimiter in unterminated {
+                    eprintln!(
+                        "shell: warning: here-document delimited by end-of-file (wanted `{}')",
+                        delimiter
+                    );
+                }
+            }
+            Err(e) => {
+                eprintln!("shell: {}", e);
+                shell.last_status = STATUS_SYNTAX_ERROR;
+                continue;
+            }
+        }
 
-        // 6. Dispatch command (built-in or external)
-        let result = dispatch_command(
-            command_name,
-            &command_args, // Use args *after* redirection parsing
-            &redirections,
+        // 6. Execute: a single command is dispatched directly, a pipeline stage by stage,
+        //    lists by their operators; output and errors are printed respecting redirection
+        execute_node(&mut shell, &node);
+    }
+    save_history(&mut shell);
+    std::process::exit(shell.last_status);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses and runs a command line, returning its exit status.
+    fn run(shell: &mut Shell, line: &str) -> i32 {
+        let node = parse_command_line(line).unwrap().unwrap();
+        execute_node(shell, &node)
+    }
+
+    #[test]
+    fn test_builtins_in_pipelines_run_in_subshells() {
+        let mut shell = Shell::new();
+        let cwd = std::env::current_dir().unwrap();
+
+        // The shell survives `exit`, which gives the status of the pipeline
+        assert_eq!(run(&mut shell, "echo hi | exit 3"), 3);
+        assert_eq!(run(&mut shell, "exit 4 | true"), 0);
+
+        assert_eq!(run(&mut shell, "cd / | true"), 0);
+        assert_eq!(std::env::current_dir().unwrap(), cwd);
+
+        assert_eq!(run(&mut shell, "export PIPED=1 | true"), 0);
+        assert_eq!(run(&mut shell, "true | PIPED=2"), 0);
+        assert_eq!(shell.vars.get("PIPED"), None);
+    }
+
+    #[test]
+    fn test_env_with_a_command_in_a_pipeline() {
+        let mut shell = Shell::new();
+        let path = std::env::temp_dir().join(format!("shell_env_pipe_{}", std::process::id()));
+
+        // `env` runs the command rather than listing the variables
+        let line = format!(
+            "env PIPED_ENV=x printenv PIPED_ENV | cat >{}",
+            path.display()
+        );
+        assert_eq!(run(&mut shell, &line), 0);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "x\n");
+        assert_eq!(
+            run(
+                &mut shell,
+                "true | env PIPED_ENV=y printenv PIPED_ENV >/dev/null"
+            ),
+            0
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_only_ctrl_c_abandons_the_line() {
+        let mut shell = Shell::new();
+        assert_eq!(run(&mut shell, "sh -c 'exit 130'; AFTER=1"), 0);
+        assert_eq!(run(&mut shell, "sh -c 'exit 130' || FALLBACK=1"), 0);
+        assert_eq!(shell.vars.get("AFTER"), Some("1"));
+        assert_eq!(shell.vars.get("FALLBACK"), Some("1"));
+
+        assert_eq!(
+            run(&mut shell, "sh -c 'kill -INT $$' || INTERRUPTED=1"),
+            130
         );
+        assert_eq!(run(&mut shell, "sh -c 'kill -INT $$'; INTERRUPTED=1"), 130);
+        assert_eq!(
+            run(&mut shell, "(sh -c 'kill -INT $$'); INTERRUPTED=1"),
+            130
+        );
+        assert_eq!(shell.vars.get("INTERRUPTED"), None);
+    }
+
+    #[test]
+    fn test_status_of_assignments() {
+        let mut shell = Shell::new();
+        shell.run_substitution = Some(run_command_substitution);
+        assert_eq!(run(&mut shell, "x=$(false)"), 1);
+        assert_eq!(run(&mut shell, "x=$(exit 3) y=$(true)"), 0);
+        assert_eq!(run(&mut shell, "false; x=1"), 0);
+        assert_eq!(run(&mut shell, "true | x=$(exit 5)"), 5);
+    }
+
+    #[test]
+    fn test_shell_pid_in_subshells() {
+        let mut shell = Shell::new();
+        shell.run_substitution = Some(run_command_substitution);
+        let pid = std::process::id().to_string();
+        assert_eq!(run(&mut shell, "PID=$$; SUB=$(echo $$)"), 0);
+        assert_eq!(shell.vars.get("PID"), Some(pid.as_str()));
+        assert_eq!(shell.vars.get("SUB"), Some(pid.as_str()));
+        assert_eq!(run(&mut shell, "NESTED=$( (echo $$) | cat)"), 0);
+        assert_eq!(shell.vars.get("NESTED"), Some(pid.as_str()));
+    }
+
+    #[test]
+    fn test_functions() {
+        let mut shell = Shell::new();
+        assert_eq!(run(&mut shell, "f() { FIRST=$1; COUNT=$#; }"), 0);
+        assert_eq!(run(&mut shell, "f 'a b' c"), 0);
+        assert_eq!(shell.vars.get("FIRST"), Some("a b"));
+        assert_eq!(shell.vars.get("COUNT"), Some("2"));
+        assert!(shell.positional.is_empty());
 
-        // 7. Handle the result (print output/errors, respect redirection)
-        handle_command_result(result, &redirections);
+        // A function runs in the shell, or in a subshell as a pipeline stage
+        assert_eq!(run(&mut shell, "g() { G=$1; (exit 4); }"), 0);
+        assert_eq!(run(&mut shell, "g in_pipe | true; g in_shell"), 4);
+        assert_eq!(shell.vars.get("G"), Some("in_shell"));
     }
 }
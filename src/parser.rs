@@ -1,115 +1,646 @@
 //! Command line parsing module for the rust shell.
 //! 
-//! This module handles parsing command line input into tokens, respecting
-//! shell quoting rules and escape sequences.
+//! This module handles splitting command line input into tokens, respecting
+//! shell quoting rules and escape sequences, and building the syntax tree of
+//! a line: pipelines joined by `&&` and `||` into and-or lists, themselves
+//! separated by `;` or `&` (which runs the list before it in the background).
+//! The commands of a pipeline are simple commands, subshells `( list )` and
+//! brace groups `{ list; }`, which nest a list and may have redirections, and
+//! function definitions `name() { list; }`.
+//! Words are kept in their raw form (quotes, escapes, `$` expansions and
+//! command substitutions intact); the `expand` module turns them into arguments.
 
+use crate::redirect::parse_redirections;
+use crate::variables::is_valid_name;
 
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+use std::vec::IntoIter;
 
 // --- Constants ---
 pub const BACKSLASH: char = '\\';
 pub const SINGLE_QUOTE: char = '\'';
 pub const DOUBLE_QUOTE: char = '"';
+pub const PIPE: char = '|';
+pub const SEMICOLON: char = ';';
+pub const AMPERSAND: char = '&';
+pub const DOLLAR: char = '$';
+pub const OPEN_BRACE: char = '{';
+pub const CLOSE_BRACE: char = '}';
+pub const OPEN_PAREN: char = '(';
+pub const CLOSE_PAREN: char = ')';
+pub const BACKTICK: char = '`';
 
-/// Parses a command line string into arguments, respecting shell quoting and escaping.
-/// Handles single quotes (''), double quotes (""), and backslash (\) escapes.
-/// Returns Err on unterminated quotes.
-pub fn parse_tokens(input_args: &str) -> Result<Vec<String>, String> {
-    let mut args: Vec<String> = Vec::new();
+/// A single lexical token of a command line.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Token {
+    /// A raw word (command name or argument), still containing its quotes,
+    /// escapes and parameter expansions
+    Word(String),
+    /// The pipe operator `|`
+    Pipe,
+    /// The sequence operator `;`
+    Semicolon,
+    /// The and operator `&&`
+    And,
+    /// The or operator `||`
+    Or,
+    /// The background operator `&`
+    Background,
+    /// The `(` opening a subshell
+    OpenParen,
+    /// The `)` closing a subshell
+    CloseParen,
+}
+
+impl Token {
+    /// Returns the text of the token, as shown in syntax errors.
+    fn text(&self) -> &str {
+        match self {
+            Token::Word(word) => word,
+            Token::Pipe => "|",
+            Token::Semicolon => ";",
+            Token::And => "&&",
+            Token::Or => "||",
+            Token::Background => "&",
+            Token::OpenParen => "(",
+            Token::CloseParen => ")",
+        }
+    }
+}
+
+/// Parses a command line string into tokens, respecting shell quoting and escaping.
+/// Handles single quotes (''), double quotes (""), backslash (\\) escapes,
+/// `${...}` parameter expansions and `$(...)` or `` `...` `` command
+/// substitutions, none of which are split by whitespace or operators.
+/// Unquoted `|`, `;`, `&`, `&&`, `||`, `(` and `)` are emitted as operator
+/// tokens, except for a `&` within a redirection operator (`2>&1`, `>&2`, `&>file`).
+/// The braces of a group are words, reserved only where a command starts.
+/// Returns Err on unterminated quotes, braces or substitutions.
+pub fn parse_tokens(input_args: &str) -> Result<Vec<Token>, String> {
+    let mut args: Vec<Token> = Vec::new();
     let mut current_arg = String::new();
-    let mut in_double_quotes = false;
-    let mut in_single_quotes = false;
     let mut chars = input_args.chars().peekable();
 
     while let Some(c) = chars.next() {
         match c {
-            // Handle backslash escapes
-            BACKSLASH => {
-                if let Some(&next_char) = chars.peek() {
-                    if in_single_quotes {
-                        // Inside single quotes, backslashes are literal
-                        current_arg.push(c);
+            // Handle whitespace, which separates arguments
+            ' ' | '\t' => {
+                if !current_arg.is_empty() {
+                    args.push(Token::Word(current_arg));
+                    current_arg = String::new();
+                }
+                // Skip additional whitespace
+                while let Some(&next_char) = chars.peek() {
+                    if next_char == ' ' || next_char == '\t' {
+                        chars.next();
                     } else {
-                        // Outside single quotes or inside double quotes, escape the next character
-                        chars.next(); // Consume the escaped character
-                        current_arg.push(next_char);
+                        break;
                     }
-                } else {
-                    // Backslash at end of input - treat as literal
-                    current_arg.push(c);
-                }
-            }
-            // Handle single quotes
-            SINGLE_QUOTE => {
-                if in_double_quotes {
-                    // Inside double quotes, single quotes are literal
-                    current_arg.push(c);
-                } else {
-                    // Toggle single quote state
-                    in_single_quotes = !in_single_quotes;
                 }
             }
-            // Handle double quotes
-            DOUBLE_QUOTE => {
-                if in_single_quotes {
-                    // Inside single quotes, double quotes are literal
+            // Handle the operators `|`, `||`, `;` and `&&`
+            PIPE | SEMICOLON | AMPERSAND => {
+                let is_double = chars.peek() == Some(&c);
+                if c == AMPERSAND
+                    && !is_double
+                    && (chars.peek() == Some(&'>') || current_arg.ends_with(['>', '<']))
+                {
+                    // `&>`, `>&` and `<&` are redirection operators, part of the word
                     current_arg.push(c);
                 } else {
-                    // Toggle double quote state
-                    in_double_quotes = !in_double_quotes;
-                }
-            }
-            // Handle whitespace
-            ' ' | '\t' => {
-                if in_single_quotes || in_double_quotes {
-                    // Inside quotes, whitespace is literal
-                    current_arg.push(c);
-                } else {
-                    // Outside quotes, whitespace separates arguments
+                    // An operator ends the current word
                     if !current_arg.is_empty() {
-                        args.push(current_arg);
+                        args.push(Token::Word(current_arg));
                         current_arg = String::new();
                     }
-                    // Skip additional whitespace
-                    while let Some(&next_char) = chars.peek() {
-                        if next_char == ' ' || next_char == '\t' {
-                            chars.next();
-                        } else {
-                            break;
-                        }
+                    let token = match c {
+                        PIPE if is_double => Token::Or,
+                        PIPE => Token::Pipe,
+                        SEMICOLON => Token::Semicolon,
+                        _ if is_double => Token::And,
+                        _ => Token::Background,
+                    };
+                    if token == Token::Or || token == Token::And {
+                        chars.next(); // Consume the second character
                     }
+                    args.push(token);
                 }
             }
-            // Regular characters
-            _ => {
-                current_arg.push(c);
+            // Handle the parentheses of a subshell
+            OPEN_PAREN | CLOSE_PAREN => {
+                if !current_arg.is_empty() {
+                    args.push(Token::Word(current_arg));
+                    current_arg = String::new();
+                }
+                args.push(if c == OPEN_PAREN {
+                    Token::OpenParen
+                } else {
+                    Token::CloseParen
+                });
             }
+            // Quotes, escapes, expansions and regular characters
+            _ => read_word_part(c, &mut chars, &mut current_arg)?,
         }
     }
 
     // Add the final argument if it's not empty
     if !current_arg.is_empty() {
-        args.push(current_arg);
+        args.push(Token::Word(current_arg));
+    }
+    Ok(args)
+}
+
+/// Copies the part of a word starting with `c` from `chars` to `word`: a
+/// quoted string, an escaped character, a `${...}` expansion, a command
+/// substitution or a single character. Quotes, expansions and substitutions
+/// are copied whole, along with everything nested in them.
+fn read_word_part(c: char, chars: &mut Peekable<Chars>, word: &mut String) -> Result<(), String> {
+    word.push(c);
+    match c {
+        // Keep the escaped character with its backslash
+        BACKSLASH => {
+            if let Some(next_char) = chars.next() {
+                word.push(next_char);
+            }
+        }
+        // Nothing is special inside single quotes
+        SINGLE_QUOTE => loop {
+            match chars.next() {
+                Some(next_char) => {
+                    word.push(next_char);
+                    if next_char == SINGLE_QUOTE {
+                        break;
+                    }
+                }
+                None => return Err("Unterminated single quote in arguments".to_string()),
+            }
+        },
+        DOUBLE_QUOTE => read_until(DOUBLE_QUOTE, chars, word)?,
+        BACKTICK => {
+            word.push_str(&read_backquoted(chars)?);
+            word.push(BACKTICK);
+        }
+        DOLLAR => match chars.peek() {
+            Some(&OPEN_BRACE) => {
+                chars.next();
+                word.push(OPEN_BRACE);
+                read_until(CLOSE_BRACE, chars, word)?;
+            }
+            Some(&OPEN_PAREN) => {
+                chars.next();
+                word.push(OPEN_PAREN);
+                word.push_str(&read_command_substitution(chars)?);
+                word.push(CLOSE_PAREN);
+            }
+            _ => {}
+        },
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Copies the rest of a double-quoted string or of a `${...}` expansion from
+/// `chars` to `word`, up to and including the closing `end` character.
+/// Single quotes are literal inside double quotes, but quote inside `${...}`.
+fn read_until(end: char, chars: &mut Peekable<Chars>, word: &mut String) -> Result<(), String> {
+    while let Some(c) = chars.next() {
+        if c == end {
+            word.push(c);
+            return Ok(());
+        }
+        match c {
+            SINGLE_QUOTE if end == DOUBLE_QUOTE => word.push(c),
+            _ => read_word_part(c, chars, word)?,
+        }
+    }
+    Err(match end {
+        DOUBLE_QUOTE => "Unterminated double quote in arguments".to_string(),
+        _ => "Unterminated ${ in arguments".to_string(),
+    })
+}
+
+/// Reads the command of a `$(...)` substitution, following its `$(`, up to the
+/// matching `)`, which is consumed. Quotes, escapes, nested substitutions and
+/// subshells inside the command are skipped over, so that a `)` in them
+/// doesn't end it.
+///
+/// # Returns
+///
+/// * `Ok(command)` - The command line between the parentheses
+/// * `Err(message)` - The substitution or something nested in it is unterminated
+///
+/// # Examples
+///
+/// ```
+/// use codecrafters_shell::parser::read_command_substitution;
+///
+/// let mut chars = "echo $(echo ')') )!".chars().peekable();
+/// assert_eq!(read_command_substitution(&mut chars).unwrap(), "echo $(echo ')') ");
+/// assert_eq!(chars.collect::<String>(), "!");
+/// ```
+pub fn read_command_substitution(chars: &mut Peekable<Chars>) -> Result<String, String> {
+    let mut command = String::new();
+    let mut depth = 0; // Nesting of subshells
+    while let Some(c) = chars.next() {
+        match c {
+            CLOSE_PAREN if depth == 0 => return Ok(command),
+            CLOSE_PAREN => depth -= 1,
+            OPEN_PAREN => depth += 1,
+            _ => {}
+        }
+        read_word_part(c, chars, &mut command)?;
+    }
+    Err("Unterminated $( in arguments".to_string())
+}
+
+/// Reads the command of a `` `...` `` substitution, following its opening
+/// backquote, up to the closing one, which is consumed. The command is
+/// returned as written, with backslashes escaping backquotes (for nesting)
+/// still in place.
+pub fn read_backquoted(chars: &mut Peekable<Chars>) -> Result<String, String> {
+    let mut command = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            BACKTICK => return Ok(command),
+            BACKSLASH => {
+                command.push(c);
+                if let Some(next_char) = chars.next() {
+                    command.push(next_char);
+                }
+            }
+            _ => command.push(c),
+        }
+    }
+    Err("Unterminated ` in arguments".to_string())
+}
+
+/// A node of the syntax tree of a command line.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Node {
+    /// A pipeline: its commands, each reading the output of the one before
+    Pipeline(Vec<Command>),
+    /// `left && right`: runs `right` only if `left` succeeds
+    And(Box<Node>, Box<Node>),
+    /// `left || right`: runs `right` only if `left` fails
+    Or(Box<Node>, Box<Node>),
+    /// `first; second; ...`: runs each command in turn
+    Sequence(Vec<Node>),
+    /// `command &`: runs the command as a background job
+    Background(Box<Node>),
+}
+
+/// A command of a pipeline.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Command {
+    /// A simple command: its raw words
+    Simple(Vec<String>),
+    /// `( list ) redirections`: runs the list in a forked copy of the shell,
+    /// so that it can't change the shell's state
+    Subshell(Box<Node>, Vec<String>),
+    /// `{ list; } redirections`: runs the list in the shell itself, with the
+    /// redirections applied to all of its commands
+    Group(Box<Node>, Vec<String>),
+    /// `name() compound-command`: defines a shell function, which runs the
+    /// compound command when called by name
+    Function(String, Box<Command>),
+}
+
+impl Node {
+    /// Returns the word lists of the tree, in the order they appear in the line:
+    /// the words of its simple commands and the redirections of compound ones.
+    pub fn words_mut(&mut self) -> Vec<&mut Vec<String>> {
+        match self {
+            Node::Pipeline(commands) => commands.iter_mut().flat_map(Command::words_mut).collect(),
+            Node::And(left, right) | Node::Or(left, right) => {
+                let mut words = left.words_mut();
+                words.extend(right.words_mut());
+                words
+            }
+            Node::Sequence(nodes) => nodes.iter_mut().flat_map(Node::words_mut).collect(),
+            Node::Background(node) => node.words_mut(),
+        }
+    }
+}
+
+impl Command {
+    /// Returns the word lists of the command (see `Node::words_mut`).
+    fn words_mut(&mut self) -> Vec<&mut Vec<String>> {
+        match self {
+            Command::Simple(words) => vec![words],
+            Command::Subshell(body, redirections) | Command::Group(body, redirections) => {
+                let mut words = body.words_mut();
+                words.push(redirections);
+                words
+            }
+            Command::Function(_, body) => body.words_mut(),
+        }
+    }
+}
+
+/// Joins raw words back into a command line, without the bodies of here-documents.
+fn words_text(words: &[String]) -> String {
+    let words: Vec<&str> = words
+        .iter()
+        .map(|word| word.split('\n').next().unwrap_or_default())
+        .collect();
+    words.join(" ")
+}
+
+/// Formats the commands of a pipeline back into a command line, as shown by
+/// `jobs`, with the raw words and without the bodies of here-documents.
+pub fn pipeline_text(commands: &[Command]) -> String {
+    let commands: Vec<String> = commands.iter().map(Command::to_string).collect();
+    commands.join(" | ")
+}
+
+impl fmt::Display for Command {
+    /// Formats the command back into a command line (see `pipeline_text`).
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let redirections = match self {
+            Command::Simple(words) => return write!(f, "{}", words_text(words)),
+            Command::Function(name, body) => return write!(f, "{}() {}", name, body),
+            Command::Subshell(body, redirections) => {
+                write!(f, "({})", body)?;
+                redirections
+            }
+            // `&` already ends a background job before the `}`
+            Command::Group(body, redirections) if matches!(**body, Node::Background(_)) => {
+                write!(f, "{{ {} }}", body)?;
+                redirections
+            }
+            Command::Group(body, redirections) => {
+                write!(f, "{{ {}; }}", body)?;
+                redirections
+            }
+        };
+        if !redirections.is_empty() {
+            write!(f, " {}", words_text(redirections))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Node {
+    /// Formats the node back into a command line, as shown by `jobs`, with
+    /// the raw words and without the bodies of here-documents.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Node::Pipeline(commands) => write!(f, "{}", pipeline_text(commands)),
+            Node::And(left, right) => write!(f, "{} && {}", left, right),
+            Node::Or(left, right) => write!(f, "{} || {}", left, right),
+            Node::Sequence(nodes) => {
+                for (index, node) in nodes.iter().enumerate() {
+                    // `&` already separates a background job from the next command
+                    match node {
+                        Node::Background(_) if index + 1 < nodes.len() => write!(f, "{} ", node)?,
+                        _ if index + 1 < nodes.len() => write!(f, "{}; ", node)?,
+                        _ => write!(f, "{}", node)?,
+                    }
+                }
+                Ok(())
+            }
+            Node::Background(node) => write!(f, "{} &", node),
+        }
+    }
+}
+
+/// Returns the syntax error for an unexpected token, or for a line ending too early.
+fn unexpected(token: Option<&Token>) -> String {
+    match token {
+        Some(token) => format!("syntax error near unexpected token `{}'", token.text()),
+        None => "syntax error: unexpected end of line".to_string(),
     }
+}
+
+/// Returns true if the next token is the word `}` closing a brace group.
+fn at_close_brace(tokens: &mut Peekable<IntoIter<Token>>) -> bool {
+    matches!(tokens.peek(), Some(Token::Word(word)) if word == "}")
+}
+
+/// Consumes the token closing a compound command, `)` or `}`.
+fn expect(tokens: &mut Peekable<IntoIter<Token>>, expected: &Token) -> Result<(), String> {
+    match tokens.next() {
+        Some(token) if token == *expected => Ok(()),
+        token => Err(unexpected(token.as_ref())),
+    }
+}
+
+/// Parses the redirections following a compound command: the words up to the
+/// next operator, which must all be redirections.
+fn parse_compound_redirections(
+    tokens: &mut Peekable<IntoIter<Token>>,
+) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    while let Some(Token::Word(word)) = tokens.next_if(|token| matches!(token, Token::Word(_))) {
+        words.push(word);
+    }
+    let (args, _) = parse_redirections(&words);
+    match args.into_iter().next() {
+        Some(arg) => Err(unexpected(Some(&Token::Word(arg)))),
+        None => Ok(words),
+    }
+}
+
+/// Parses a command: a subshell, a brace group (when the first word is `{`),
+/// or a simple command made of the words up to the next operator.
+fn parse_command(tokens: &mut Peekable<IntoIter<Token>>) -> Result<Command, String> {
+    if tokens.next_if_eq(&Token::OpenParen).is_some() {
+        let body = parse_list(tokens)?;
+        expect(tokens, &Token::CloseParen)?;
+        let redirections = parse_compound_redirections(tokens)?;
+        return Ok(Command::Subshell(Box::new(body), redirections));
+    }
+    let open_brace = Token::Word(OPEN_BRACE.to_string());
+    if tokens.next_if_eq(&open_brace).is_some() {
+        let body = parse_list(tokens)?;
+        expect(tokens, &Token::Word(CLOSE_BRACE.to_string()))?;
+        let redirections = parse_compound_redirections(tokens)?;
+        return Ok(Command::Group(Box::new(body), redirections));
+    }
+
+    let mut words = Vec::new();
+    if !at_close_brace(tokens) {
+        while let Some(Token::Word(word)) = tokens.next_if(|token| matches!(token, Token::Word(_)))
+        {
+            words.push(word);
+        }
+    }
+    // Every command needs a word, including the one after a trailing `|`
+    if words.is_empty() {
+        return Err(unexpected(tokens.peek()));
+    }
+    // A single word followed by `()` names a function, defined by a compound command
+    if words.len() == 1 && tokens.next_if_eq(&Token::OpenParen).is_some() {
+        if tokens.next_if_eq(&Token::CloseParen).is_none() {
+            return Err(unexpected(Some(&Token::OpenParen)));
+        }
+        let name = words.remove(0);
+        if !is_valid_name(&name) {
+            return Err(format!("`{}': not a valid identifier", name));
+        }
+        let is_compound = |token: &Token| *token == Token::OpenParen || *token == open_brace;
+        if !tokens.peek().is_some_and(is_compound) {
+            return Err(unexpected(tokens.peek()));
+        }
+        let body = parse_command(tokens)?;
+        return Ok(Command::Function(name, Box::new(body)));
+    }
+    Ok(Command::Simple(words))
+}
 
-    // Check for unterminated quotes
-    if in_double_quotes {
-        Err("Unterminated double quote in arguments".to_string())
-    } else if in_single_quotes {
-        Err("Unterminated single quote in arguments".to_string())
-    } else {
-        Ok(args)
+/// Parses the commands of a pipeline, separated by `|`.
+fn parse_stages(tokens: &mut Peekable<IntoIter<Token>>) -> Result<Vec<Command>, String> {
+    let mut commands = vec![parse_command(tokens)?];
+    while tokens.next_if_eq(&Token::Pipe).is_some() {
+        commands.push(parse_command(tokens)?);
     }
+    Ok(commands)
+}
+
+/// Parses an and-or list: pipelines joined by `&&` and `||`, which have the
+/// same precedence and group from the left.
+fn parse_and_or(tokens: &mut Peekable<IntoIter<Token>>) -> Result<Node, String> {
+    let mut node = Node::Pipeline(parse_stages(tokens)?);
+    while let Some(operator) = tokens.next_if(|token| matches!(token, Token::And | Token::Or)) {
+        let left = Box::new(node);
+        let right = Box::new(Node::Pipeline(parse_stages(tokens)?));
+        node = match operator {
+            Token::And => Node::And(left, right),
+            _ => Node::Or(left, right),
+        };
+    }
+    Ok(node)
+}
+
+/// Parses a list: and-or lists separated by `;` or `&`, which may also end
+/// it. A list followed by `&` is wrapped in a `Node::Background`. The list
+/// ends with the line, or before the `)` or `}` closing a compound command.
+fn parse_list(tokens: &mut Peekable<IntoIter<Token>>) -> Result<Node, String> {
+    let mut nodes = Vec::new();
+    loop {
+        let mut node = parse_and_or(tokens)?;
+        let terminator =
+            tokens.next_if(|token| matches!(token, Token::Semicolon | Token::Background));
+        if terminator == Some(Token::Background) {
+            node = Node::Background(Box::new(node));
+        }
+        nodes.push(node);
+        let at_end = matches!(tokens.peek(), None | Some(Token::CloseParen));
+        if terminator.is_none() || at_end || at_close_brace(tokens) {
+            break;
+        }
+    }
+    if nodes.len() == 1 {
+        return Ok(nodes.pop().unwrap());
+    }
+    Ok(Node::Sequence(nodes))
+}
+
+/// Parses a command line into its syntax tree (see `parse_list`). A single
+/// command gives a `Node::Pipeline` of one command.
+///
+/// # Returns
+///
+/// * `Ok(Some(node))` - The tree of the line
+/// * `Ok(None)` - The line has no commands
+/// * `Err(message)` - Unterminated quotes, an operator without a command on
+///   one of its sides, or an unbalanced `(`, `)`, `{` or `}`
+///
+/// # Examples
+///
+/// ```
+/// use codecrafters_shell::parser::{parse_command_line, Node};
+///
+/// let node = parse_command_line("make && ./run").unwrap().unwrap();
+/// assert!(matches!(node, Node::And(_, _)));
+/// ```
+pub fn parse_command_line(input: &str) -> Result<Option<Node>, String> {
+    let mut tokens = parse_tokens(input)?.into_iter().peekable();
+    if tokens.peek().is_none() {
+        return Ok(None);
+    }
+    let node = parse_list(&mut tokens)?;
+    match tokens.next() {
+        Some(token) => Err(unexpected(Some(&token))),
+        None => Ok(Some(node)),
+    }
+}
+
+/// Parses a command line into pipeline stages, each stage being the list of
+/// raw words (command name followed by its arguments) between `|` operators.
+/// Returns Err on unterminated quotes, on an empty pipeline stage, on any
+/// other operator or on a compound command.
+///
+/// # Examples
+///
+/// ```
+/// use codecrafters_shell::parser::parse_pipeline;
+///
+/// let stages = parse_pipeline("ls -l | grep foo").unwrap();
+/// assert_eq!(stages, vec![vec!["ls", "-l"], vec!["grep", "foo"]]);
+/// ```
+pub fn parse_pipeline(input: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut tokens = parse_tokens(input)?.into_iter().peekable();
+    if tokens.peek().is_none() {
+        return Ok(Vec::new());
+    }
+    let commands = parse_stages(&mut tokens)?;
+    if let Some(token) = tokens.next() {
+        return Err(unexpected(Some(&token)));
+    }
+    commands
+        .into_iter()
+        .map(|command| match command {
+            Command::Simple(words) => Ok(words),
+            Command::Subshell(..) | Command::Function(..) => {
+                Err(unexpected(Some(&Token::OpenParen)))
+            }
+            Command::Group(..) => Err(unexpected(Some(&Token::Word(OPEN_BRACE.to_string())))),
+        })
+        .collect()
+}
+
+/// Returns true if `c` has no special meaning to the shell, so that it never
+/// needs to be quoted.
+pub fn is_plain_char(c: char) -> bool {
+    c.is_alphanumeric() || "_-./:,+=@%".contains(c)
+}
+
+/// Quotes `text` so that it parses back into a single word with exactly this value.
+/// Text made only of characters with no special meaning is returned unchanged,
+/// anything else is wrapped in single quotes.
+///
+/// # Examples
+///
+/// ```
+/// use codecrafters_shell::parser::quote_word;
+///
+/// assert_eq!(quote_word("plain"), "plain");
+/// assert_eq!(quote_word("it's here"), r"'it'\''s here'");
+/// ```
+pub fn quote_word(text: &str) -> String {
+    if !text.is_empty() && text.chars().all(is_plain_char) {
+        return text.to_string();
+    }
+    // A single quote can't appear inside single quotes: close, escape it, reopen
+    format!("'{}'", text.replace(SINGLE_QUOTE, r"'\''"))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn words(items: &[&str]) -> Vec<Token> {
+        items.iter().map(|s| Token::Word(s.to_string())).collect()
+    }
+
     #[test]
     fn test_simple_parsing() {
         assert_eq!(
             parse_tokens("echo hello world").unwrap(),
-            vec!["echo", "hello", "world"]
+            words(&["echo", "hello", "world"])
         );
     }
 
@@ -117,7 +648,7 @@ mod tests {
     fn test_double_quotes() {
         assert_eq!(
             parse_tokens(r#"echo "hello world""#).unwrap(),
-            vec!["echo", "hello world"]
+            words(&["echo", r#""hello world""#])
         );
     }
 
@@ -125,7 +656,7 @@ mod tests {
     fn test_single_quotes() {
         assert_eq!(
             parse_tokens("echo 'hello world'").unwrap(),
-            vec!["echo", "hello world"]
+            words(&["echo", "'hello world'"])
         );
     }
 
@@ -133,7 +664,7 @@ mod tests {
     fn test_backslash_escape() {
         assert_eq!(
             parse_tokens(r"echo hello\ world").unwrap(),
-            vec!["echo", "hello world"]
+            words(&["echo", r"hello\ world"])
         );
     }
 
@@ -149,11 +680,290 @@ mod tests {
 
     #[test]
     fn test_empty_input() {
-        assert_eq!(parse_tokens("").unwrap(), Vec::<String>::new());
+        assert_eq!(parse_tokens("").unwrap(), Vec::<Token>::new());
     }
 
     #[test]
     fn test_whitespace_only() {
-        assert_eq!(parse_tokens("   ").unwrap(), Vec::<String>::new());
+        assert_eq!(parse_tokens("   ").unwrap(), Vec::<Token>::new());
+    }
+
+    #[test]
+    fn test_pipe_token() {
+        assert_eq!(
+            parse_tokens("ls|wc -l").unwrap(),
+            vec![
+                Token::Word("ls".to_string()),
+                Token::Pipe,
+                Token::Word("wc".to_string()),
+                Token::Word("-l".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_quoted_pipe_is_literal() {
+        assert_eq!(
+            parse_tokens(r#"echo "a|b" 'c|d' e\|f"#).unwrap(),
+            words(&["echo", r#""a|b""#, "'c|d'", r"e\|f"])
+        );
+    }
+
+    #[test]
+    fn test_braced_expansion_is_one_word() {
+        assert_eq!(
+            parse_tokens(r#"echo ${X:-a b|c} "${Y:-}}" '${'"#).unwrap(),
+            words(&["echo", "${X:-a b|c}", r#""${Y:-}}""#, "'${'"])
+        );
+    }
+
+    #[test]
+    fn test_unterminated_brace() {
+        assert!(parse_tokens("echo ${HOME").is_err());
+    }
+
+    #[test]
+    fn test_command_substitution_is_one_word() {
+        assert_eq!(
+            parse_tokens(r#"echo $(a | b; c) "$(d ")" (e))" x`f; g`"#).unwrap(),
+            words(&["echo", "$(a | b; c)", r#""$(d ")" (e))""#, "x`f; g`"])
+        );
+        assert_eq!(
+            parse_tokens(r"echo $(a $(b ')') `c \` d`) ${X:-$(e })}").unwrap(),
+            words(&["echo", r"$(a $(b ')') `c \` d`)", "${X:-$(e })}"])
+        );
+        assert_eq!(
+            parse_tokens(r#"echo '$(' "\`" '`'"#).unwrap(),
+            words(&["echo", "'$('", r#""\`""#, "'`'"])
+        );
+    }
+
+    #[test]
+    fn test_unterminated_command_substitution() {
+        assert!(parse_tokens("echo $(a").is_err());
+        assert!(parse_tokens("echo $(a ')'").is_err());
+        assert!(parse_tokens("echo `a").is_err());
+        assert!(parse_tokens(r"echo `a\`").is_err());
+    }
+
+    #[test]
+    fn test_quote_word() {
+        assert_eq!(quote_word("simple-name_1.txt"), "simple-name_1.txt");
+        assert_eq!(quote_word(""), "''");
+        assert_eq!(quote_word("a b"), "'a b'");
+        assert_eq!(quote_word("$HOME"), "'$HOME'");
+        assert_eq!(
+            parse_tokens(&quote_word("it's")).unwrap(),
+            words(&[r"'it'\''s'"])
+        );
+    }
+
+    #[test]
+    fn test_parse_pipeline() {
+        assert_eq!(
+            parse_pipeline("cat file | grep foo | wc -l").unwrap(),
+            vec![vec!["cat", "file"], vec!["grep", "foo"], vec!["wc", "-l"]]
+        );
+    }
+
+    #[test]
+    fn test_list_operator_tokens() {
+        assert_eq!(
+            parse_tokens("a&&b || c;d 2>&1 'e;f'").unwrap(),
+            vec![
+                Token::Word("a".to_string()),
+                Token::And,
+                Token::Word("b".to_string()),
+                Token::Or,
+                Token::Word("c".to_string()),
+                Token::Semicolon,
+                Token::Word("d".to_string()),
+                Token::Word("2>&1".to_string()),
+                Token::Word("'e;f'".to_string()),
+            ]
+        );
+    }
+
+    fn pipeline(stages: &[&[&str]]) -> Box<Node> {
+        let stages = stages
+            .iter()
+            .map(|stage| Command::Simple(stage.iter().map(|word| word.to_string()).collect()))
+            .collect();
+        Box::new(Node::Pipeline(stages))
+    }
+
+    #[test]
+    fn test_parse_command_line() {
+        assert_eq!(parse_command_line("  ").unwrap(), None);
+        assert_eq!(
+            parse_command_line("ls | wc -l").unwrap(),
+            Some(*pipeline(&[&["ls"], &["wc", "-l"]]))
+        );
+        // `&&` and `||` group from the left
+        assert_eq!(
+            parse_command_line("false && echo no || echo yes").unwrap(),
+            Some(Node::Or(
+                Box::new(Node::And(pipeline(&[&["false"]]), pipeline(&[&["echo", "no"]]))),
+                pipeline(&[&["echo", "yes"]])
+            ))
+        );
+        // `;` separates and-or lists, and may end the line
+        assert_eq!(
+            parse_command_line("cd build; make && ./run;").unwrap(),
+            Some(Node::Sequence(vec![
+                *pipeline(&[&["cd", "build"]]),
+                Node::And(pipeline(&[&["make"]]), pipeline(&[&["./run"]])),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_parse_command_line_errors() {
+        for line in ["; ls", "ls ;; pwd", "&& ls", "ls &&", "ls || | wc", "ls | && wc"] {
+            assert!(parse_command_line(line).is_err(), "{}", line);
+        }
+        assert_eq!(
+            parse_command_line("ls ;; pwd").unwrap_err(),
+            "syntax error near unexpected token `;'"
+        );
+        assert_eq!(
+            parse_command_line("ls &&").unwrap_err(),
+            "syntax error: unexpected end of line"
+        );
+        assert!(parse_pipeline("ls; pwd").is_err());
+    }
+
+    #[test]
+    fn test_background() {
+        assert_eq!(
+            parse_tokens("sleep 1& cmd 2>&1 >&2 &>log <&0").unwrap(),
+            vec![
+                Token::Word("sleep".to_string()),
+                Token::Word("1".to_string()),
+                Token::Background,
+                Token::Word("cmd".to_string()),
+                Token::Word("2>&1".to_string()),
+                Token::Word(">&2".to_string()),
+                Token::Word("&>log".to_string()),
+                Token::Word("<&0".to_string()),
+            ]
+        );
+        assert_eq!(
+            parse_command_line("make && ./run &").unwrap(),
+            Some(Node::Background(Box::new(Node::And(
+                pipeline(&[&["make"]]),
+                pipeline(&[&["./run"]])
+            ))))
+        );
+        let node = parse_command_line("sleep 10 & ls | wc -l; cat <<EOF\nbody\n").unwrap();
+        assert_eq!(
+            node.unwrap().to_string(),
+            "sleep 10 & ls | wc -l; cat <<EOF"
+        );
+        assert!(parse_command_line("& ls").is_err());
+        assert!(parse_command_line("ls & & ls").is_err());
+    }
+
+    #[test]
+    fn test_words_mut() {
+        let mut node = parse_command_line("a | b && c; (d <<X) >out").unwrap().unwrap();
+        let firsts: Vec<String> = node
+            .words_mut()
+            .into_iter()
+            .map(|words| words[0].clone())
+            .collect();
+        assert_eq!(firsts, vec!["a", "b", "c", "d", ">out"]);
+    }
+
+    #[test]
+    fn test_compound_commands() {
+        assert_eq!(
+            parse_tokens("(cd sub&&make)>log").unwrap(),
+            vec![
+                Token::OpenParen,
+                Token::Word("cd".to_string()),
+                Token::Word("sub".to_string()),
+                Token::And,
+                Token::Word("make".to_string()),
+                Token::CloseParen,
+                Token::Word(">log".to_string()),
+            ]
+        );
+        assert_eq!(
+            parse_command_line("(cd sub && make) > build.log").unwrap(),
+            Some(Node::Pipeline(vec![Command::Subshell(
+                Box::new(Node::And(pipeline(&[&["cd", "sub"]]), pipeline(&[&["make"]]))),
+                vec![">".to_string(), "build.log".to_string()]
+            )]))
+        );
+        assert_eq!(
+            parse_command_line("{ echo a; echo b; } >out | wc -l").unwrap(),
+            Some(Node::Pipeline(vec![
+                Command::Group(
+                    Box::new(Node::Sequence(vec![
+                        *pipeline(&[&["echo", "a"]]),
+                        *pipeline(&[&["echo", "b"]]),
+                    ])),
+                    vec![">out".to_string()]
+                ),
+                Command::Simple(vec!["wc".to_string(), "-l".to_string()]),
+            ]))
+        );
+        // Braces are only reserved where a command starts
+        assert_eq!(
+            parse_command_line("echo { }").unwrap(),
+            Some(*pipeline(&[&["echo", "{", "}"]]))
+        );
+        for line in ["{ (ls); }", "{ sleep 1 & }", "({ a; } | b; c) 2>&1 &"] {
+            let node = parse_command_line(line).unwrap().unwrap();
+            assert_eq!(node.to_string(), line);
+        }
+    }
+
+    #[test]
+    fn test_compound_command_errors() {
+        for (line, error) in [
+            ("(ls", "syntax error: unexpected end of line"),
+            ("{ ls }", "syntax error: unexpected end of line"),
+            ("ls )", "syntax error near unexpected token `)'"),
+            ("()", "syntax error near unexpected token `)'"),
+            ("ls; }", "syntax error near unexpected token `}'"),
+            ("(ls) foo", "syntax error near unexpected token `foo'"),
+            ("ls (pwd)", "syntax error near unexpected token `('"),
+        ] {
+            assert_eq!(parse_command_line(line).unwrap_err(), error, "{}", line);
+        }
+        assert!(parse_pipeline("(ls)").is_err());
+    }
+
+    #[test]
+    fn test_function_definitions() {
+        assert_eq!(
+            parse_command_line("greet() { echo hi $1; }").unwrap(),
+            Some(Node::Pipeline(vec![Command::Function(
+                "greet".to_string(),
+                Box::new(Command::Group(pipeline(&[&["echo", "hi", "$1"]]), Vec::new()))
+            )]))
+        );
+        for line in ["f() { a; b; }", "f() (cd / && ls) >log", "f() { :; } | cat"] {
+            let node = parse_command_line(line).unwrap().unwrap();
+            assert_eq!(node.to_string(), line);
+        }
+        for (line, error) in [
+            ("f() echo x", "syntax error near unexpected token `echo'"),
+            ("f()", "syntax error: unexpected end of line"),
+            ("f(x) { :; }", "syntax error near unexpected token `('"),
+            ("1f() { :; }", "`1f': not a valid identifier"),
+        ] {
+            assert_eq!(parse_command_line(line).unwrap_err(), error, "{}", line);
+        }
+        assert!(parse_pipeline("f() { :; }").is_err());
+    }
+
+    #[test]
+    fn test_parse_pipeline_empty_stage() {
+        assert!(parse_pipeline("| wc").is_err());
+        assert!(parse_pipeline("ls | | wc").is_err());
+        assert!(parse_pipeline("ls |").is_err());
     }
 }
\ No newline at end of file
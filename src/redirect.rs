@@ -1,19 +1,30 @@
 //! I/O redirection handling module for the rust shell.
-//! 
-//! This module handles parsing and managing I/O redirections for commands,
-//! including stdout and stderr redirections with overwrite and append modes.
+//!
+//! This module handles parsing and managing I/O redirections for commands:
+//! output to files with overwrite and append modes, input from files,
+//! here-documents and here-strings, and copying or closing file descriptors.
+//! A command's redirections form an ordered list, applied from left to right
+//! as POSIX requires, so `>file 2>&1` sends both streams to the file while
+//! `2>&1 >file` sends stderr where stdout pointed before.
+//! The bodies of here-documents are read after the command line, and attached
+//! to their operator word before redirections are parsed.
+
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, ErrorKind};
+use std::os::fd::RawFd;
 
 /// Represents the mode of redirection operation.
 #[derive(PartialEq, Eq, Debug, Clone, Copy)]
 pub enum RedirectionMode {
-    /// Overwrite the target file (> or 1> or 2>)
+    /// Overwrite the target file (> or 2> or &>)
     Overwrite,
-    /// Append to the target file (>> or 1>> or 2>>)
+    /// Append to the target file (>> or 2>> or &>>)
     Append,
 }
 
 /// Represents a single redirection to a file.
-#[derive(Debug, Clone)]
+#[derive(PartialEq, Eq, Debug, Clone)]
 pub struct RedirectFile {
     /// The filename to redirect to
     pub filename: String,
@@ -21,131 +32,490 @@ pub struct RedirectFile {
     pub mode: RedirectionMode,
 }
 
-/// Holds all redirection information for a command.
-#[derive(Default, Debug)]
-pub struct Redirections {
-    /// Optional stdout redirection
-    pub stdout_redirect: Option<RedirectFile>,
-    /// Optional stderr redirection
-    pub stderr_redirect: Option<RedirectFile>,
+/// Where a command reads its input from, when redirected.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum InputSource {
+    /// Read a file (<)
+    File(String),
+    /// Read the body of a here-document (<< or <<-)
+    HereDoc {
+        /// The lines of the document, each ending with a newline
+        body: String,
+        /// Whether parameters in the body are still to be expanded; false once
+        /// expanded, or if the delimiter was quoted
+        expand: bool,
+    },
+    /// Read a word followed by a newline (<<<)
+    HereString(String),
+}
+
+impl InputSource {
+    /// Returns the text fed to the command, or None for a file.
+    pub fn text(&self) -> Option<String> {
+        match self {
+            InputSource::File(_) => None,
+            InputSource::HereDoc { body, .. } => Some(body.clone()),
+            InputSource::HereString(word) => Some(format!("{}\n", word)),
+        }
+    }
+}
+
+/// What a redirection does to its file descriptor.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum RedirectAction {
+    /// Write to a file (>, >>)
+    Output(RedirectFile),
+    /// Read from a file, here-document or here-string (<, <<, <<<)
+    Input(InputSource),
+    /// Make the descriptor a copy of the one named by the target, or close it
+    /// if the target is `-` (>&, <&)
+    Duplicate(String),
 }
 
-/// Parses redirection operators (>, 1>, 2>, >>, 1>>, 2>>) from the end of a token list.
-/// Returns the remaining arguments and optional filenames for stdout/stderr redirection.
-/// 
+/// A single redirection of a command.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Redirection {
+    /// The file descriptor being redirected
+    pub fd: RawFd,
+    /// What is done to it
+    pub action: RedirectAction,
+}
+
+/// Holds all redirections of a command, in the order they are applied.
+pub type Redirections = Vec<Redirection>;
+
+/// A redirection operator, as written before its target.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+enum Operator {
+    /// > or >>
+    Output(RedirectionMode),
+    /// &> or &>>, redirecting both stdout and stderr
+    OutputBoth(RedirectionMode),
+    /// <
+    Input,
+    /// << or <<-
+    HereDoc,
+    /// <<<
+    HereString,
+    /// >& or <&
+    Duplicate,
+}
+
+/// Redirection operators with the descriptor they redirect by default, longest
+/// first so that a word matches the right one.
+const OPERATORS: [(&str, Operator, RawFd); 10] = [
+    ("&>>", Operator::OutputBoth(RedirectionMode::Append), 1),
+    ("&>", Operator::OutputBoth(RedirectionMode::Overwrite), 1),
+    (">>", Operator::Output(RedirectionMode::Append), 1),
+    (">&", Operator::Duplicate, 1),
+    (">", Operator::Output(RedirectionMode::Overwrite), 1),
+    ("<<<", Operator::HereString, 0),
+    ("<<-", Operator::HereDoc, 0),
+    ("<<", Operator::HereDoc, 0),
+    ("<&", Operator::Duplicate, 0),
+    ("<", Operator::Input, 0),
+];
+
+/// Parses a file descriptor number written as decimal digits.
+fn parse_fd(text: &str) -> Option<RawFd> {
+    if text.is_empty() || !text.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    text.parse().ok()
+}
+
+/// Splits a raw word into the descriptor it redirects (given before the
+/// operator, or the operator's default), the redirection operator and the
+/// target attached to it (empty if the target is the next word).
+fn split_operator(word: &str) -> Option<(RawFd, Operator, &str)> {
+    let digits = word.len() - word.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let (number, rest) = word.split_at(digits);
+    let (operator, default_fd, attached) = OPERATORS
+        .iter()
+        .find_map(|&(text, operator, fd)| Some((operator, fd, rest.strip_prefix(text)?)))?;
+    match operator {
+        _ if number.is_empty() => Some((default_fd, operator, attached)),
+        Operator::OutputBoth(_) => None, // `2&>` is not an operator
+        _ => Some((parse_fd(number)?, operator, attached)),
+    }
+}
+
+/// Parses redirection operators from a token list: `<`, `<<`, `<<-`, `<<<`,
+/// `>`, `>>`, `>&`, `<&`, each optionally preceded by a file descriptor number
+/// (`2>`, `3<`, `2>&1`), and `&>`, `&>>` for both stdout and stderr. The target
+/// may be attached to the operator (`<file`) or be the next word.
+/// Returns the remaining arguments and the redirections, in order.
+///
+/// Here-documents are expected in the form produced by `read_heredocs`: the
+/// operator and delimiter followed by a newline and the body, in a single word.
+///
 /// # Arguments
-/// 
+///
 /// * `args_slice` - The command arguments to parse redirections from
-/// 
+///
 /// # Returns
-/// 
+///
 /// A tuple containing:
 /// * `Vec<String>` - The remaining command arguments after removing redirection operators
-/// * `Redirections` - The parsed redirection information
-/// 
+/// * `Redirections` - The parsed redirections, in the order they are applied
+///
 /// # Examples
-/// 
+///
 /// ```
 /// use codecrafters_shell::redirect::parse_redirections;
-/// 
-/// let args = vec!["ls".to_string(), "-l".to_string(), ">".to_string(), "output.txt".to_string()];
+///
+/// let args = vec!["ls".to_string(), ">".to_string(), "out.txt".to_string(), "2>&1".to_string()];
 /// let (remaining_args, redirections) = parse_redirections(&args);
-/// assert_eq!(remaining_args, vec!["ls", "-l"]);
-/// assert!(redirections.stdout_redirect.is_some());
+/// assert_eq!(remaining_args, vec!["ls"]);
+/// assert_eq!(redirections.len(), 2);
 /// ```
 pub fn parse_redirections(args_slice: &[String]) -> (Vec<String>, Redirections) {
-    let mut command_args = args_slice.to_vec(); // Clone to modify
-    let mut red = Redirections::default();
-
-    // Loop backwards checking for `op filename` patterns
-    loop {
-        let len = command_args.len();
-        if len < 2 {
-            break;
-        } // Need op + file
-
-        let op = &command_args[len - 2];
-        let filename = &command_args[len - 1];
-
-        match op.as_str() {
-            ">" | "1>" => {
-                red.stdout_redirect = Some(RedirectFile {
-                    filename: filename.clone(),
-                    mode: RedirectionMode::Overwrite,
+    let mut command_args = Vec::new();
+    let mut redirections = Vec::new();
+    let mut words = args_slice.iter().peekable();
+
+    while let Some(word) = words.next() {
+        let Some((fd, operator, attached)) = split_operator(word) else {
+            command_args.push(word.clone()); // Not a redirection operator
+            continue;
+        };
+        let target = if !attached.is_empty() {
+            attached.to_string()
+        } else if let Some(next) = words.next_if(|next| split_operator(next).is_none()) {
+            next.clone()
+        } else {
+            command_args.push(word.clone()); // An operator without a target is a plain word
+            continue;
+        };
+
+        let action = match operator {
+            Operator::Output(mode) => RedirectAction::Output(RedirectFile {
+                filename: target,
+                mode,
+            }),
+            Operator::OutputBoth(mode) => {
+                // Same as `>file 2>&1`
+                redirections.push(Redirection {
+                    fd: 1,
+                    action: RedirectAction::Output(RedirectFile {
+                        filename: target,
+                        mode,
+                    }),
                 });
-                command_args.truncate(len - 2); // Remove op + file
-            }
-            "2>" => {
-                red.stderr_redirect = Some(RedirectFile {
-                    filename: filename.clone(),
-                    mode: RedirectionMode::Overwrite,
+                redirections.push(Redirection {
+                    fd: 2,
+                    action: RedirectAction::Duplicate("1".to_string()),
                 });
-                command_args.truncate(len - 2); // Remove op + file
+                continue;
             }
-            ">>" | "1>>" => {
-                red.stdout_redirect = Some(RedirectFile {
-                    filename: filename.clone(),
-                    mode: RedirectionMode::Append,
-                });
-                command_args.truncate(len - 2); // Remove op + file
+            Operator::Input => RedirectAction::Input(InputSource::File(target)),
+            Operator::HereDoc => {
+                let (delimiter, body) = target.split_once('\n').unwrap_or((&target, ""));
+                RedirectAction::Input(InputSource::HereDoc {
+                    body: body.to_string(),
+                    expand: !is_quoted(delimiter),
+                })
             }
-            "2>>" => {
-                red.stderr_redirect = Some(RedirectFile {
-                    filename: filename.clone(),
-                    mode: RedirectionMode::Append,
-                });
-                command_args.truncate(len - 2); // Remove op + file
+            Operator::HereString => RedirectAction::Input(InputSource::HereString(target)),
+            Operator::Duplicate => RedirectAction::Duplicate(target),
+        };
+        redirections.push(Redirection { fd, action });
+    }
+    (command_args, redirections)
+}
+
+/// Where a file descriptor of a command is redirected to, once the target of
+/// the redirection is open.
+#[derive(Debug)]
+pub enum FdSource {
+    /// An open file
+    File(File),
+    /// Text read through a pipe (a here-document or here-string)
+    Text(String),
+    /// A copy of another descriptor of the command, as it is at that point
+    Fd(RawFd),
+    /// Nothing: the descriptor is closed
+    Closed,
+}
+
+/// A redirection whose target is open, ready to be applied.
+#[derive(Debug)]
+pub struct OpenRedirection {
+    /// The file descriptor being redirected
+    pub fd: RawFd,
+    /// What it is redirected to
+    pub source: FdSource,
+}
+
+/// Opens the targets of a command's redirections in order, creating or
+/// truncating output files. A descriptor being copied must be open at that
+/// point: a standard stream, or redirected by an earlier redirection.
+///
+/// # Arguments
+///
+/// * `redirections` - The redirections, with their targets expanded
+///
+/// # Returns
+///
+/// * `Ok(opened)` - The redirections, to be applied in the same order
+/// * `Err(message)` - A target can't be opened or is not a descriptor, as
+///   `TARGET: reason`
+pub fn open_redirections(redirections: &[Redirection]) -> Result<Vec<OpenRedirection>, String> {
+    let mut opened = Vec::new();
+    for redirection in redirections {
+        let source = match &redirection.action {
+            RedirectAction::Output(file) => FdSource::File(
+                OpenOptions::new()
+                    .create(true)
+                    .write(true)
+                    .truncate(file.mode == RedirectionMode::Overwrite)
+                    .append(file.mode == RedirectionMode::Append)
+                    .open(&file.filename)
+                    .map_err(|e| format!("{}: {}", file.filename, error_reason(&e)))?,
+            ),
+            RedirectAction::Input(InputSource::File(filename)) => FdSource::File(
+                File::open(filename).map_err(|e| format!("{}: {}", filename, error_reason(&e)))?,
+            ),
+            RedirectAction::Input(input) => FdSource::Text(input.text().unwrap_or_default()),
+            RedirectAction::Duplicate(target) if target == "-" => FdSource::Closed,
+            RedirectAction::Duplicate(target) => FdSource::Fd(
+                parse_fd(target).ok_or_else(|| format!("{}: ambiguous redirect", target))?,
+            ),
+        };
+        opened.push(OpenRedirection {
+            fd: redirection.fd,
+            source,
+        });
+    }
+    resolve_fds(&opened)?; // Check the copied descriptors
+    Ok(opened)
+}
+
+/// Describes an error opening or writing a redirection target, the way the
+/// shell reports it.
+pub fn error_reason(e: &io::Error) -> String {
+    match e.kind() {
+        ErrorKind::NotFound => "No such file or directory".to_string(),
+        ErrorKind::PermissionDenied => "Permission denied".to_string(),
+        _ if e.raw_os_error() == Some(libc::EISDIR) => "Is a directory".to_string(),
+        _ if e.raw_os_error() == Some(libc::EBADF) => "Bad file descriptor".to_string(),
+        _ => e.to_string(),
+    }
+}
+
+/// Where a file descriptor of a command points once its redirections are applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FdTarget {
+    /// The shell's own standard input, output or error
+    Shell(RawFd),
+    /// The source of the redirection at this index
+    Redirection(usize),
+}
+
+/// Applies opened redirections to a table of descriptors, starting from the
+/// shell's standard streams, to find where each descriptor of the command
+/// ends up. Built-ins, which run in the shell, write their output this way.
+///
+/// # Returns
+///
+/// * `Ok(fds)` - The open descriptors of the command and where they point
+/// * `Err(message)` - A redirection copies a descriptor that is not open
+pub fn resolve_fds(opened: &[OpenRedirection]) -> Result<BTreeMap<RawFd, FdTarget>, String> {
+    let mut fds: BTreeMap<RawFd, FdTarget> = (0..=2).map(|fd| (fd, FdTarget::Shell(fd))).collect();
+    for (index, redirection) in opened.iter().enumerate() {
+        match redirection.source {
+            FdSource::Fd(source) => {
+                let target = *fds
+                    .get(&source)
+                    .ok_or_else(|| format!("{}: Bad file descriptor", source))?;
+                fds.insert(redirection.fd, target);
+            }
+            FdSource::Closed => {
+                fds.remove(&redirection.fd);
             }
-            _ => break, // Not a redirection operator
+            _ => {
+                fds.insert(redirection.fd, FdTarget::Redirection(index));
+            }
+        }
+    }
+    Ok(fds)
+}
+
+/// Returns true if a raw word contains quotes or escapes.
+fn is_quoted(word: &str) -> bool {
+    word.contains(['\'', '"', '\\'])
+}
+
+/// Removes the quotes and escapes of a here-document delimiter. No expansion
+/// is done: `$` is literal in a delimiter.
+fn unquote_delimiter(word: &str) -> String {
+    let mut delimiter = String::new();
+    let mut quote = None;
+    let mut chars = word.chars();
+    while let Some(c) = chars.next() {
+        match (c, quote) {
+            ('\'' | '"', None) => quote = Some(c),
+            (c, Some(open)) if c == open => quote = None,
+            ('\\', None) => delimiter.extend(chars.next()),
+            _ => delimiter.push(c),
         }
     }
-    (command_args, red)
+    delimiter
+}
+
+/// Reads the bodies of the here-documents in the pipeline stages of a command
+/// line, from the lines following it, and attaches each to its operator: the
+/// operator word becomes `<<DELIMITER` (after its descriptor number, if any),
+/// a newline and the body, the form expected by `parse_redirections`.
+///
+/// A body ends at a line equal to the delimiter. With `<<-`, leading tabs are
+/// removed from its lines and from the delimiter line.
+///
+/// # Arguments
+///
+/// * `stages` - The raw words of each pipeline stage, in the order of the line,
+///   modified in place
+/// * `read_line` - Reads the next line (without its newline), None at the end
+///   of input, which also ends the document being read
+///
+/// # Returns
+///
+/// * `Ok(unterminated)` - The delimiters of the documents ended by the end of input
+/// * `Err(message)` - A here-document operator has no delimiter
+pub fn read_heredocs<'a>(
+    stages: impl IntoIterator<Item = &'a mut Vec<String>>,
+    mut read_line: impl FnMut() -> Option<String>,
+) -> Result<Vec<String>, String> {
+    let mut unterminated = Vec::new();
+    for words in stages {
+        let mut index = 0;
+        while index < words.len() {
+            let word = &words[index];
+            let (operator, attached) = match split_operator(word) {
+                Some((_, Operator::HereDoc, attached)) => {
+                    (&word[..word.len() - attached.len()], attached.to_string())
+                }
+                _ => {
+                    index += 1;
+                    continue;
+                }
+            };
+            // Keep the descriptor number, if any, and drop the `-` of `<<-`
+            let strip_tabs = operator.ends_with('-');
+            let operator = operator.trim_end_matches('-').to_string();
+            let raw_delimiter = if !attached.is_empty() {
+                attached
+            } else if index + 1 < words.len() && split_operator(&words[index + 1]).is_none() {
+                words.remove(index + 1)
+            } else {
+                return Err("syntax error: here-document without a delimiter".to_string());
+            };
+
+            let delimiter = unquote_delimiter(&raw_delimiter);
+            let mut body = String::new();
+            loop {
+                let Some(line) = read_line() else {
+                    unterminated.push(delimiter.clone());
+                    break;
+                };
+                let line = if strip_tabs {
+                    line.trim_start_matches('\t')
+                } else {
+                    &line
+                };
+                if line == delimiter {
+                    break;
+                }
+                body.push_str(line);
+                body.push('\n');
+            }
+            words[index] = format!("{}{}\n{}", operator, raw_delimiter, body);
+            index += 1;
+        }
+    }
+    Ok(unterminated)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn output(fd: RawFd, filename: &str, mode: RedirectionMode) -> Redirection {
+        let filename = filename.to_string();
+        Redirection {
+            fd,
+            action: RedirectAction::Output(RedirectFile { filename, mode }),
+        }
+    }
+
+    fn input(fd: RawFd, source: InputSource) -> Redirection {
+        Redirection {
+            fd,
+            action: RedirectAction::Input(source),
+        }
+    }
+
+    fn duplicate(fd: RawFd, target: &str) -> Redirection {
+        Redirection {
+            fd,
+            action: RedirectAction::Duplicate(target.to_string()),
+        }
+    }
+
     #[test]
     fn test_no_redirection() {
         let args = vec!["ls".to_string(), "-l".to_string()];
         let (remaining_args, redirections) = parse_redirections(&args);
         assert_eq!(remaining_args, args);
-        assert!(redirections.stdout_redirect.is_none());
-        assert!(redirections.stderr_redirect.is_none());
+        assert!(redirections.is_empty());
     }
 
     #[test]
     fn test_stdout_overwrite() {
-        let args = vec!["echo".to_string(), "hello".to_string(), ">".to_string(), "output.txt".to_string()];
+        let args = vec![
+            "echo".to_string(),
+            "hello".to_string(),
+            ">".to_string(),
+            "output.txt".to_string(),
+        ];
         let (remaining_args, redirections) = parse_redirections(&args);
         assert_eq!(remaining_args, vec!["echo", "hello"]);
-        assert!(redirections.stdout_redirect.is_some());
-        let stdout = redirections.stdout_redirect.unwrap();
-        assert_eq!(stdout.filename, "output.txt");
-        assert_eq!(stdout.mode, RedirectionMode::Overwrite);
+        assert_eq!(
+            redirections,
+            vec![output(1, "output.txt", RedirectionMode::Overwrite)]
+        );
     }
 
     #[test]
     fn test_stdout_append() {
-        let args = vec!["echo".to_string(), "hello".to_string(), ">>".to_string(), "output.txt".to_string()];
+        let args = vec![
+            "echo".to_string(),
+            "hello".to_string(),
+            ">>".to_string(),
+            "output.txt".to_string(),
+        ];
         let (remaining_args, redirections) = parse_redirections(&args);
         assert_eq!(remaining_args, vec!["echo", "hello"]);
-        assert!(redirections.stdout_redirect.is_some());
-        let stdout = redirections.stdout_redirect.unwrap();
-        assert_eq!(stdout.filename, "output.txt");
-        assert_eq!(stdout.mode, RedirectionMode::Append);
+        assert_eq!(
+            redirections,
+            vec![output(1, "output.txt", RedirectionMode::Append)]
+        );
     }
 
     #[test]
     fn test_stderr_redirection() {
-        let args = vec!["ls".to_string(), "/nonexistent".to_string(), "2>".to_string(), "error.txt".to_string()];
+        let args = vec![
+            "ls".to_string(),
+            "/nonexistent".to_string(),
+            "2>".to_string(),
+            "error.txt".to_string(),
+        ];
         let (remaining_args, redirections) = parse_redirections(&args);
         assert_eq!(remaining_args, vec!["ls", "/nonexistent"]);
-        assert!(redirections.stderr_redirect.is_some());
-        let stderr = redirections.stderr_redirect.unwrap();
-        assert_eq!(stderr.filename, "error.txt");
-        assert_eq!(stderr.mode, RedirectionMode::Overwrite);
+        assert_eq!(
+            redirections,
+            vec![output(2, "error.txt", RedirectionMode::Overwrite)]
+        );
     }
 
     #[test]
@@ -155,22 +525,201 @@ mod tests {
             ">".to_string(),
             "output.txt".to_string(),
             "2>".to_string(),
-            "error.txt".to_string()
+            "error.txt".to_string(),
         ];
         let (remaining_args, redirections) = parse_redirections(&args);
         assert_eq!(remaining_args, vec!["command"]);
-        assert!(redirections.stdout_redirect.is_some());
-        assert!(redirections.stderr_redirect.is_some());
+        assert_eq!(
+            redirections,
+            vec![
+                output(1, "output.txt", RedirectionMode::Overwrite),
+                output(2, "error.txt", RedirectionMode::Overwrite),
+            ]
+        );
     }
 
     #[test]
     fn test_explicit_fd_redirections() {
-        let args = vec!["echo".to_string(), "test".to_string(), "1>".to_string(), "out.txt".to_string()];
+        let args = vec![
+            "echo".to_string(),
+            "test".to_string(),
+            "1>".to_string(),
+            "out.txt".to_string(),
+        ];
         let (remaining_args, redirections) = parse_redirections(&args);
         assert_eq!(remaining_args, vec!["echo", "test"]);
-        assert!(redirections.stdout_redirect.is_some());
-        let stdout = redirections.stdout_redirect.unwrap();
-        assert_eq!(stdout.filename, "out.txt");
-        assert_eq!(stdout.mode, RedirectionMode::Overwrite);
+        assert_eq!(
+            redirections,
+            vec![output(1, "out.txt", RedirectionMode::Overwrite)]
+        );
     }
-}
\ No newline at end of file
+
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_redirections_anywhere() {
+        let args = strings(&["cat", "<in.txt", "-n", ">", "out.txt", "2>>err.txt"]);
+        let (remaining_args, redirections) = parse_redirections(&args);
+        assert_eq!(remaining_args, vec!["cat", "-n"]);
+        assert_eq!(
+            redirections,
+            vec![
+                input(0, InputSource::File("in.txt".to_string())),
+                output(1, "out.txt", RedirectionMode::Overwrite),
+                output(2, "err.txt", RedirectionMode::Append),
+            ]
+        );
+
+        // Quoted operators and operators without a target are plain words
+        let args = strings(&["echo", "'>'", "x", ">"]);
+        let (remaining_args, redirections) = parse_redirections(&args);
+        assert_eq!(remaining_args, args);
+        assert!(redirections.is_empty());
+    }
+
+    #[test]
+    fn test_input_redirections() {
+        let (remaining_args, redirections) = parse_redirections(&strings(&["cat", "<", "in.txt"]));
+        assert_eq!(remaining_args, vec!["cat"]);
+        assert_eq!(
+            redirections,
+            vec![input(0, InputSource::File("in.txt".to_string()))]
+        );
+
+        let (_, redirections) = parse_redirections(&strings(&["cat", "<<<", "\"$HOME\""]));
+        let here_string = InputSource::HereString("\"$HOME\"".to_string());
+        assert_eq!(redirections, vec![input(0, here_string.clone())]);
+        assert_eq!(here_string.text().unwrap(), "\"$HOME\"\n");
+    }
+
+    #[test]
+    fn test_fd_redirections() {
+        let args = strings(&["cmd", "2>&1", ">&2", "3>", "trace", "4<in", "0<&-", "12a>b"]);
+        let (remaining_args, redirections) = parse_redirections(&args);
+        assert_eq!(remaining_args, vec!["cmd", "12a>b"]);
+        assert_eq!(
+            redirections,
+            vec![
+                duplicate(2, "1"),
+                duplicate(1, "2"),
+                output(3, "trace", RedirectionMode::Overwrite),
+                input(4, InputSource::File("in".to_string())),
+                duplicate(0, "-"),
+            ]
+        );
+
+        // `&>` and `&>>` stand for `>file 2>&1` and `>>file 2>&1`
+        let (remaining_args, redirections) =
+            parse_redirections(&strings(&["cmd", "&>>all.log", "2&>x"]));
+        assert_eq!(remaining_args, vec!["cmd", "2&>x"]);
+        assert_eq!(
+            redirections,
+            vec![
+                output(1, "all.log", RedirectionMode::Append),
+                duplicate(2, "1")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_open_redirections() {
+        let dir = std::env::temp_dir().join(format!("redirect-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let log = dir.join("log").to_string_lossy().into_owned();
+
+        // `2>&1 >log`: stderr goes where stdout was, stdout to the file
+        let opened = open_redirections(&[
+            duplicate(2, "1"),
+            output(1, &log, RedirectionMode::Overwrite),
+        ])
+        .unwrap();
+        let fds = resolve_fds(&opened).unwrap();
+        assert_eq!(fds[&1], FdTarget::Redirection(1));
+        assert_eq!(fds[&2], FdTarget::Shell(1));
+        assert!(std::path::Path::new(&log).exists());
+
+        // `>log 2>&1`: both go to the file; `<&-` closes stdin
+        let opened = open_redirections(&[
+            output(1, &log, RedirectionMode::Append),
+            duplicate(2, "1"),
+            duplicate(0, "-"),
+        ])
+        .unwrap();
+        let fds = resolve_fds(&opened).unwrap();
+        assert_eq!(
+            (fds[&1], fds[&2]),
+            (FdTarget::Redirection(0), FdTarget::Redirection(0))
+        );
+        assert!(!fds.contains_key(&0));
+
+        // Errors name the target
+        let missing = dir.join("missing").to_string_lossy().into_owned();
+        let error = open_redirections(&[input(0, InputSource::File(missing.clone()))]).unwrap_err();
+        assert_eq!(error, format!("{}: No such file or directory", missing));
+        let dir_name = dir.to_string_lossy().into_owned();
+        let error =
+            open_redirections(&[output(1, &dir_name, RedirectionMode::Overwrite)]).unwrap_err();
+        assert_eq!(error, format!("{}: Is a directory", dir_name));
+        assert_eq!(
+            open_redirections(&[duplicate(1, "5")]).unwrap_err(),
+            "5: Bad file descriptor"
+        );
+        assert_eq!(
+            open_redirections(&[duplicate(1, "x")]).unwrap_err(),
+            "x: ambiguous redirect"
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_read_heredocs() {
+        let mut stages = vec![
+            strings(&["cat", "<<", "EOF", ">", "out"]),
+            strings(&["tr", "3<<-'END'", "x"]),
+        ];
+        let mut lines = ["hello $USER", "EOF", "\tindented", "\tEND", "rest"].into_iter();
+        let unterminated = read_heredocs(&mut stages, || lines.next().map(str::to_string)).unwrap();
+        assert!(unterminated.is_empty());
+        assert_eq!(lines.next(), Some("rest"));
+        assert_eq!(
+            stages[0],
+            strings(&["cat", "<<EOF\nhello $USER\n", ">", "out"])
+        );
+        assert_eq!(stages[1], strings(&["tr", "3<<'END'\nindented\n", "x"]));
+
+        let (remaining_args, redirections) = parse_redirections(&stages[0]);
+        assert_eq!(remaining_args, vec!["cat"]);
+        let body = "hello $USER\n".to_string();
+        assert_eq!(
+            redirections[0],
+            input(0, InputSource::HereDoc { body, expand: true })
+        );
+        let (_, redirections) = parse_redirections(&stages[1]);
+        let body = "indented\n".to_string();
+        assert_eq!(
+            redirections,
+            vec![input(
+                3,
+                InputSource::HereDoc {
+                    body,
+                    expand: false
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn test_read_heredocs_end_of_input() {
+        let mut stages = vec![strings(&["cat", "<<\"E\"OF"])];
+        let mut lines = ["line"].into_iter();
+        let unterminated = read_heredocs(&mut stages, || lines.next().map(str::to_string)).unwrap();
+        assert_eq!(unterminated, vec!["EOF"]);
+        assert_eq!(stages[0][1], "<<\"E\"OF\nline\n");
+
+        let mut stages = vec![strings(&["cat", "<<"])];
+        assert!(read_heredocs(&mut stages, || None).is_err());
+    }
+}
@@ -0,0 +1,74 @@
+//! Shell state module for the rust shell.
+//!
+//! This module holds the state that persists between command lines,
+//! such as the shell variables and functions, the command history, shell
+//! options, completion specs, background jobs and the exit status of the last
+//! command. It also holds the hook through which word expansion runs the
+//! commands of command substitutions.
+
+use crate::completion::CompletionSpec;
+use crate::history::History;
+use crate::jobs::JobTable;
+use crate::keymap::EditingMode;
+use crate::parser::Command;
+use crate::variables::Variables;
+use std::collections::BTreeMap;
+
+/// Runs the command line of a command substitution and returns its output.
+pub type CommandRunner = fn(&mut Shell, &str) -> Result<String, String>;
+
+/// State of the running shell, shared by the command loop and built-ins.
+#[derive(Debug, Default)]
+pub struct Shell {
+    /// Exit status of the most recently executed command, exposed as `$?`
+    pub last_status: i32,
+    /// Process id of the shell, expanded as `$$`; subshells keep their parent's
+    pub pid: u32,
+    /// Shell variables, expanded as `$NAME`
+    pub vars: Variables,
+    /// Shell functions defined with `name() { ...; }`, by name
+    pub functions: BTreeMap<String, Command>,
+    /// The arguments of the running function, expanded as `$1`, `$2`, ...
+    pub positional: Vec<String>,
+    /// Lines entered in this session, recalled with the arrow keys
+    pub history: History,
+    /// Keymap of the line editor, chosen with `set -o emacs` or `set -o vi`
+    pub editing_mode: EditingMode,
+    /// Completion specs registered with `complete`, by command name
+    pub completions: BTreeMap<String, CompletionSpec>,
+    /// Jobs started in the background with `&`
+    pub jobs: JobTable,
+    /// Runs the commands of `$(...)` substitutions, None if commands can't be run
+    pub run_substitution: Option<CommandRunner>,
+    /// Exit status of the last substitution run while expanding the current
+    /// command, which is the status of a command without a command name
+    pub substitution_status: Option<i32>,
+}
+
+impl Shell {
+    /// Creates the state for a new shell session, with variables taken from
+    /// the process environment.
+    pub fn new() -> Self {
+        Self {
+            last_status: 0,
+            pid: std::process::id(),
+            vars: Variables::from_env(),
+            functions: BTreeMap::new(),
+            positional: Vec::new(),
+            history: History::default(),
+            editing_mode: EditingMode::default(),
+            completions: BTreeMap::new(),
+            jobs: JobTable::default(),
+            run_substitution: None,
+            substitution_status: None,
+        }
+    }
+
+    /// Drops the state that belongs to the parent shell, in a forked subshell:
+    /// its history is saved by the parent, and its jobs are not the subshell's
+    /// children.
+    pub fn enter_subshell(&mut self) {
+        self.history.stop_saving();
+        self.jobs = JobTable::default();
+    }
+}
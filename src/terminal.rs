@@ -0,0 +1,202 @@
+//! Terminal ownership module for the rust shell.
+//!
+//! When the shell runs interactively, each foreground command is started in its
+//! own process group and given the controlling terminal, so that interactive and
+//! full-screen programs (vim, less, a python REPL) can read from it and receive
+//! keyboard signals. When the command finishes the shell takes the terminal back
+//! and restores the terminal modes it had before the command ran. The shell
+//! itself ignores the keyboard signals, which are meant for the foreground
+//! command. Ctrl-Z stops the foreground command, which becomes a stopped job:
+//! the shell takes the terminal back, keeping the command's terminal modes for
+//! when `fg` resumes it. Background jobs run in process groups of their own,
+//! without the terminal, until `fg` gives it to them.
+//!
+//! A subshell (a forked copy of the shell) runs without job control: the
+//! commands it starts stay in its process group.
+
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+
+/// The terminal state captured when the interactive shell started.
+struct ShellTerminal {
+    /// Close-on-exec duplicate of the controlling terminal (the shell's stdin),
+    /// still usable in a forked child after its stdin has been redirected
+    fd: libc::c_int,
+    /// Process group of the shell itself
+    pgid: libc::pid_t,
+    /// Terminal modes (cooked) to restore after a foreground command exits
+    modes: libc::termios,
+}
+
+// SAFETY: `termios` is plain data; the state is written once and then only read.
+unsafe impl Send for ShellTerminal {}
+unsafe impl Sync for ShellTerminal {}
+
+static SHELL_TERMINAL: OnceLock<ShellTerminal> = OnceLock::new();
+
+/// Set in a subshell, which leaves job control to its parent shell.
+static JOB_CONTROL_DISABLED: AtomicBool = AtomicBool::new(false);
+
+/// Returns the shell's terminal state, or None without job control.
+fn shell_terminal() -> Option<&'static ShellTerminal> {
+    if JOB_CONTROL_DISABLED.load(Ordering::Relaxed) {
+        return None;
+    }
+    SHELL_TERMINAL.get()
+}
+
+/// Returns true if the shell runs commands in process groups of their own,
+/// handing them the terminal: it is interactive and not a subshell.
+pub fn job_control_enabled() -> bool {
+    shell_terminal().is_some()
+}
+
+/// Turns job control off, in a subshell.
+pub fn disable_job_control() {
+    JOB_CONTROL_DISABLED.store(true, Ordering::Relaxed);
+}
+
+/// Returns the process group of the shell, which commands that run as part of
+/// the shell's own job, like those of a command substitution, join.
+pub fn shell_pgid() -> libc::pid_t {
+    // SAFETY: getpgrp has no preconditions.
+    unsafe { libc::getpgrp() }
+}
+
+/// Ignores Ctrl-Z in a subshell whose output the shell is waiting to read, and
+/// so in the commands it runs: stopped, they would never finish writing it.
+pub fn ignore_stop_signal() {
+    // SAFETY: changes the signal disposition of the current process only.
+    unsafe {
+        libc::signal(libc::SIGTSTP, libc::SIG_IGN);
+    }
+}
+
+/// Kills the current process with SIGINT, even though the shell ignores it: a
+/// subshell whose command was interrupted by Ctrl-C reports it to its parent.
+pub fn raise_interrupt() {
+    // SAFETY: changes the signal disposition of, and signals, the current process only.
+    unsafe {
+        libc::signal(libc::SIGINT, libc::SIG_DFL);
+        libc::raise(libc::SIGINT);
+    }
+}
+
+/// Terminal modes set by a job, restored when it's resumed in the foreground.
+#[derive(Clone, Copy)]
+pub struct TerminalModes(libc::termios);
+
+impl fmt::Debug for TerminalModes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("TerminalModes")
+    }
+}
+
+/// Returns the current terminal modes, or None without job control.
+pub fn terminal_modes() -> Option<TerminalModes> {
+    let terminal = shell_terminal()?;
+    // SAFETY: tcgetattr only writes the owned out-parameter.
+    unsafe {
+        let mut modes: libc::termios = std::mem::zeroed();
+        (libc::tcgetattr(terminal.fd, &mut modes) == 0).then_some(TerminalModes(modes))
+    }
+}
+
+/// Gives the terminal to process group `pgid`, to run a job in the foreground,
+/// with the terminal `modes` it had when it was stopped.
+/// Does nothing without job control.
+pub fn give_terminal(pgid: libc::pid_t, modes: Option<&TerminalModes>) {
+    if let Some(terminal) = shell_terminal() {
+        // SAFETY: plain libc calls on the shell's own terminal descriptor.
+        unsafe {
+            if let Some(TerminalModes(modes)) = modes {
+                libc::tcsetattr(terminal.fd, libc::TCSADRAIN, modes);
+            }
+            libc::tcsetpgrp(terminal.fd, pgid);
+        }
+    }
+}
+
+/// Signals the interactive shell ignores and its children handle by default:
+/// Ctrl-C, Ctrl-\ and Ctrl-Z are for the foreground command (at the prompt
+/// the line editor reads them as keys), and SIGTTOU would stop the shell when
+/// it reclaims the terminal.
+const SHELL_IGNORED_SIGNALS: [libc::c_int; 4] =
+    [libc::SIGINT, libc::SIGQUIT, libc::SIGTSTP, libc::SIGTTOU];
+
+/// Prepares the shell for handing its terminal to child process groups.
+/// Must be called once at startup, and only when stdin is a terminal.
+///
+/// # Returns
+///
+/// * `Ok(())` - The shell owns the terminal and job control is enabled
+/// * `Err(e)` - The terminal state could not be read or claimed
+pub fn init_interactive() -> io::Result<()> {
+    // SAFETY: plain libc calls on a valid file descriptor with owned out-parameters.
+    unsafe {
+        let fd = libc::fcntl(libc::STDIN_FILENO, libc::F_DUPFD_CLOEXEC, 10);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // Reclaiming the terminal from a background group would otherwise stop the shell
+        libc::signal(libc::SIGTTOU, libc::SIG_IGN);
+
+        let pgid = libc::getpgrp();
+        if libc::tcsetpgrp(fd, pgid) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut modes: libc::termios = std::mem::zeroed();
+        if libc::tcgetattr(fd, &mut modes) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        for signal in SHELL_IGNORED_SIGNALS {
+            libc::signal(signal, libc::SIG_IGN);
+        }
+        let _ = SHELL_TERMINAL.set(ShellTerminal { fd, pgid, modes });
+    }
+    Ok(())
+}
+
+/// Takes the terminal back for the shell after a foreground command finished,
+/// restoring the terminal modes the command may have changed.
+/// Does nothing without job control.
+pub fn reclaim_terminal() {
+    if let Some(terminal) = shell_terminal() {
+        // SAFETY: plain libc calls on the shell's own terminal descriptor.
+        unsafe {
+            libc::tcsetpgrp(terminal.fd, terminal.pgid);
+            libc::tcsetattr(terminal.fd, libc::TCSADRAIN, &terminal.modes);
+        }
+    }
+}
+
+/// Runs in a forked child right before `exec`: joins the process group `pgid`
+/// (0 starts a new group led by the child), takes the terminal if the command
+/// runs in the foreground, and restores default handling of the signals the
+/// shell ignores. Does nothing without job control.
+///
+/// Only async-signal-safe functions are called, as required after `fork`.
+pub fn setup_child_process(pgid: libc::pid_t, foreground: bool) -> io::Result<()> {
+    let Some(terminal) = shell_terminal() else {
+        return Ok(());
+    };
+    // SAFETY: async-signal-safe libc calls on the child's own process.
+    unsafe {
+        if libc::setpgid(0, pgid) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if foreground {
+            // Taking the terminal before exec means the program can use it right away
+            libc::tcsetpgrp(terminal.fd, libc::getpgrp());
+        }
+        for signal in SHELL_IGNORED_SIGNALS {
+            libc::signal(signal, libc::SIG_DFL);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,206 @@
+//! Shell variable storage module for the rust shell.
+//!
+//! This module holds the shell's variables, initialized from the process
+//! environment when the shell starts, and validates variable names.
+//! Variables are local to the shell unless exported, in which case they
+//! form the environment of the commands the shell runs. Assignments
+//! preceding a command (`NAME=value cmd`) are made temporarily and undone
+//! once the command has started.
+
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+
+/// A single shell variable.
+#[derive(Debug, Clone, Default)]
+struct Variable {
+    /// The value, or None for a name exported before being assigned (`export NAME`)
+    value: Option<String>,
+    /// Whether the variable is passed to child processes
+    exported: bool,
+}
+
+/// The previous state of variables assigned with [`Variables::set_temporarily`],
+/// used to undo those assignments.
+#[derive(Debug, Default)]
+pub struct SavedVariables {
+    /// Each replaced variable by name, None if it did not exist
+    previous: Vec<(String, Option<Variable>)>,
+}
+
+/// The shell's variable table.
+#[derive(Debug, Clone, Default)]
+pub struct Variables {
+    /// Variables by name
+    values: HashMap<String, Variable>,
+}
+
+impl Variables {
+    /// Creates a variable table holding every variable of the process environment,
+    /// all of them exported.
+    pub fn from_env() -> Self {
+        let values = env::vars()
+            .map(|(name, value)| {
+                let variable = Variable {
+                    value: Some(value),
+                    exported: true,
+                };
+                (name, variable)
+            })
+            .collect();
+        Self { values }
+    }
+
+    /// Returns the value of a variable, or None if it is unset.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values.get(name).and_then(|v| v.value.as_deref())
+    }
+
+    /// Sets a variable, creating it as a shell-local variable if needed.
+    /// An exported variable stays exported.
+    pub fn set(&mut self, name: &str, value: &str) {
+        self.values.entry(name.to_string()).or_default().value = Some(value.to_string());
+    }
+
+    /// Marks a variable as exported, optionally assigning it a value first.
+    pub fn export(&mut self, name: &str, value: Option<&str>) {
+        let variable = self.values.entry(name.to_string()).or_default();
+        if let Some(value) = value {
+            variable.value = Some(value.to_string());
+        }
+        variable.exported = true;
+    }
+
+    /// Removes a variable entirely, including its export attribute.
+    pub fn unset(&mut self, name: &str) {
+        self.values.remove(name);
+    }
+
+    /// Sets and exports a variable until `saved` is passed to [`Variables::restore`].
+    pub fn set_temporarily(&mut self, name: &str, value: &str, saved: &mut SavedVariables) {
+        if !saved
+            .previous
+            .iter()
+            .any(|(saved_name, _)| saved_name == name)
+        {
+            let previous = self.values.get(name).cloned();
+            saved.previous.push((name.to_string(), previous));
+        }
+        let variable = Variable {
+            value: Some(value.to_string()),
+            exported: true,
+        };
+        self.values.insert(name.to_string(), variable);
+    }
+
+    /// Undoes the temporary assignments recorded in `saved`.
+    pub fn restore(&mut self, saved: SavedVariables) {
+        for (name, previous) in saved.previous {
+            match previous {
+                Some(variable) => self.values.insert(name, variable),
+                None => self.values.remove(&name),
+            };
+        }
+    }
+
+    /// Returns all variables that have a value, sorted by name.
+    pub fn all(&self) -> BTreeMap<&str, &str> {
+        self.values
+            .iter()
+            .filter_map(|(name, v)| Some((name.as_str(), v.value.as_deref()?)))
+            .collect()
+    }
+
+    /// Returns the exported variables that have a value, sorted by name:
+    /// exactly the environment given to child processes.
+    pub fn exported(&self) -> BTreeMap<&str, &str> {
+        self.values
+            .iter()
+            .filter(|(_, v)| v.exported)
+            .filter_map(|(name, v)| Some((name.as_str(), v.value.as_deref()?)))
+            .collect()
+    }
+}
+
+/// Returns true if `name` is a valid variable name: a letter or underscore
+/// followed by letters, digits or underscores.
+pub fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) if first.is_ascii_alphabetic() || first == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_and_get() {
+        let mut vars = Variables::default();
+        assert_eq!(vars.get("FOO"), None);
+        vars.set("FOO", "bar");
+        assert_eq!(vars.get("FOO"), Some("bar"));
+        vars.set("FOO", "baz");
+        assert_eq!(vars.get("FOO"), Some("baz"));
+    }
+
+    #[test]
+    fn test_export() {
+        let mut vars = Variables::default();
+        vars.set("LOCAL", "1");
+        vars.export("EXPORTED", Some("2"));
+        vars.export("LATER", None);
+        assert_eq!(
+            vars.exported().into_iter().collect::<Vec<_>>(),
+            vec![("EXPORTED", "2")]
+        );
+
+        // Assigning a value to an exported name keeps it exported
+        vars.set("LATER", "3");
+        assert_eq!(vars.exported().get("LATER"), Some(&"3"));
+        assert_eq!(vars.all().len(), 3);
+        assert!(!vars.exported().contains_key("LOCAL"));
+    }
+
+    #[test]
+    fn test_unset() {
+        let mut vars = Variables::default();
+        vars.export("GONE", Some("1"));
+        vars.unset("GONE");
+        assert_eq!(vars.get("GONE"), None);
+        assert!(vars.exported().is_empty());
+
+        // A new variable with the same name is local again
+        vars.set("GONE", "2");
+        assert!(vars.exported().is_empty());
+    }
+
+    #[test]
+    fn test_temporary_assignments() {
+        let mut vars = Variables::default();
+        vars.set("LOCAL", "old");
+        let mut saved = SavedVariables::default();
+        vars.set_temporarily("LOCAL", "new", &mut saved);
+        vars.set_temporarily("FRESH", "1", &mut saved);
+        vars.set_temporarily("LOCAL", "newer", &mut saved);
+        assert_eq!(vars.exported().get("LOCAL"), Some(&"newer"));
+        assert_eq!(vars.exported().get("FRESH"), Some(&"1"));
+
+        vars.restore(saved);
+        assert_eq!(vars.get("LOCAL"), Some("old"));
+        assert_eq!(vars.get("FRESH"), None);
+        assert!(vars.exported().is_empty());
+    }
+
+    #[test]
+    fn test_valid_names() {
+        assert!(is_valid_name("HOME"));
+        assert!(is_valid_name("_private1"));
+        assert!(!is_valid_name("1abc"));
+        assert!(!is_valid_name("a-b"));
+        assert!(!is_valid_name(""));
+    }
+}
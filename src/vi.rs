@@ -0,0 +1,743 @@
+//! Vi editing mode module for the rust shell.
+//!
+//! This module implements the vi keymap of the line editor, selected with
+//! `set -o vi`. Lines start in insert mode, where keys edit the line much as in
+//! emacs mode; Esc switches to normal mode, where keys form commands: motions
+//! (`h l w b e W B E 0 ^ $ f t F T`), operators applied to a motion (`d c y`,
+//! doubled to apply to the whole line), shorthands (`x X D C s S Y`), pasting
+//! (`p P`), entering insert mode (`i a I A`) and `.` to repeat the last change.
+//! Commands may be preceded by a count.
+
+use crate::editor::{KillRing, LineBuffer};
+use crate::keymap::{vi_insert_command, EditCommand};
+use termion::event::Key;
+
+/// Prompt prefix shown in insert mode.
+const INSERT_INDICATOR: &str = "(ins) ";
+/// Prompt prefix shown in normal mode.
+const NORMAL_INDICATOR: &str = "(cmd) ";
+
+/// The current vi mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum ViMode {
+    /// Keys insert text
+    #[default]
+    Insert,
+    /// Keys are commands
+    Normal,
+}
+
+/// A cursor motion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Motion {
+    /// `h`: one character left
+    Left,
+    /// `l`: one character right
+    Right,
+    /// `w`/`W`: start of the next word (true for blank-delimited words)
+    WordForward(bool),
+    /// `b`/`B`: start of the current or previous word
+    WordBackward(bool),
+    /// `e`/`E`: end of the current or next word
+    WordEnd(bool),
+    /// `0`: start of the line
+    LineStart,
+    /// `^`: first non-blank character
+    FirstNonBlank,
+    /// `$`: end of the line
+    LineEnd,
+    /// `f`/`t`/`F`/`T`: to (or, with `till`, next to) a character
+    Find {
+        target: char,
+        forward: bool,
+        till: bool,
+    },
+    /// A doubled operator (`dd`): the whole line
+    WholeLine,
+}
+
+impl Motion {
+    /// Returns true if an operator applied to this motion includes the
+    /// character the motion ends on.
+    fn is_inclusive(self) -> bool {
+        matches!(
+            self,
+            Motion::WordEnd(_) | Motion::Find { forward: true, .. }
+        )
+    }
+}
+
+/// An operator applied to the text covered by a motion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operator {
+    /// `d`: delete the text
+    Delete,
+    /// `c`: delete the text and enter insert mode
+    Change,
+    /// `y`: copy the text
+    Yank,
+}
+
+/// Where the cursor goes when entering insert mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InsertPosition {
+    /// `i`: before the cursor
+    BeforeCursor,
+    /// `a`: after the cursor
+    AfterCursor,
+    /// `I`: before the first non-blank character
+    LineStart,
+    /// `A`: at the end of the line
+    LineEnd,
+}
+
+/// A complete normal mode command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NormalCommand {
+    /// Move the cursor
+    Move(Motion),
+    /// Apply an operator to the text between the cursor and a motion's target
+    Operate(Operator, Motion),
+    /// Paste the last deleted or copied text after (`p`) or before (`P`) the cursor
+    Paste { after: bool },
+    /// Enter insert mode
+    Insert(InsertPosition),
+}
+
+impl NormalCommand {
+    /// Returns true if the command modifies the line, and so is repeated by `.`.
+    fn is_change(self) -> bool {
+        !matches!(
+            self,
+            NormalCommand::Move(_) | NormalCommand::Operate(Operator::Yank, _)
+        )
+    }
+}
+
+/// The result of parsing the keys typed in normal mode so far.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Parsed {
+    /// More keys are needed
+    Incomplete,
+    /// The keys don't form a command
+    Invalid,
+    /// A command with its count, if one was typed
+    Command(Option<usize>, NormalCommand),
+    /// `.`: repeat the last change, with a new count if one was typed
+    Repeat(Option<usize>),
+    /// `k` (true) or `j` (false): recall an older or newer history entry
+    History(bool),
+}
+
+/// Reads a count at the start of `keys`, returning it and the remaining keys.
+fn parse_count(keys: &[char]) -> (Option<usize>, &[char]) {
+    let digits = match keys.first() {
+        Some('1'..='9') => keys.iter().take_while(|c| c.is_ascii_digit()).count(),
+        _ => 0,
+    };
+    let count = keys[..digits].iter().collect::<String>().parse().ok();
+    (count, &keys[digits..])
+}
+
+/// Parses a motion, returning None if more keys are needed and
+/// `Some(None)` if the keys are not a motion.
+fn parse_motion(keys: &[char]) -> Option<Option<Motion>> {
+    let motion = match keys {
+        [] => return None,
+        ['h', ..] => Motion::Left,
+        ['l', ..] | [' ', ..] => Motion::Right,
+        ['w', ..] => Motion::WordForward(false),
+        ['W', ..] => Motion::WordForward(true),
+        ['b', ..] => Motion::WordBackward(false),
+        ['B', ..] => Motion::WordBackward(true),
+        ['e', ..] => Motion::WordEnd(false),
+        ['E', ..] => Motion::WordEnd(true),
+        ['0', ..] => Motion::LineStart,
+        ['^', ..] => Motion::FirstNonBlank,
+        ['$', ..] => Motion::LineEnd,
+        ['f' | 't' | 'F' | 'T'] => return None,
+        [kind @ ('f' | 't' | 'F' | 'T'), target, ..] => Motion::Find {
+            target: *target,
+            forward: matches!(kind, 'f' | 't'),
+            till: matches!(kind, 't' | 'T'),
+        },
+        _ => return Some(None),
+    };
+    Some(Some(motion))
+}
+
+/// Parses the keys typed in normal mode.
+fn parse_normal(keys: &[char]) -> Parsed {
+    use NormalCommand::*;
+
+    let (count, rest) = parse_count(keys);
+    let command = match rest {
+        [] => return Parsed::Incomplete,
+        ['.'] => return Parsed::Repeat(count),
+        ['k'] => return Parsed::History(true),
+        ['j'] => return Parsed::History(false),
+        ['i'] => Insert(InsertPosition::BeforeCursor),
+        ['a'] => Insert(InsertPosition::AfterCursor),
+        ['I'] => Insert(InsertPosition::LineStart),
+        ['A'] => Insert(InsertPosition::LineEnd),
+        ['p'] => Paste { after: true },
+        ['P'] => Paste { after: false },
+        ['x'] => Operate(Operator::Delete, Motion::Right),
+        ['X'] => Operate(Operator::Delete, Motion::Left),
+        ['D'] => Operate(Operator::Delete, Motion::LineEnd),
+        ['C'] => Operate(Operator::Change, Motion::LineEnd),
+        ['s'] => Operate(Operator::Change, Motion::Right),
+        ['S'] => Operate(Operator::Change, Motion::WholeLine),
+        ['Y'] => Operate(Operator::Yank, Motion::WholeLine),
+        [op @ ('d' | 'c' | 'y'), motion_keys @ ..] => {
+            let operator = match op {
+                'd' => Operator::Delete,
+                'c' => Operator::Change,
+                _ => Operator::Yank,
+            };
+            let (motion_count, motion_keys) = parse_count(motion_keys);
+            let count = match (count, motion_count) {
+                (Some(a), Some(b)) => Some(a * b),
+                (a, b) => a.or(b),
+            };
+            let motion = match motion_keys {
+                [key] if key == op => Some(Some(Motion::WholeLine)),
+                _ => parse_motion(motion_keys),
+            };
+            return match motion {
+                None => Parsed::Incomplete,
+                Some(None) => Parsed::Invalid,
+                Some(Some(motion)) => Parsed::Command(count, Operate(operator, motion)),
+            };
+        }
+        _ => match parse_motion(rest) {
+            None => return Parsed::Incomplete,
+            Some(None) => return Parsed::Invalid,
+            Some(Some(motion)) => Move(motion),
+        },
+    };
+    Parsed::Command(count, command)
+}
+
+/// Character classes used to find word boundaries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CharClass {
+    /// Whitespace
+    Blank,
+    /// Letters, digits and underscores (any non-blank for blank-delimited words)
+    Word,
+    /// Other non-blank characters
+    Punctuation,
+}
+
+/// Returns the class of `c`; with `big`, words are delimited by blanks only.
+fn char_class(c: char, big: bool) -> CharClass {
+    if c.is_whitespace() {
+        CharClass::Blank
+    } else if big || c.is_alphanumeric() || c == '_' {
+        CharClass::Word
+    } else {
+        CharClass::Punctuation
+    }
+}
+
+/// Returns the index of the start of the next word after `pos`.
+fn next_word_start(chars: &[char], pos: usize, big: bool) -> usize {
+    let class = |i: usize| char_class(chars[i], big);
+    let mut i = pos;
+    if i < chars.len() && class(i) != CharClass::Blank {
+        let start_class = class(i);
+        while i < chars.len() && class(i) == start_class {
+            i += 1;
+        }
+    }
+    while i < chars.len() && class(i) == CharClass::Blank {
+        i += 1;
+    }
+    i
+}
+
+/// Returns the index of the start of the word before `pos`.
+fn previous_word_start(chars: &[char], pos: usize, big: bool) -> usize {
+    let class = |i: usize| char_class(chars[i], big);
+    let mut i = pos;
+    while i > 0 && class(i - 1) == CharClass::Blank {
+        i -= 1;
+    }
+    if i > 0 {
+        let word_class = class(i - 1);
+        while i > 0 && class(i - 1) == word_class {
+            i -= 1;
+        }
+    }
+    i
+}
+
+/// Returns the index of the last character of the word after `pos`.
+fn word_end(chars: &[char], pos: usize, big: bool) -> usize {
+    let class = |i: usize| char_class(chars[i], big);
+    let mut i = pos + 1;
+    while i < chars.len() && class(i) == CharClass::Blank {
+        i += 1;
+    }
+    if i >= chars.len() {
+        return chars.len().saturating_sub(1).max(pos);
+    }
+    let word_class = class(i);
+    while i + 1 < chars.len() && class(i + 1) == word_class {
+        i += 1;
+    }
+    i
+}
+
+/// Returns the character index a motion moves to from `pos`, repeated `count`
+/// times, or None if the motion fails (e.g. the character to find is missing).
+fn motion_target(chars: &[char], pos: usize, motion: Motion, count: usize) -> Option<usize> {
+    let len = chars.len();
+    let repeat = |step: &dyn Fn(usize) -> usize| (0..count).fold(pos, |i, _| step(i));
+    let target = match motion {
+        Motion::Left if pos > 0 => pos.saturating_sub(count),
+        Motion::Right if pos < len => (pos + count).min(len),
+        Motion::Left | Motion::Right => return None,
+        Motion::WordForward(big) => repeat(&|i| next_word_start(chars, i, big)),
+        Motion::WordBackward(big) => repeat(&|i| previous_word_start(chars, i, big)),
+        Motion::WordEnd(big) => repeat(&|i| word_end(chars, i, big)),
+        Motion::LineStart | Motion::WholeLine => 0,
+        Motion::FirstNonBlank => chars.iter().take_while(|c| c.is_whitespace()).count(),
+        Motion::LineEnd => len,
+        Motion::Find {
+            target,
+            forward: true,
+            till,
+        } => {
+            let mut i = pos;
+            for _ in 0..count {
+                i += 1 + chars.get(i + 1..)?.iter().position(|&c| c == target)?;
+            }
+            if till {
+                i - 1
+            } else {
+                i
+            }
+        }
+        Motion::Find {
+            target,
+            forward: false,
+            till,
+        } => {
+            let mut i = pos;
+            for _ in 0..count {
+                i = chars[..i].iter().rposition(|&c| c == target)?;
+            }
+            if till {
+                i + 1
+            } else {
+                i
+            }
+        }
+    };
+    Some(target)
+}
+
+/// Converts a character index in `text` into a byte offset.
+fn byte_offset(text: &str, index: usize) -> usize {
+    text.char_indices()
+        .nth(index)
+        .map_or(text.len(), |(offset, _)| offset)
+}
+
+/// A change made in normal mode, kept so that `.` can repeat it.
+#[derive(Debug, Clone, PartialEq)]
+struct Change {
+    /// The count the command was given
+    count: Option<usize>,
+    /// The command
+    command: NormalCommand,
+    /// Text typed in the insert mode the command entered, if any
+    inserted: String,
+}
+
+/// The state of the vi keymap, kept from one line to the next.
+#[derive(Debug, Default)]
+pub struct ViState {
+    /// The current mode
+    mode: ViMode,
+    /// Keys typed in normal mode that don't form a complete command yet
+    pending: Vec<char>,
+    /// The last change, repeated by `.`
+    last_change: Option<Change>,
+    /// The change being made in insert mode, recording the typed text
+    recording: Option<Change>,
+}
+
+impl ViState {
+    /// Prepares for reading a new line, which starts in insert mode.
+    pub fn start_line(&mut self) {
+        self.mode = ViMode::Insert;
+        self.pending.clear();
+        self.recording = None;
+    }
+
+    /// Returns the mode indicator shown before the prompt.
+    pub fn indicator(&self) -> &'static str {
+        match self.mode {
+            ViMode::Insert => INSERT_INDICATOR,
+            ViMode::Normal => NORMAL_INDICATOR,
+        }
+    }
+
+    /// Keeps the cursor on a character in normal mode, where it can't be past
+    /// the end of the line.
+    pub fn clamp_cursor(&self, buffer: &mut LineBuffer) {
+        if self.mode == ViMode::Normal && buffer.cursor() == buffer.text().len() {
+            buffer.move_left();
+        }
+    }
+
+    /// Handles one key press in vi mode. Normal mode commands edit `buffer`
+    /// directly, keeping deleted and copied text in `kill_ring`; anything else
+    /// is returned as a command for the line editor to run.
+    pub fn handle_key(
+        &mut self,
+        key: Key,
+        buffer: &mut LineBuffer,
+        kill_ring: &mut KillRing,
+    ) -> Option<EditCommand> {
+        match self.mode {
+            ViMode::Insert => self.handle_insert_key(key, buffer, kill_ring),
+            ViMode::Normal => self.handle_normal_key(key, buffer, kill_ring),
+        }
+    }
+
+    /// Handles a key in insert mode.
+    fn handle_insert_key(
+        &mut self,
+        key: Key,
+        buffer: &mut LineBuffer,
+        kill_ring: &mut KillRing,
+    ) -> Option<EditCommand> {
+        match key {
+            Key::Esc => {
+                self.enter_normal_mode(buffer);
+                None
+            }
+            // Esc quickly followed by another key arrives as Alt
+            Key::Alt(c) => {
+                self.enter_normal_mode(buffer);
+                self.handle_normal_key(Key::Char(c), buffer, kill_ring)
+            }
+            key => {
+                let command = vi_insert_command(key)?;
+                if let Some(change) = &mut self.recording {
+                    match command {
+                        EditCommand::Insert(c) => change.inserted.push(c),
+                        EditCommand::BackwardDeleteChar => {
+                            change.inserted.pop();
+                        }
+                        _ => {}
+                    }
+                }
+                Some(command)
+            }
+        }
+    }
+
+    /// Handles a key in normal mode.
+    fn handle_normal_key(
+        &mut self,
+        key: Key,
+        buffer: &mut LineBuffer,
+        kill_ring: &mut KillRing,
+    ) -> Option<EditCommand> {
+        let c = match key {
+            Key::Char('\n') => return Some(EditCommand::AcceptLine),
+            Key::Ctrl('l') => return Some(EditCommand::ClearScreen),
+            Key::Ctrl('c') => return Some(EditCommand::Interrupt),
+            Key::Ctrl('d') => return Some(EditCommand::DeleteCharOrEof),
+            Key::Up => return Some(EditCommand::PreviousHistory),
+            Key::Down => return Some(EditCommand::NextHistory),
+            Key::Esc => {
+                self.pending.clear();
+                return None;
+            }
+            Key::Left | Key::Backspace => 'h',
+            Key::Right => 'l',
+            Key::Home => '0',
+            Key::End => '$',
+            Key::Char(c) => c,
+            _ => return None,
+        };
+
+        self.pending.push(c);
+        let parsed = parse_normal(&self.pending);
+        if parsed != Parsed::Incomplete {
+            self.pending.clear();
+        }
+        match parsed {
+            Parsed::Incomplete | Parsed::Invalid => None,
+            Parsed::History(older) => Some(if older {
+                EditCommand::PreviousHistory
+            } else {
+                EditCommand::NextHistory
+            }),
+            Parsed::Repeat(count) => {
+                self.repeat_change(count, buffer, kill_ring);
+                None
+            }
+            Parsed::Command(count, command) => {
+                let inserting = self.run(count, command, buffer, kill_ring);
+                if command.is_change() {
+                    let change = Change {
+                        count,
+                        command,
+                        inserted: String::new(),
+                    };
+                    if inserting {
+                        self.recording = Some(change);
+                    } else {
+                        self.last_change = Some(change);
+                    }
+                }
+                None
+            }
+        }
+    }
+
+    /// Switches to normal mode, moving the cursor back onto the last inserted
+    /// character, and completes the change being recorded.
+    fn enter_normal_mode(&mut self, buffer: &mut LineBuffer) {
+        if self.mode == ViMode::Insert {
+            buffer.move_left();
+        }
+        self.mode = ViMode::Normal;
+        if let Some(change) = self.recording.take() {
+            self.last_change = Some(change);
+        }
+    }
+
+    /// Repeats the last change (`.`), optionally with a new count.
+    fn repeat_change(
+        &mut self,
+        count: Option<usize>,
+        buffer: &mut LineBuffer,
+        kill_ring: &mut KillRing,
+    ) {
+        let Some(change) = self.last_change.clone() else {
+            return;
+        };
+        if self.run(count.or(change.count), change.command, buffer, kill_ring) {
+            buffer.insert(&change.inserted);
+            self.enter_normal_mode(buffer);
+            self.last_change = Some(change);
+        }
+    }
+
+    /// Runs a normal mode command. Returns true if it entered insert mode.
+    fn run(
+        &mut self,
+        count: Option<usize>,
+        command: NormalCommand,
+        buffer: &mut LineBuffer,
+        kill_ring: &mut KillRing,
+    ) -> bool {
+        let chars: Vec<char> = buffer.text().chars().collect();
+        let pos = buffer.before_cursor().chars().count();
+        let count = count.unwrap_or(1);
+
+        match command {
+            NormalCommand::Move(motion) => {
+                if let Some(target) = motion_target(&chars, pos, motion, count) {
+                    buffer.set_cursor(byte_offset(buffer.text(), target));
+                }
+                self.clamp_cursor(buffer);
+                false
+            }
+            NormalCommand::Operate(operator, motion) => {
+                self.operate(operator, motion, count, buffer, kill_ring)
+            }
+            NormalCommand::Paste { after } => {
+                let Some(text) = kill_ring.yank().map(|text| text.repeat(count)) else {
+                    return false;
+                };
+                if after {
+                    buffer.move_right();
+                }
+                buffer.insert(&text);
+                buffer.move_left(); // Onto the last pasted character
+                false
+            }
+            NormalCommand::Insert(position) => {
+                match position {
+                    InsertPosition::BeforeCursor => {}
+                    InsertPosition::AfterCursor => {
+                        buffer.move_right();
+                    }
+                    InsertPosition::LineStart => {
+                        let first = motion_target(&chars, pos, Motion::FirstNonBlank, 1);
+                        buffer.set_cursor(byte_offset(buffer.text(), first.unwrap_or(0)));
+                    }
+                    InsertPosition::LineEnd => buffer.move_end(),
+                }
+                self.mode = ViMode::Insert;
+                true
+            }
+        }
+    }
+
+    /// Applies an operator to the text between the cursor and the target of
+    /// `motion`. Returns true if it entered insert mode.
+    fn operate(
+        &mut self,
+        operator: Operator,
+        motion: Motion,
+        count: usize,
+        buffer: &mut LineBuffer,
+        kill_ring: &mut KillRing,
+    ) -> bool {
+        let chars: Vec<char> = buffer.text().chars().collect();
+        let pos = buffer.before_cursor().chars().count();
+
+        // Like vi, `cw` on a word changes only up to the end of the word
+        let motion = match (operator, motion) {
+            (Operator::Change, Motion::WordForward(big))
+                if chars.get(pos).is_some_and(|c| !c.is_whitespace()) =>
+            {
+                Motion::WordEnd(big)
+            }
+            _ => motion,
+        };
+        let Some(target) = motion_target(&chars, pos, motion, count) else {
+            return false;
+        };
+        let range = match motion {
+            Motion::WholeLine => 0..chars.len(),
+            _ if target >= pos => {
+                let end = target + usize::from(motion.is_inclusive());
+                pos..end.min(chars.len())
+            }
+            _ => target..pos,
+        };
+        let text = buffer.text();
+        let bytes = byte_offset(text, range.start)..byte_offset(text, range.end);
+
+        match operator {
+            Operator::Yank => {
+                kill_ring.kill(&text[bytes.clone()], false, false);
+                buffer.set_cursor(bytes.start.min(buffer.cursor()));
+                false
+            }
+            Operator::Delete => {
+                let deleted = buffer.remove(bytes);
+                kill_ring.kill(&deleted, false, false);
+                self.clamp_cursor(buffer);
+                false
+            }
+            Operator::Change => {
+                let deleted = buffer.remove(bytes);
+                kill_ring.kill(&deleted, false, false);
+                self.mode = ViMode::Insert;
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Types `keys` in normal mode on a line holding `text`, with the cursor at
+    /// character `pos`, and returns the resulting line and cursor (as a character index).
+    fn normal(text: &str, pos: usize, keys: &str) -> (String, usize) {
+        let mut buffer = LineBuffer::default();
+        buffer.set_text(text);
+        buffer.set_cursor(byte_offset(text, pos));
+        let mut kill_ring = KillRing::default();
+        let mut vi = ViState {
+            mode: ViMode::Normal,
+            ..ViState::default()
+        };
+        for c in keys.chars() {
+            let key = if c == '\x1b' { Key::Esc } else { Key::Char(c) };
+            if let Some(EditCommand::Insert(c)) = vi.handle_key(key, &mut buffer, &mut kill_ring) {
+                buffer.insert(&c.to_string());
+            }
+        }
+        let cursor = buffer.before_cursor().chars().count();
+        (buffer.text().to_string(), cursor)
+    }
+
+    #[test]
+    fn test_parse_normal() {
+        use NormalCommand::*;
+        assert_eq!(parse_normal(&['3']), Parsed::Incomplete);
+        assert_eq!(
+            parse_normal(&['3', 'w']),
+            Parsed::Command(Some(3), Move(Motion::WordForward(false)))
+        );
+        assert_eq!(
+            parse_normal(&['2', 'd', '3', 'w']),
+            Parsed::Command(
+                Some(6),
+                Operate(Operator::Delete, Motion::WordForward(false))
+            )
+        );
+        assert_eq!(
+            parse_normal(&['c', 'c']),
+            Parsed::Command(None, Operate(Operator::Change, Motion::WholeLine))
+        );
+        assert_eq!(parse_normal(&['d', 'f']), Parsed::Incomplete);
+        assert_eq!(parse_normal(&['d', 'z']), Parsed::Invalid);
+        assert_eq!(parse_normal(&['.']), Parsed::Repeat(None));
+    }
+
+    #[test]
+    fn test_motions() {
+        let text = "git commit -m 'fix: typo'";
+        assert_eq!(normal(text, 0, "w").1, 4);
+        assert_eq!(normal(text, 0, "3w").1, 12);
+        assert_eq!(normal(text, 0, "3W").1, 14);
+        assert_eq!(normal(text, 0, "e").1, 2);
+        assert_eq!(normal(text, 11, "b").1, 4);
+        assert_eq!(normal(text, 11, "B").1, 4);
+        assert_eq!(normal(text, 0, "$").1, 24);
+        assert_eq!(normal(text, 10, "0").1, 0);
+        assert_eq!(normal(text, 0, "fm").1, 6);
+        assert_eq!(normal(text, 0, "2fm").1, 7);
+        assert_eq!(normal(text, 0, "tm").1, 5);
+        assert_eq!(normal(text, 12, "Fc").1, 4);
+        assert_eq!(normal(text, 0, "fz").1, 0);
+    }
+
+    #[test]
+    fn test_operators() {
+        assert_eq!(normal("echo one two", 5, "dw"), ("echo two".to_string(), 5));
+        assert_eq!(normal("echo one two", 5, "d$"), ("echo ".to_string(), 4));
+        assert_eq!(normal("echo one two", 5, "dd"), (String::new(), 0));
+        assert_eq!(normal("echo one two", 5, "dto"), ("echo o".to_string(), 5));
+        assert_eq!(
+            normal("echo one two", 5, "cwthree\x1b"),
+            ("echo three two".to_string(), 9)
+        );
+        assert_eq!(normal("echo one", 5, "x"), ("echo ne".to_string(), 5));
+        assert_eq!(normal("echo one", 7, "X"), ("echo oe".to_string(), 6));
+        assert_eq!(normal("echo one", 5, "ywP"), ("echo oneone".to_string(), 7));
+    }
+
+    #[test]
+    fn test_insert_and_paste() {
+        assert_eq!(normal("ls", 1, "a -l\x1b"), ("ls -l".to_string(), 4));
+        assert_eq!(normal("ls", 1, "Isudo \x1b"), ("sudo ls".to_string(), 4));
+        assert_eq!(normal("ab", 0, "xp"), ("ba".to_string(), 1));
+    }
+
+    #[test]
+    fn test_repeat() {
+        assert_eq!(normal("a b c d", 0, "dw."), ("c d".to_string(), 0));
+        assert_eq!(normal("a b c d", 0, "x2."), (" c d".to_string(), 0));
+        assert_eq!(
+            normal("one two", 0, "cwuno\x1bw."),
+            ("uno uno".to_string(), 6)
+        );
+    }
+}